@@ -4,6 +4,7 @@ import FocusTrap from 'focus-trap-react';
 import { Portal } from 'folds';
 import { useLiveKitContext } from './LiveKitContext';
 import { usePingHistory, getPingColor, getPingQuality, PING_COLORS } from './usePingHistory';
+import { onNativeStreamStats, NativeStreamStats } from './nativeStreaming';
 import * as css from './voicePanel.css';
 
 // Discord-style icons (20px)
@@ -110,6 +111,7 @@ export function ConnectionStatsModal({ onClose }: ConnectionStatsModalProps) {
   const { connectionQuality } = useLiveKitContext();
   const pingHistory = usePingHistory(connectionQuality);
   const [, setRefresh] = useState(0);
+  const [nativeStats, setNativeStats] = useState<NativeStreamStats | null>(null);
 
   // Force refresh every second for smooth updates
   useEffect(() => {
@@ -119,6 +121,20 @@ export function ConnectionStatsModal({ onClose }: ConnectionStatsModalProps) {
     return () => clearInterval(interval);
   }, []);
 
+  // Native (GStreamer WHIP) stream health, if a native stream is running
+  useEffect(() => {
+    let unlisten: (() => void) | undefined;
+    let cancelled = false;
+    onNativeStreamStats(setNativeStats).then((fn) => {
+      if (cancelled) fn();
+      else unlisten = fn;
+    });
+    return () => {
+      cancelled = true;
+      unlisten?.();
+    };
+  }, []);
+
   const quality = getPingQuality(pingHistory.lastPing);
   const qualityLabel = {
     excellent: 'Excellent',
@@ -224,6 +240,47 @@ export function ConnectionStatsModal({ onClose }: ConnectionStatsModalProps) {
             </div>
           </div>
 
+          {/* Native Stream Stats */}
+          {nativeStats && (
+            <div className={css.OtherStatsSection}>
+              <div className={css.OtherStatRow}>
+                <span className={css.OtherStatLabel}>Stream Bitrate</span>
+                <span className={css.OtherStatValue}>
+                  {Math.round(nativeStats.bitrate_kbps)}
+                  {nativeStats.target_bitrate_kbps !== null && ` / ${Math.round(nativeStats.target_bitrate_kbps)}`} kbps
+                </span>
+              </div>
+              <div className={css.OtherStatRow}>
+                <span className={css.OtherStatLabel}>Stream RTT</span>
+                <span className={css.OtherStatValue}>
+                  {nativeStats.rtt_ms !== null ? `${Math.round(nativeStats.rtt_ms)}ms` : '-'}
+                </span>
+              </div>
+              <div className={css.OtherStatRow}>
+                <span className={css.OtherStatLabel}>Stream Packet Loss</span>
+                <span className={css.OtherStatValue}>
+                  {nativeStats.fraction_lost !== null
+                    ? `${(nativeStats.fraction_lost * 100).toFixed(1)}%`
+                    : `${nativeStats.packets_lost} packets`}
+                </span>
+              </div>
+              <div className={css.OtherStatRow}>
+                <span className={css.OtherStatLabel}>Encoder FPS</span>
+                <span className={css.OtherStatValue}>{Math.round(nativeStats.encoder_fps)}</span>
+              </div>
+              <div className={css.OtherStatRow}>
+                <span className={css.OtherStatLabel}>Dropped Frames</span>
+                <span className={css.OtherStatValue}>{nativeStats.dropped_frames}</span>
+              </div>
+              <div className={css.OtherStatRow}>
+                <span className={css.OtherStatLabel}>NACK / PLI</span>
+                <span className={css.OtherStatValue}>
+                  {nativeStats.nack_count} / {nativeStats.pli_count}
+                </span>
+              </div>
+            </div>
+          )}
+
           {/* Color Legend */}
           <div className={css.PingLegend}>
             <div className={css.PingLegendItem}>
@@ -265,8 +265,8 @@ export function StreamingModal({ onClose }: StreamingModalProps) {
         const info = await checkGStreamer();
         setGStreamerInfo(info);
         if (!info.available) {
-          setError("GStreamer not available");
-        } else if (!info.whip_support) {
+          setError(info.error || "GStreamer not available");
+        } else if (!info.has_whip) {
           setError("GStreamer WHIP support not available");
         }
       } catch (e: any) {
@@ -408,13 +408,13 @@ export function StreamingModal({ onClose }: StreamingModalProps) {
         height: settings.height,
         fps: settings.fps,
         bitrate: settings.bitrate,
-        encoder: "x264", // GStreamer auto-selects best encoder
-        preset: "ultrafast",
+        encoder: 'auto', // Hardware first, then openh264, then x264; preset follows quality_mode
         audio_enabled: audioEnabled,
         bearer_token: ingress.streamKey,
         backend: 'gstreamer',
         turn_server: turnServer,
         quality_mode: settings.quality_mode,
+        reconnect: { max_attempts: 5, initial_delay_ms: 1000, max_delay_ms: 30000 },
       };
 
       await startNativeStream(config);
@@ -138,17 +138,44 @@ fn main() {
         .plugin(tauri_plugin_window_state::Builder::default().build())
         // Register streaming state
         .manage(streaming::StreamingState::default())
+        // Audio device hotplug monitor (started on first list_audio_devices)
+        .manage(streaming::DeviceWatcher::default())
         // Register upload state for cancellation tracking
         .manage(upload::UploadState::new())
+        // Streaming log in the app data directory
+        .setup(|app| {
+            streaming::init_logging(&app.handle());
+            Ok(())
+        })
         // Register streaming and upload commands
         .invoke_handler(tauri::generate_handler![
             streaming::list_capture_sources,
+            streaming::list_audio_devices,
             streaming::start_stream,
             streaming::stop_stream,
             streaming::get_stream_status,
+            streaming::list_stream_sessions,
+            streaming::switch_capture_source,
+            streaming::update_stream_config,
+            streaming::set_audio_volume,
+            streaming::set_audio_mute,
+            streaming::update_overlay,
+            streaming::pause_stream,
+            streaming::resume_stream,
+            streaming::restart_output,
+            streaming::start_playback,
+            streaming::stop_playback,
+            streaming::get_playback_status,
+            streaming::start_recording,
+            streaming::stop_recording,
+            streaming::list_recordings,
+            streaming::save_replay,
             streaming::get_streaming_log,
             streaming::clear_streaming_log,
+            streaming::dump_stream_pipeline,
+            streaming::render_stream_pipeline,
             streaming::check_gstreamer,
+            streaming::benchmark_stream_settings,
             upload::native_upload_file,
             upload::native_upload_file_path,
             upload::cancel_native_upload,
@@ -1,19 +1,88 @@
 //! Native game streaming via GStreamer WHIP
 //!
 //! This module provides:
-//! - Screen/window enumeration via Windows API
+//! - Screen/window enumeration via Windows API and X11 (XRandR, _NET_CLIENT_LIST)
 //! - GStreamer pipeline with whipclientsink for WHIP streaming
+//! - RTMP / SRT outputs next to WHIP, sharing one encode separate from
+//!   whipclientsink's own (two encodes when both are used)
+//! - Local recording to fragmented MP4 / Matroska, with or without WHIP
+//! - Instant replay buffer saved to MP4 on demand
+//! - Optional simulcast layers (full / half / quarter resolution)
+//! - Concurrent stream sessions keyed by session id
+//! - Hot-switching the capture source without renegotiating WHIP
+//! - Privacy pause showing a slate while the session keeps running
+//! - Wayland screen capture through the xdg-desktop-portal ScreenCast API
+//! - Microphone mixed with desktop audio, with live volume / mute
+//! - Webcam, image and text overlays composited over the capture
+//! - Audio device discovery with hotplug events
 //! - Stream status monitoring
+//! - An encoder benchmark recommending resolution, framerate, bitrate and quality mode
+//! - Pipeline graph / state / latency dumps for support reports
+//! - A rotating streaming log with levels and session ids in the app data directory
+//! - A `test` source (and, behind a feature, a local WHIP endpoint) for headless runs
+//! - WHEP playback into a native window or as frame events
 //!
 //! Uses gstreamer-rs crate directly instead of spawning gst-launch-1.0 process.
+//! Pipelines are built element-by-element (see `pipeline`), never parsed from strings.
+
+mod benchmark;
+mod capabilities;
+mod devices;
+mod diagnostics;
+mod encoder;
+mod lifecycle;
+mod logging;
+mod mixer;
+mod outputs;
+mod overlay;
+mod pipeline;
+mod playback;
+#[cfg(target_os = "linux")]
+mod portal;
+mod reconnect;
+mod recording;
+mod replay;
+mod session;
+mod slate;
+mod simulcast;
+mod stats;
+mod switcher;
+mod tap;
+mod testsrc;
+// Only driven by headless tests
+#[cfg(any(test, feature = "whip-test-endpoint"))]
+#[allow(dead_code)]
+mod whip_endpoint;
+#[cfg(target_os = "linux")]
+mod x11;
 
 use gstreamer as gst;
-use gst::prelude::{Cast, ElementExt, ElementExtManual, GstBinExt, GstBinExtManual, GstObjectExt, ObjectExt};
+use gst::prelude::{Cast, ElementExt, GstBinExt, GstObjectExt, ObjectExt};
+use benchmark::{BenchmarkOptions, BenchmarkResult};
+use capabilities::GStreamerInfo;
+use diagnostics::{GraphFormat, PipelineDump};
+use encoder::{EncoderChoice, EncoderPin, VideoCodec};
+use lifecycle::{ErrorKind, StreamState};
+use logging::LogLevel;
+use mixer::{AudioInput, AudioLevel, MicrophoneConfig};
+use outputs::{OutputConfig, OutputStatus};
+use overlay::{OverlayLayer, OverlayUpdate};
+use playback::{PlaybackConfig, PlaybackSession, PlaybackStatus};
+use reconnect::ReconnectPolicy;
+use recording::{RecordingInfo, RecordingTarget};
+use replay::{ReplayClip, ReplayConfig};
+use pipeline::{BranchSpec, ElementSpec, PipelineSpec, PropValue};
 use serde::{Deserialize, Serialize};
+use session::StreamSession;
+use simulcast::SimulcastConfig;
+use slate::SlateConfig;
+
+pub use devices::DeviceWatcher;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::fs::OpenOptions;
-use std::io::Write;
-use tauri::{AppHandle, Manager};
+use tauri::{AppHandle, Manager, Runtime};
 
 /// Capture source (screen or window)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +94,7 @@ pub struct CaptureSource {
     pub height: Option<u32>,
     pub hwnd: Option<u64>, // Window handle for window capture
     pub hmonitor: Option<u64>, // Monitor handle for screen capture (HMONITOR)
+    pub xid: Option<u64>, // X11 window id for window capture
     pub thumbnail: Option<String>, // Base64-encoded JPEG thumbnail
 }
 
@@ -48,7 +118,8 @@ impl Default for QualityMode {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StreamConfig {
     pub source_id: String,
-    pub whip_url: String,
+    #[serde(default)]
+    pub whip_url: String, // Empty = local-only session (recording / replay buffer)
     pub width: u32,
     pub height: u32,
     pub fps: u32,
@@ -58,95 +129,340 @@ pub struct StreamConfig {
     pub turn_server: Option<String>,
     #[serde(default)]
     pub quality_mode: QualityMode,
+    #[serde(default)]
+    pub codec: VideoCodec, // Negotiated with the WHIP endpoint
+    #[serde(default)]
+    pub encoder: EncoderChoice,
+    #[serde(default)]
+    pub preset: Option<String>, // Encoder-specific or "performance"/"balanced"/"quality"
+    #[serde(default)]
+    pub reconnect: Option<ReconnectPolicy>, // None = no automatic WHIP reconnect
+    #[serde(default)]
+    pub recording: Option<RecordingTarget>, // Record to disk from the start
+    #[serde(default)]
+    pub replay: Option<ReplayConfig>, // Keep an instant replay buffer for save_replay
+    #[serde(default)]
+    pub desktop_audio: AudioLevel, // Used when audio_enabled
+    #[serde(default)]
+    pub loopback_device: Option<String>, // Device id to capture desktop audio from (None = default output)
+    #[serde(default)]
+    pub microphone: Option<MicrophoneConfig>, // Mixed with desktop audio
+    #[serde(default)]
+    pub simulcast: Option<SimulcastConfig>, // Publish lower resolutions alongside the full one
+    #[serde(default)]
+    pub overlays: Vec<OverlayLayer>, // Composited over the capture, bottom to top
+    #[serde(default)]
+    pub slate: SlateConfig, // Shown while paused
+    #[serde(default)]
+    pub outputs: Vec<OutputConfig>, // RTMP / SRT destinations (a WHIP entry fills whip_url)
+}
+
+impl StreamConfig {
+    /// Whether this session publishes over WHIP (otherwise it only records)
+    fn has_whip(&self) -> bool {
+        !self.whip_url.trim().is_empty()
+    }
+}
+
+/// Live change to a running stream; omitted fields stay as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamConfigUpdate {
+    #[serde(default)]
+    pub bitrate: Option<u32>, // kbps
+    #[serde(default)]
+    pub fps: Option<u32>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub quality_mode: Option<QualityMode>,
+    /// Allow a new WHIP session for framerate / resolution changes
+    #[serde(default)]
+    pub reconnect: bool,
+}
+
+/// Which changed fields `update_stream_config` applied how
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigUpdateReport {
+    pub applied: Vec<String>,            // Without interrupting anything
+    pub reconnected: Vec<String>,        // Applied through a new WHIP session
+    pub requires_reconnect: Vec<String>, // Not applied: needs `reconnect` or a restart
 }
 
 /// Stream status
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StreamStatus {
+    pub session_id: Option<String>,
     pub active: bool,
     pub source_id: Option<String>,
     pub whip_url: Option<String>,
     pub duration_seconds: u64,
     pub error: Option<String>,
+    pub state: StreamState,
+    pub paused: bool, // Slate shown instead of the capture
+    pub recording: Option<String>, // Path of the active recording
+    pub outputs: Vec<OutputStatus>, // RTMP / SRT destinations
 }
 
-/// GStreamer availability info
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GStreamerInfo {
-    pub available: bool,
-    pub version: Option<String>,
-    pub has_whip: bool,
-    pub has_d3d11: bool,
-}
-
-/// Shared state that can be sent across threads
+/// Per-session state that can be sent across threads
 #[derive(Clone, Default)]
 pub struct SharedState {
+    pub session_id: String,
     pub last_error: Arc<Mutex<Option<String>>>,
     pub is_running: Arc<Mutex<bool>>,
+    pub state: Arc<Mutex<StreamState>>,
+    pub playback: bool, // WHEP playback: lifecycle events go to `playback-state`
+    pub counters: stats::Counters, // Frame counters for `stream-stats`
+    pub layer_caps: simulcast::LayerCaps, // Simulcast encoder caps, moved by bitrate updates
 }
 
 /// State for managing streams
 pub struct StreamingState {
-    pipeline: Mutex<Option<gst::Pipeline>>,
-    current_config: Mutex<Option<StreamConfig>>,
-    start_time: Mutex<Option<std::time::Instant>>,
-    shared: SharedState,
+    sessions: Mutex<HashMap<String, Arc<StreamSession>>>,
+    /// Ids claimed by `start_stream` calls still building their session,
+    /// with the codec they pinned (see `Reservation`)
+    starting: Mutex<HashMap<String, Option<VideoCodec>>>,
+    playbacks: Mutex<HashMap<String, Arc<PlaybackSession>>>,
+    /// whipclientsink picks its encoder by factory rank, which is global, so
+    /// all WHIP sessions of a codec share one pin (see `encoder::EncoderPin`)
+    encoder_pins: Mutex<HashMap<VideoCodec, EncoderPin>>,
+    next_id: AtomicU64,
 }
 
 impl Default for StreamingState {
     fn default() -> Self {
         Self {
-            pipeline: Mutex::new(None),
-            current_config: Mutex::new(None),
-            start_time: Mutex::new(None),
-            shared: SharedState::default(),
+            sessions: Mutex::new(HashMap::new()),
+            starting: Mutex::new(HashMap::new()),
+            playbacks: Mutex::new(HashMap::new()),
+            encoder_pins: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
         }
     }
 }
 
-impl Drop for StreamingState {
-    fn drop(&mut self) {
-        // Clean up pipeline when app is closed
-        if let Ok(mut pipeline_lock) = self.pipeline.lock() {
-            if let Some(pipeline) = pipeline_lock.take() {
-                log::info!("Cleaning up streaming pipeline on app exit");
-                let _ = pipeline.set_state(gst::State::Null);
+impl StreamingState {
+    /// Look up a session. Without an id, the only running session is used.
+    fn session(&self, session_id: Option<&str>) -> Result<Arc<StreamSession>, String> {
+        let sessions = self.sessions.lock().unwrap();
+        match session_id {
+            Some(id) => sessions
+                .get(id)
+                .cloned()
+                .ok_or_else(|| format!("No stream session '{}'", id)),
+            None => match sessions.len() {
+                0 => Err("No active stream".to_string()),
+                1 => Ok(sessions.values().next().unwrap().clone()),
+                _ => Err("Multiple streams are running; specify a session id".to_string()),
+            },
+        }
+    }
+
+    /// Look up a playback. Without an id, the only running playback is used.
+    fn playback(&self, session_id: Option<&str>) -> Result<Arc<PlaybackSession>, String> {
+        let playbacks = self.playbacks.lock().unwrap();
+        match session_id {
+            Some(id) => playbacks
+                .get(id)
+                .cloned()
+                .ok_or_else(|| format!("No playback session '{}'", id)),
+            None => match playbacks.len() {
+                0 => Err("No active playback".to_string()),
+                1 => Ok(playbacks.values().next().unwrap().clone()),
+                _ => Err("Multiple playbacks are running; specify a session id".to_string()),
+            },
+        }
+    }
+
+    /// Remove `session` unless its id was already taken over by a new one
+    fn remove_session(&self, session: &Arc<StreamSession>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.get(&session.id).map(|s| Arc::ptr_eq(s, session)).unwrap_or(false) {
+            sessions.remove(&session.id);
+        }
+    }
+
+    /// Claim `session_id` for a session about to be built. A failed session
+    /// with the same id is replaced once the new one is committed.
+    fn reserve(&self, session_id: &str) -> Result<Reservation<'_>, String> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut starting = self.starting.lock().unwrap();
+        if starting.contains_key(BENCHMARK_RESERVATION) {
+            return Err("Wait for the encoder benchmark to finish".to_string());
+        }
+        let running = sessions.get(session_id).map(|s| !s.is_failed()).unwrap_or(false);
+        if running || starting.contains_key(session_id) {
+            return Err(format!("Session '{}' is already streaming", session_id));
+        }
+        starting.insert(session_id.to_string(), None);
+        Ok(Reservation {
+            state: self,
+            session_id: session_id.to_string(),
+            committed: false,
+        })
+    }
+
+    /// Claim the encoders for the benchmark. Fails while any session runs or
+    /// starts, and keeps new sessions from starting until dropped.
+    fn reserve_benchmark(&self) -> Result<Reservation<'_>, String> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut starting = self.starting.lock().unwrap();
+        if sessions.values().any(|s| !s.is_failed()) || !starting.is_empty() {
+            return Err("Stop all streams before running the benchmark".to_string());
+        }
+        starting.insert(BENCHMARK_RESERVATION.to_string(), None);
+        Ok(Reservation {
+            state: self,
+            session_id: BENCHMARK_RESERVATION.to_string(),
+            committed: false,
+        })
+    }
+
+    /// Pin `factory` for a WHIP session being started, or fail if other WHIP
+    /// sessions with the same codec run (or are starting) with a different
+    /// encoder
+    fn pin_encoder(&self, session_id: &str, codec: VideoCodec, factory: &'static str) -> Result<(), String> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut starting = self.starting.lock().unwrap();
+        let mut pins = self.encoder_pins.lock().unwrap();
+        let in_use = sessions.values().any(|s| s.has_whip() && !s.is_failed() && s.codec() == codec)
+            || starting.iter().any(|(id, pinned)| id != session_id && *pinned == Some(codec));
+        match pins.get(&codec) {
+            Some(current) if current.factory == factory => {}
+            Some(current) if in_use => {
+                return Err(format!(
+                    "Encoder {} is in use by another stream; concurrent {} streams must use the same encoder",
+                    current.factory,
+                    codec.as_str().to_uppercase()
+                ))
+            }
+            _ => {
+                // Drop the old pin first so its saved ranks are restored
+                pins.remove(&codec);
+                pins.insert(codec, EncoderPin::new(factory, codec));
             }
         }
+        starting.insert(session_id.to_string(), Some(codec));
+        Ok(())
+    }
+
+    /// Restore encoder ranks of codecs no WHIP session uses any more
+    fn release_encoder_pin(&self) {
+        let sessions = self.sessions.lock().unwrap();
+        let starting = self.starting.lock().unwrap();
+        self.encoder_pins.lock().unwrap().retain(|codec, _| {
+            sessions.values().any(|s| s.has_whip() && !s.is_failed() && s.codec() == *codec)
+                || starting.values().any(|pinned| *pinned == Some(*codec))
+        });
     }
 }
 
-/// Log to debug file in user's temp directory
-fn log_to_file(message: &str) {
-    // Always log to console
-    log::info!("[Stream] {}", message);
+/// A session id held by `start_stream` while it builds the session, so
+/// concurrent starts can't take the same id or pin different encoders.
+/// Dropping it without `commit` frees the id and the pin.
+struct Reservation<'a> {
+    state: &'a StreamingState,
+    session_id: String,
+    committed: bool,
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        // Use TEMP directory which exists for all users
-        let log_path = std::env::var("TEMP")
-            .unwrap_or_else(|_| "C:\\Windows\\Temp".to_string());
-        let log_file = format!("{}\\cinny_streaming.log", log_path);
-
-        if let Ok(mut file) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&log_file)
+impl Reservation<'_> {
+    fn pin_encoder(&self, codec: VideoCodec, factory: &'static str) -> Result<(), String> {
+        self.state.pin_encoder(&self.session_id, codec, factory)
+    }
+
+    /// Publish the built session under the reserved id
+    fn commit(mut self, session: Arc<StreamSession>) {
+        self.committed = true;
         {
-            let timestamp = chrono_timestamp();
-            let _ = writeln!(file, "[{}] {}", timestamp, message);
+            let mut sessions = self.state.sessions.lock().unwrap();
+            self.state.starting.lock().unwrap().remove(&self.session_id);
+            sessions.insert(self.session_id.clone(), session);
+        }
+        // The session may have failed already and given up its pin
+        self.state.release_encoder_pin();
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.state.starting.lock().unwrap().remove(&self.session_id);
+            self.state.release_encoder_pin();
         }
     }
 }
 
-/// Get formatted timestamp
-fn chrono_timestamp() -> String {
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0);
-    format!("{}", now)
+/// `starting` key held by the benchmark; not a valid session id, so it
+/// can't clash with one
+const BENCHMARK_RESERVATION: &str = "<benchmark>";
+
+/// Session ids end up in log lines, event payloads and file names
+fn validate_session_id(session_id: &str) -> Result<(), String> {
+    let valid = (1..=64).contains(&session_id.len())
+        && session_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err("Session ids must be 1-64 letters, digits, '-' or '_'".to_string())
+    }
+}
+
+impl Drop for StreamingState {
+    fn drop(&mut self) {
+        // Clean up pipelines when app is closed
+        if let Ok(mut sessions) = self.sessions.lock() {
+            for (id, session) in sessions.drain() {
+                log::info!("Cleaning up streaming pipeline {} on app exit", id);
+                let _ = session.pipeline.set_state(gst::State::Null);
+            }
+        }
+        if let Ok(mut playbacks) = self.playbacks.lock() {
+            for (_, playback) in playbacks.drain() {
+                let _ = playback.pipeline.set_state(gst::State::Null);
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// Session id added to log lines written by this thread
+    static LOG_SESSION: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Tags this thread's log lines with a session id until dropped
+struct LogScope(Option<String>);
+
+fn log_scope(session_id: &str) -> LogScope {
+    LogScope(LOG_SESSION.with(|s| s.replace(Some(session_id.to_string()))))
+}
+
+impl Drop for LogScope {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        LOG_SESSION.with(|s| *s.borrow_mut() = previous);
+    }
+}
+
+/// Log a line (level from its "ERROR" / "WARNING" prefix) to the console
+/// and the streaming log, tagged with this thread's session
+fn log_to_file(message: &str) {
+    log_at(LogLevel::of(message), message);
+}
+
+/// Log a chatty line only shown when asking for debug output
+fn log_debug(message: &str) {
+    log_at(LogLevel::Debug, message);
+}
+
+fn log_at(level: LogLevel, message: &str) {
+    LOG_SESSION.with(|s| logging::write(level, s.borrow().as_deref(), message));
+}
+
+/// Start writing the streaming log to the app data directory
+pub fn init_logging<R: Runtime>(app: &AppHandle<R>) {
+    logging::init(app);
 }
 
 /// Thumbnail size (width x height)
@@ -365,6 +681,13 @@ fn capture_window_thumbnail(hwnd_value: u64) -> Option<String> {
     Some(format!("data:image/jpeg;base64,{}", b64))
 }
 
+/// List audio sources, sinks and monitor sources. Also starts emitting
+/// `devices-changed` events on hotplug.
+#[tauri::command]
+pub async fn list_audio_devices<R: Runtime>(app: AppHandle<R>) -> Result<Vec<devices::AudioDevice>, String> {
+    devices::list(&app)
+}
+
 /// List available capture sources (screens and windows)
 #[tauri::command]
 pub async fn list_capture_sources() -> Result<Vec<CaptureSource>, String> {
@@ -393,6 +716,7 @@ pub async fn list_capture_sources() -> Result<Vec<CaptureSource>, String> {
                     height: None,
                     hwnd: None,
                     hmonitor: None,
+                    xid: None,
                     thumbnail: None,
                 }])
             },
@@ -406,6 +730,7 @@ pub async fn list_capture_sources() -> Result<Vec<CaptureSource>, String> {
                     height: None,
                     hwnd: None,
                     hmonitor: None,
+                    xid: None,
                     thumbnail: None,
                 }])
             },
@@ -419,11 +744,39 @@ pub async fn list_capture_sources() -> Result<Vec<CaptureSource>, String> {
                     height: None,
                     hwnd: None,
                     hmonitor: None,
+                    xid: None,
                     thumbnail: None,
                 }])
             }
         }
     }
+    #[cfg(target_os = "linux")]
+    {
+        if portal::is_wayland() {
+            // The portal dialog picks the actual screen / window
+            let portal_source = |kind: &str, name: &str, source_type: &str| CaptureSource {
+                id: format!("{}{}", portal::PORTAL_PREFIX, kind),
+                name: name.to_string(),
+                source_type: source_type.to_string(),
+                width: None,
+                height: None,
+                hwnd: None,
+                hmonitor: None,
+                xid: None,
+                thumbnail: None,
+            };
+            return Ok(vec![
+                portal_source("monitor", "Screen", "screen"),
+                portal_source("window", "Window", "window"),
+            ]);
+        }
+
+        match x11::list_sources() {
+            Ok(sources) if !sources.is_empty() => return Ok(sources),
+            Ok(_) => log_to_file("X11: no sources found, returning fallback"),
+            Err(e) => log_to_file(&format!("X11 enumeration failed: {}", e)),
+        }
+    }
     #[cfg(not(target_os = "windows"))]
     {
         Ok(vec![CaptureSource {
@@ -434,6 +787,7 @@ pub async fn list_capture_sources() -> Result<Vec<CaptureSource>, String> {
             height: None,
             hwnd: None,
             hmonitor: None,
+            xid: None,
             thumbnail: None,
         }])
     }
@@ -533,6 +887,7 @@ fn list_windows_sources_safe() -> Result<Vec<CaptureSource>, String> {
                 height: Some(mon.height),
                 hwnd: None,
                 hmonitor: Some(mon.handle as u64),
+                xid: None,
                 thumbnail,
             });
         }
@@ -548,6 +903,7 @@ fn list_windows_sources_safe() -> Result<Vec<CaptureSource>, String> {
             height: None,
             hwnd: None,
             hmonitor: None,
+            xid: None,
             thumbnail: None,
         });
     }
@@ -678,6 +1034,7 @@ fn list_windows_sources_safe() -> Result<Vec<CaptureSource>, String> {
             height: Some(height),
             hwnd: Some(hwnd_value),
             hmonitor: None,
+            xid: None,
             thumbnail,
         });
     }
@@ -696,9 +1053,13 @@ fn list_windows_sources_safe() -> Result<Vec<CaptureSource>, String> {
     Ok(sources)
 }
 
-/// Build video capture pipeline segment based on source
-fn build_video_capture(config: &StreamConfig) -> String {
-    let mut video = String::new();
+/// Build the switchable part of the video branch: the capture source plus
+/// conversion to the configured size, so every source yields the same caps
+fn build_video_source(config: &StreamConfig) -> Vec<ElementSpec> {
+    if testsrc::is_test(&config.source_id) {
+        return testsrc::video(config);
+    }
+    let mut video = Vec::new();
 
     #[cfg(target_os = "windows")]
     {
@@ -707,46 +1068,133 @@ fn build_video_capture(config: &StreamConfig) -> String {
             // Window capture using window handle (HWND)
             let hwnd: u64 = config.source_id[5..].parse().unwrap_or(0);
             // Use WGC (Windows Graphics Capture) for window capture
-            video.push_str(&format!(
-                "d3d11screencapturesrc window-handle={} capture-api=wgc show-cursor=true",
-                hwnd
-            ));
+            video.push(
+                ElementSpec::new("d3d11screencapturesrc")
+                    .int("window-handle", hwnd as i64)
+                    .enum_nick("capture-api", "wgc")
+                    .bool("show-cursor", true),
+            );
         } else if config.source_id.starts_with("monitor:") {
             // Per-monitor capture using HMONITOR handle with WGC for correct monitor targeting
             let monitor_handle: i64 = config.source_id[8..].parse().unwrap_or(0);
             if monitor_handle > 0 {
                 // Use WGC with monitor-handle for accurate capture
-                video.push_str(&format!(
-                    "d3d11screencapturesrc monitor-handle={} capture-api=wgc show-cursor=true",
-                    monitor_handle
-                ));
+                video.push(
+                    ElementSpec::new("d3d11screencapturesrc")
+                        .int("monitor-handle", monitor_handle)
+                        .enum_nick("capture-api", "wgc")
+                        .bool("show-cursor", true),
+                );
             } else {
                 // Fallback to monitor-index=0 if no valid handle
-                video.push_str("d3d11screencapturesrc monitor-index=0 show-cursor=true");
+                video.push(
+                    ElementSpec::new("d3d11screencapturesrc")
+                        .int("monitor-index", 0)
+                        .bool("show-cursor", true),
+                );
             }
         } else {
             // Default: primary monitor (index 0)
-            video.push_str("d3d11screencapturesrc monitor-index=0 show-cursor=true");
+            video.push(
+                ElementSpec::new("d3d11screencapturesrc")
+                    .int("monitor-index", 0)
+                    .bool("show-cursor", true),
+            );
         }
 
         // Framerate caps
-        video.push_str(&format!(
-            " ! video/x-raw(memory:D3D11Memory),framerate={}/1",
+        video.push(ElementSpec::caps(format!(
+            "video/x-raw(memory:D3D11Memory),framerate={}/1",
             config.fps
-        ));
+        )));
 
-        // Convert BGRA to NV12 in GPU memory
-        video.push_str(" ! d3d11convert");
-        video.push_str(&format!(
-            " ! video/x-raw(memory:D3D11Memory),format=NV12,width={},height={}",
+        // Convert BGRA to NV12 in GPU memory. Square pixels make d3d11convert
+        // letterbox instead of changing the aspect ratio, keeping caps stable.
+        video.push(ElementSpec::new("d3d11convert"));
+        video.push(ElementSpec::caps(format!(
+            "video/x-raw(memory:D3D11Memory),format=NV12,width={},height={},pixel-aspect-ratio=1/1",
             config.width, config.height
-        ));
+        )));
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        if let Some((fd, node_id)) = portal::parse_pipewire_source(&config.source_id) {
+            // Portal screencast (Wayland); see `portal::resolve`
+            video.push(
+                ElementSpec::new("pipewiresrc")
+                    .int("fd", fd)
+                    .str("path", &node_id.to_string())
+                    .bool("always-copy", true)
+                    // Resend the last frame while the screen is static
+                    .int("keepalive-time", 1000),
+            );
+            // Portal streams often have a variable framerate
+            video.push(ElementSpec::new("videorate"));
+        } else {
+            let mut ximagesrc = ElementSpec::new("ximagesrc")
+                .bool("show-pointer", true)
+                .bool("use-damage", false);
+            if let Some(xid) = config.source_id.strip_prefix(x11::XID_PREFIX).and_then(|id| id.parse::<u64>().ok()) {
+                // Single window, follows it when moved
+                ximagesrc = ximagesrc.int("xid", xid as i64);
+            } else if let Some(rect) = config.source_id
+                .strip_prefix("monitor:")
+                .and_then(|index| index.parse::<usize>().ok())
+                .and_then(x11::monitor_rect)
+            {
+                // One XRandR monitor out of the root window (end coordinates are inclusive)
+                ximagesrc = ximagesrc
+                    .int("startx", rect.x as i64)
+                    .int("starty", rect.y as i64)
+                    .int("endx", rect.x as i64 + rect.width as i64 - 1)
+                    .int("endy", rect.y as i64 + rect.height as i64 - 1);
+            }
+            video.push(ximagesrc);
+        }
+        video.push(ElementSpec::new("videoconvert"));
+        video.push(ElementSpec::new("videoscale"));
+        video.push(ElementSpec::caps(format!(
+            "video/x-raw,format=NV12,width={},height={},framerate={}/1,pixel-aspect-ratio=1/1",
+            config.width, config.height, config.fps
+        )));
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        video.push(ElementSpec::new("avfvideosrc").bool("capture-screen", true));
+        video.push(ElementSpec::new("videoconvert"));
+        video.push(ElementSpec::new("videoscale"));
+        video.push(ElementSpec::caps(format!(
+            "video/x-raw,format=NV12,width={},height={},framerate={}/1,pixel-aspect-ratio=1/1",
+            config.width, config.height, config.fps
+        )));
+    }
+
+    video
+}
+
+/// Build video capture pipeline segment based on source
+fn build_video_capture(config: &StreamConfig) -> Vec<ElementSpec> {
+    let mut video = build_video_source(config);
+
+    // Sources are swapped behind this selector (see `switcher`)
+    video.push(ElementSpec::new("input-selector").named(switcher::SELECTOR_NAME));
 
+    #[cfg(target_os = "windows")]
+    {
         // Queue for stability
-        video.push_str(" ! queue max-size-buffers=3 max-size-time=50000000 max-size-bytes=0 leaky=downstream");
+        video.push(
+            ElementSpec::new("queue")
+                .named("video_queue")
+                .int("max-size-buffers", 3)
+                .int("max-size-time", 50_000_000)
+                .int("max-size-bytes", 0)
+                .enum_nick("leaky", "downstream"),
+        );
 
         // Download from GPU memory to system memory for whipclientsink
-        video.push_str(" ! d3d11download");
+        video.push(ElementSpec::new("d3d11download"));
 
         // Let whipclientsink handle ALL encoding with congestion control
         // This enables automatic bitrate adaptation based on network conditions
@@ -757,79 +1205,155 @@ fn build_video_capture(config: &StreamConfig) -> String {
         // No explicit encoder needed here - congestion control handles bitrate
     }
 
-    #[cfg(target_os = "linux")]
+    #[cfg(not(target_os = "windows"))]
     {
-        video.push_str("ximagesrc show-pointer=true use-damage=false");
-        video.push_str(" ! videoconvert");
-        video.push_str(&format!(
-            " ! videoscale ! video/x-raw,format=NV12,width={},height={},framerate={}/1",
-            config.width, config.height, config.fps
-        ));
-        video.push_str(" ! queue max-size-buffers=5 max-size-time=0 max-size-bytes=0 leaky=downstream");
+        video.push(
+            ElementSpec::new("queue")
+                .named("video_queue")
+                .int("max-size-buffers", 5)
+                .int("max-size-time", 0)
+                .int("max-size-bytes", 0)
+                .enum_nick("leaky", "downstream"),
+        );
     }
 
-    #[cfg(target_os = "macos")]
-    {
-        video.push_str("avfvideosrc capture-screen=true");
-        video.push_str(" ! videoconvert");
-        video.push_str(&format!(
-            " ! videoscale ! video/x-raw,format=NV12,width={},height={},framerate={}/1",
-            config.width, config.height, config.fps
-        ));
-        video.push_str(" ! queue max-size-buffers=5 max-size-time=0 max-size-bytes=0 leaky=downstream");
+    // Behind the selector, so overlays survive source switches
+    if !config.overlays.is_empty() {
+        video.extend(overlay::compositor(config.width, config.height, config.fps));
     }
 
     video
 }
 
-/// Build audio capture pipeline segment (system audio loopback) with Opus encoding
-fn build_audio_capture() -> String {
-    let mut audio = String::new();
-
+/// System audio loopback source. `device` is an id from `list_audio_devices`:
+/// a sink on Windows, a monitor source on Linux.
+fn build_desktop_audio_source(device: Option<&str>) -> ElementSpec {
     #[cfg(target_os = "windows")]
     {
         // WASAPI loopback capture for system audio
-        audio.push_str("wasapisrc loopback=true low-latency=true");
-        audio.push_str(" ! audioconvert ! audioresample");
-        audio.push_str(" ! audio/x-raw,rate=48000,channels=2");
-        audio.push_str(" ! queue max-size-buffers=10 max-size-time=0 max-size-bytes=0 leaky=downstream");
-        // Opus encoding for WebRTC - 128kbps stereo, low latency
-        audio.push_str(" ! opusenc bitrate=128000 audio-type=generic frame-size=20");
-        audio.push_str(" ! audio/x-opus,rate=48000,channels=2");
+        let mut src = ElementSpec::new("wasapisrc")
+            .bool("loopback", true)
+            .bool("low-latency", true);
+        if let Some(device) = device {
+            src = src.str("device", device);
+        }
+        src
     }
 
     #[cfg(target_os = "linux")]
     {
-        // PulseAudio monitor source for system audio
-        audio.push_str("pulsesrc");
-        audio.push_str(" ! audioconvert ! audioresample");
-        audio.push_str(" ! audio/x-raw,rate=48000,channels=2");
-        audio.push_str(" ! queue max-size-buffers=10 max-size-time=0 max-size-bytes=0 leaky=downstream");
-        // Opus encoding for WebRTC
-        audio.push_str(" ! opusenc bitrate=128000 audio-type=generic frame-size=20");
-        audio.push_str(" ! audio/x-opus,rate=48000,channels=2");
+        // PulseAudio monitor source for system audio. A bare pulsesrc would
+        // record the default microphone instead.
+        ElementSpec::new("pulsesrc").str("device", device.unwrap_or("@DEFAULT_MONITOR@"))
     }
 
     #[cfg(target_os = "macos")]
     {
         // macOS audio capture (requires BlackHole or similar virtual device)
-        audio.push_str("osxaudiosrc");
-        audio.push_str(" ! audioconvert ! audioresample");
-        audio.push_str(" ! audio/x-raw,rate=48000,channels=2");
-        audio.push_str(" ! queue max-size-buffers=10 max-size-time=0 max-size-bytes=0 leaky=downstream");
-        // Opus encoding for WebRTC
-        audio.push_str(" ! opusenc bitrate=128000 audio-type=generic frame-size=20");
-        audio.push_str(" ! audio/x-opus,rate=48000,channels=2");
+        let mut src = ElementSpec::new("osxaudiosrc");
+        if let Some(id) = device.and_then(|d| d.parse::<i64>().ok()) {
+            src = src.int("device", id);
+        }
+        src
     }
+}
 
-    audio
+/// Microphone source, `device` from `list_audio_devices` (None = default input)
+fn build_microphone_source(device: Option<&str>) -> ElementSpec {
+    #[cfg(target_os = "windows")]
+    {
+        let mut src = ElementSpec::new("wasapisrc").bool("low-latency", true);
+        if let Some(device) = device {
+            src = src.str("device", device);
+        }
+        src
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut src = ElementSpec::new("pulsesrc");
+        if let Some(device) = device {
+            src = src.str("device", device);
+        }
+        src
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        // osxaudiosrc takes a numeric AudioDeviceID
+        let mut src = ElementSpec::new("osxaudiosrc");
+        if let Some(id) = device.and_then(|d| d.parse::<i64>().ok()) {
+            src = src.int("device", id);
+        }
+        src
+    }
 }
 
-/// Build GStreamer pipeline string for WHIP streaming
-fn build_gstreamer_pipeline(config: &StreamConfig) -> String {
-    // Calculate bitrates based on quality mode
-    // whipclientsink handles encoding internally with congestion control
-    let (start_bitrate, min_bitrate, max_bitrate) = match config.quality_mode {
+/// One audio input: source, conversion to 48 kHz stereo, volume
+fn build_audio_input(source: ElementSpec, input: AudioInput, level: AudioLevel) -> Vec<ElementSpec> {
+    vec![
+        source,
+        ElementSpec::new("audioconvert"),
+        ElementSpec::new("audioresample"),
+        ElementSpec::caps("audio/x-raw,rate=48000,channels=2"),
+        mixer::volume_element(input, level),
+    ]
+}
+
+/// Build the audio branch: desktop audio and/or microphone, mixed when both
+/// are present, then Opus encoding. None if the stream has no audio.
+fn build_audio_capture(config: &StreamConfig) -> Option<BranchSpec> {
+    let mut inputs = Vec::new();
+    if config.audio_enabled {
+        let source = if testsrc::is_test(&config.source_id) {
+            testsrc::audio()
+        } else {
+            build_desktop_audio_source(config.loopback_device.as_deref())
+        };
+        inputs.push(build_audio_input(source, AudioInput::Desktop, config.desktop_audio));
+    }
+    if let Some(ref mic) = config.microphone {
+        inputs.push(build_audio_input(
+            build_microphone_source(mic.device.as_deref()),
+            AudioInput::Microphone,
+            mic.level,
+        ));
+    }
+
+    let mut audio = match inputs.len() {
+        0 => return None,
+        // A single input runs straight into the encoder
+        1 => inputs.remove(0),
+        _ => vec![mixer::mixer(), ElementSpec::caps("audio/x-raw,rate=48000,channels=2")],
+    };
+    audio.push(
+        ElementSpec::new("queue")
+            .int("max-size-buffers", 10)
+            .int("max-size-time", 0)
+            .int("max-size-bytes", 0)
+            .enum_nick("leaky", "downstream"),
+    );
+    // Opus encoding for WebRTC - 128kbps stereo, low latency
+    audio.push(
+        ElementSpec::new("opusenc")
+            .int("bitrate", 128_000)
+            .enum_nick("audio-type", "generic")
+            .enum_nick("frame-size", "20"),
+    );
+    audio.push(ElementSpec::caps("audio/x-opus,rate=48000,channels=2"));
+
+    Some(BranchSpec {
+        elements: audio,
+        inputs,
+        layers: Vec::new(),
+        tee: "audio_tee",
+        sink_pad: "audio_%u",
+    })
+}
+
+/// Calculate (start, min, max) bitrates in bps based on quality mode
+fn quality_bitrates(config: &StreamConfig) -> (u32, u32, u32) {
+    match config.quality_mode {
         QualityMode::Performance => {
             // Lower bitrate, prioritize smoothness
             (config.bitrate * 1000, 500_000, config.bitrate * 1000 * 3 / 2)
@@ -847,115 +1371,108 @@ fn build_gstreamer_pipeline(config: &StreamConfig) -> String {
             // Note: True lossless not possible over WebRTC, this is "visually lossless"
             (config.bitrate * 1000 * 2, 5_000_000, config.bitrate * 1000 * 4)
         }
-    };
+    }
+}
+
+/// Build the whipclientsink element description
+fn build_whip_sink(config: &StreamConfig) -> ElementSpec {
+    // whipclientsink handles encoding internally with congestion control
+    let (start_bitrate, min_bitrate, max_bitrate) = quality_bitrates(config);
 
     log_to_file(&format!(
         "Bitrate settings - start: {} kbps, min: {} kbps, max: {} kbps",
         start_bitrate / 1000, min_bitrate / 1000, max_bitrate / 1000
     ));
+    for layer in simulcast::layers(config) {
+        log_to_file(&format!(
+            "Simulcast layer {}: {}x{}, max {} kbps",
+            layer.rid, layer.width, layer.height, layer.max_bitrate / 1000
+        ));
+    }
 
-    // Build WHIP sink properties
     // video-caps tells whipclientsink what codec to use for internal encoding
     // Congestion control will adapt bitrate between min and max based on network
-    let mut whip_props = format!(
-        "whipclientsink name=whip \
-video-caps=\"video/x-h264,profile=constrained-baseline\" \
-start-bitrate={} \
-min-bitrate={} \
-max-bitrate={} \
-do-fec=true \
-do-retransmission=true \
-signaller::whip-endpoint=\"{}\"",
-        start_bitrate,
-        min_bitrate,
-        max_bitrate,
-        config.whip_url
-    );
+    let mut whip = ElementSpec::new("whipclientsink")
+        .named("whip")
+        .prop("video-caps", PropValue::Caps(config.codec.whip_caps()))
+        .int("start-bitrate", start_bitrate as i64)
+        .int("min-bitrate", min_bitrate as i64)
+        .int("max-bitrate", max_bitrate as i64)
+        .bool("do-fec", true)
+        .bool("do-retransmission", true)
+        .str("signaller::whip-endpoint", &config.whip_url);
 
     if let Some(ref token) = config.bearer_token {
-        whip_props.push_str(&format!(" signaller::auth-token=\"{}\"", token));
+        whip = whip.str("signaller::auth-token", token);
     }
 
     // Add TURN server if provided (for users without direct connectivity)
-    // GstValueArray format requires escaped quotes: <"url1", "url2">
     if let Some(ref turn) = config.turn_server {
-        whip_props.push_str(&format!(" turn-servers=\"<\\\"{}\\\">\"", turn));
+        whip = whip.prop("turn-servers", PropValue::StrArray(vec![turn.clone()]));
     }
 
-    // Build complete pipeline
-    let video_pipeline = build_video_capture(config);
-
-    if config.audio_enabled {
-        // Video + Audio pipeline using whipclientsink's multiple pad support
-        // Named element must be defined FIRST, then streams connect to it via whip.
-        let audio_pipeline = build_audio_capture();
-        format!(
-            "{} {} ! whip. {} ! whip.",
-            whip_props, video_pipeline, audio_pipeline
-        )
-    } else {
-        // Video-only pipeline
-        format!("{} ! {}", video_pipeline, whip_props)
-    }
+    whip
 }
 
-/// Start streaming to WHIP endpoint using GStreamer
-#[tauri::command]
-pub async fn start_stream(
-    app: AppHandle,
-    config: StreamConfig,
-) -> Result<(), String> {
-    log_to_file("=== START_STREAM DEBUG ===");
-    log_to_file(&format!("Source ID: {}", config.source_id));
-    log_to_file(&format!("WHIP URL: {}", config.whip_url));
-    log_to_file(&format!("Resolution: {}x{} @ {}fps", config.width, config.height, config.fps));
-    log_to_file(&format!("Bitrate: {} kbps", config.bitrate));
-    log_to_file(&format!("Audio enabled: {}", config.audio_enabled));
-    log_to_file(&format!("Bearer token: {}", if config.bearer_token.is_some() { "present" } else { "none" }));
-    log_to_file(&format!("TURN server: {}", config.turn_server.as_ref().unwrap_or(&"none".to_string())));
-
-    let state = app.state::<StreamingState>();
-
-    // Check if already streaming
-    {
-        let pipeline = state.pipeline.lock().unwrap();
-        if pipeline.is_some() {
-            log_to_file("ERROR: Already streaming");
-            return Err("Already streaming".to_string());
-        }
+/// Build typed GStreamer pipeline description for WHIP streaming
+fn build_gstreamer_pipeline(config: &StreamConfig) -> PipelineSpec {
+    let mut branches = vec![BranchSpec {
+        elements: build_video_capture(config),
+        inputs: Vec::new(),
+        layers: simulcast::layers(config).iter().skip(1).map(simulcast::layer_chain).collect(),
+        tee: "video_tee",
+        sink_pad: "video_%u",
+    }];
+
+    // Video + Audio pipeline using whipclientsink's multiple pad support
+    if let Some(audio) = build_audio_capture(config) {
+        branches.push(audio);
     }
 
-    // Check GStreamer initialization
-    log_to_file("Checking GStreamer initialization...");
-    if !gst::init().is_ok() {
-        log_to_file("WARNING: GStreamer may not be fully initialized");
-    } else {
-        log_to_file(&format!("GStreamer version: {}", gst::version_string()));
+    PipelineSpec {
+        // Recording-only sessions end at the tees
+        sink: if config.has_whip() { Some(build_whip_sink(config)) } else { None },
+        branches,
     }
+}
 
-    // Build pipeline string
-    let pipeline_str = build_gstreamer_pipeline(&config);
-    log_to_file("=== PIPELINE STRING ===");
-    log_to_file(&pipeline_str);
-    log_to_file("=== END PIPELINE STRING ===");
+/// Hook up a WHIP sink before it negotiates: encoder preset, simulcast
+/// layer caps and frame counting. Runs for the initial sink and for every
+/// sink rebuilt by a reconnect or config update.
+fn setup_whip(whip: &gst::Element, config: &StreamConfig, encoder_factory: &'static str, shared: &SharedState) {
+    encoder::connect_encoder_setup(whip, encoder_factory, config.preset.clone(), config.quality_mode.clone());
+    simulcast::connect_layer_caps(whip, encoder_factory, &simulcast::layers(config), &shared.layer_caps);
+    stats::count_encoded_frames(whip, &shared.counters);
+    lifecycle::watch_peer_connection(whip);
+}
 
-    // Parse and create pipeline using gst_parse_launch
-    log_to_file("Parsing pipeline string...");
-    let pipeline = gst::parse::launch(&pipeline_str)
+/// Build the pipeline and set it to PLAYING
+fn create_pipeline(config: &StreamConfig, encoder_factory: &'static str, shared: &SharedState) -> Result<gst::Pipeline, String> {
+    // Build typed pipeline description
+    let spec = build_gstreamer_pipeline(config);
+    log_to_file("=== PIPELINE ===");
+    log_to_file(&spec.describe());
+    log_to_file("=== END PIPELINE ===");
+
+    // Create and link elements
+    log_to_file("Creating pipeline elements...");
+    let pipeline = spec.instantiate()
         .map_err(|e| {
-            let msg = format!("PARSE ERROR: {} (pipeline: {})", e, pipeline_str);
+            let msg = format!("PIPELINE ERROR: {}", e);
             log_to_file(&msg);
             msg
         })?;
-    log_to_file("Pipeline parsed successfully");
-
-    // Cast to Pipeline
-    let pipeline: gst::Pipeline = pipeline.downcast()
-        .map_err(|_| {
-            let msg = "Pipeline is not a GstPipeline";
-            log_to_file(msg);
-            msg.to_string()
-        })?;
+    log_to_file("Pipeline created successfully");
+
+    if let Some(whip) = pipeline.by_name("whip") {
+        setup_whip(&whip, config, encoder_factory, shared);
+    }
+
+    if let Err(e) = overlay::attach(&pipeline, &config.overlays, config.fps) {
+        let msg = format!("OVERLAY ERROR: {}", e);
+        log_to_file(&msg);
+        return Err(msg);
+    }
 
     // List all elements in the pipeline for debugging
     log_to_file("=== PIPELINE ELEMENTS ===");
@@ -971,339 +1488,893 @@ pub async fn start_stream(
 
     // Set to playing state
     log_to_file("Setting pipeline to PLAYING state...");
-    pipeline.set_state(gst::State::Playing)
-        .map_err(|e| {
-            let msg = format!("Failed to start pipeline: {:?}", e);
-            log_to_file(&msg);
-            msg
-        })?;
+    if let Err(e) = pipeline.set_state(gst::State::Playing) {
+        let msg = format!("Failed to start pipeline: {:?}", e);
+        log_to_file(&msg);
+        let _ = pipeline.set_state(gst::State::Null);
+        return Err(msg);
+    }
 
-    log_to_file("Pipeline state change to PLAYING initiated (async)");
+    Ok(pipeline)
+}
 
-    // Get bus for message handling
-    let bus = pipeline.bus().ok_or("Failed to get pipeline bus")?;
+/// Start local recording / replay outputs requested in the config
+fn start_local_outputs<R: Runtime>(app: &AppHandle<R>, session: &StreamSession) -> Result<(), String> {
+    let config = session.config.lock().unwrap().clone();
 
-    // Store pipeline and config
-    {
-        let mut pipeline_lock = state.pipeline.lock().unwrap();
-        *pipeline_lock = Some(pipeline);
+    let outputs = outputs::start_all(&session.pipeline, &config, session.encoder_factory)
+        .map_err(|e| format!("Failed to start outputs: {}", e))?;
+    *session.outputs.lock().unwrap() = outputs;
+
+    if let Some(ref target) = config.recording {
+        let recorder = recording::start(app, &session.id, &session.pipeline, &config, session.encoder_factory, target)
+            .map_err(|e| format!("Failed to start recording: {}", e))?;
+        *session.recorder.lock().unwrap() = Some(recorder);
     }
-    {
-        let mut config_lock = state.current_config.lock().unwrap();
-        *config_lock = Some(config);
+
+    if let Some(ref replay) = config.replay {
+        let buffer = replay::start(&session.pipeline, &config, session.encoder_factory, replay)
+            .map_err(|e| format!("Failed to start replay buffer: {}", e))?;
+        *session.replay.lock().unwrap() = Some(buffer);
     }
-    {
-        let mut start_lock = state.start_time.lock().unwrap();
-        *start_lock = Some(std::time::Instant::now());
+
+    Ok(())
+}
+
+/// Start a stream session (WHIP and/or local outputs) using GStreamer.
+/// Returns the session id, generated when not given.
+#[tauri::command]
+pub async fn start_stream<R: Runtime>(
+    app: AppHandle<R>,
+    config: StreamConfig,
+    session_id: Option<String>,
+) -> Result<String, String> {
+    let session_id = session_id
+        .filter(|id| !id.trim().is_empty())
+        .unwrap_or_else(|| {
+            let next = app.state::<StreamingState>().next_id.fetch_add(1, Ordering::Relaxed);
+            format!("stream-{}", next)
+        });
+    validate_session_id(&session_id)?;
+
+    // The portal dialog and the pipeline start both block
+    tauri::async_runtime::spawn_blocking(move || {
+        let _log = log_scope(&session_id);
+        start_session(&app, config, session_id)
+    })
+    .await
+    .map_err(|e| format!("Start stream task failed: {}", e))?
+}
+
+/// Build and start a session; the blocking part of `start_stream`
+fn start_session<R: Runtime>(app: &AppHandle<R>, mut config: StreamConfig, session_id: String) -> Result<String, String> {
+    let state = app.state::<StreamingState>();
+    outputs::apply_whip_output(&mut config)?;
+
+    log_to_file("=== START_STREAM DEBUG ===");
+    log_to_file(&format!("Source ID: {}", config.source_id));
+    log_to_file(&format!("WHIP URL: {}", config.whip_url));
+    log_to_file(&format!("Resolution: {}x{} @ {}fps", config.width, config.height, config.fps));
+    log_to_file(&format!("Bitrate: {} kbps", config.bitrate));
+    log_to_file(&format!("Audio enabled: {}", config.audio_enabled));
+    log_to_file(&format!("Microphone: {}", config.microphone.as_ref().map(|m| m.device.as_deref().unwrap_or("default")).unwrap_or("off")));
+    log_to_file(&format!("Bearer token: {}", if config.bearer_token.is_some() { "present" } else { "none" }));
+    log_to_file(&format!("TURN server: {}", config.turn_server.as_ref().unwrap_or(&"none".to_string())));
+    log_to_file(&format!("Codec: {}", config.codec.as_str()));
+    log_to_file(&format!("Encoder: {} (preset: {})", config.encoder.as_str(), config.preset.as_deref().unwrap_or("default")));
+    log_to_file(&format!("Recording: {}", config.recording.as_ref().map(|r| r.format.extension()).unwrap_or("off")));
+    log_to_file(&format!("Replay buffer: {}", config.replay.as_ref().map(|r| format!("{}s", r.seconds)).unwrap_or_else(|| "off".to_string())));
+    log_to_file(&format!("Outputs: {}", config.outputs.iter().filter(|o| !matches!(o, OutputConfig::Whip { .. })).count()));
+
+    if !config.has_whip() && !outputs::has_outputs(&config) && config.recording.is_none() && config.replay.is_none() {
+        log_to_file("ERROR: No WHIP URL, output, recording target or replay buffer");
+        return Err("A WHIP URL, an RTMP / SRT output, a recording target or a replay buffer is required".to_string());
     }
+
+    // Held until the session is inserted, so a concurrent start can't take the id
+    let reservation = state.reserve(&session_id).map_err(|e| {
+        log_to_file(&format!("ERROR: {}", e));
+        e
+    })?;
+
+    // Check GStreamer initialization
+    log_to_file("Checking GStreamer initialization...");
+    if !gst::init().is_ok() {
+        log_to_file("WARNING: GStreamer may not be fully initialized");
+    } else {
+        log_to_file(&format!("GStreamer version: {}", gst::version_string()));
+    }
+
+    let shared = SharedState {
+        session_id: session_id.clone(),
+        ..SharedState::default()
+    };
+    lifecycle::transition(app, &shared, StreamState::Starting, None, None, None);
+
+    let fail = |e: String, kind: Option<ErrorKind>| {
+        log_to_file(&format!("ERROR: {}", e));
+        lifecycle::transition(app, &shared, StreamState::Failed, None, kind, Some(e.clone()));
+        e
+    };
+
+    // Resolve the encoder whipclientsink (and local outputs) should use
+    let encoder_factory = encoder::select_encoder(config.encoder, config.codec)
+        .and_then(|factory| {
+            encoder::validate_preset(factory, config.preset.as_deref(), &config.quality_mode).map(|_| factory)
+        })
+        .map_err(|e| fail(e, Some(ErrorKind::Encoder)))?;
+
+    // Wayland: ask the ScreenCast portal what to capture
+    #[cfg(target_os = "linux")]
+    let (config, screencast) = {
+        let mut config = config;
+        let (source_id, screencast) = portal::resolve(app, &config.source_id)
+            .map_err(|e| fail(e, Some(ErrorKind::Capture)))?;
+        config.source_id = source_id;
+        (config, screencast)
+    };
+
+    if config.has_whip() {
+        reservation.pin_encoder(config.codec, encoder_factory).map_err(|e| fail(e, Some(ErrorKind::Encoder)))?;
+    }
+
+    let pipeline = create_pipeline(&config, encoder_factory, &shared).map_err(|e| fail(e, None))?;
+
+    let session = Arc::new(StreamSession::new(pipeline, config, encoder_factory, shared.clone()));
+    #[cfg(target_os = "linux")]
     {
-        let mut error_lock = state.shared.last_error.lock().unwrap();
-        *error_lock = None;
+        *session.screencast.lock().unwrap() = screencast;
+    }
+    if let Err(e) = start_local_outputs(app, &session) {
+        session.shutdown();
+        return Err(fail(e, Some(ErrorKind::Recording)));
     }
+
+    lifecycle::transition(app, &shared, StreamState::Connecting, None, None, None);
+    log_to_file("Pipeline state change to PLAYING initiated (async)");
+
     {
-        let mut running = state.shared.is_running.lock().unwrap();
+        let mut running = shared.is_running.lock().unwrap();
         *running = true;
     }
 
+    // Periodic WebRTC stats -> "stream-stats" events
+    stats::spawn_collector(app.clone(), &session.pipeline, shared.clone());
+
     // Spawn message handler thread
-    let shared = state.shared.clone();
-    std::thread::spawn(move || {
-        log_to_file("Message handler thread started");
-        let mut message_count = 0u32;
-
-        loop {
-            let msg = match bus.timed_pop(gst::ClockTime::from_seconds(1)) {
-                Some(m) => m,
-                None => {
-                    // Log every 10 seconds to show we're still alive
-                    if let Ok(running) = shared.is_running.lock() {
-                        if *running {
-                            message_count += 1;
-                            if message_count % 10 == 0 {
-                                log_to_file(&format!("Pipeline still running ({}s)", message_count));
-                            }
-                        }
-                    }
-                    continue;
-                },
-            };
+    if let Err(e) = session::watch_bus(app.clone(), &session) {
+        session.shutdown();
+        return Err(fail(e, None));
+    }
 
-            use gst::MessageView;
-            let src_name = msg.src().map(|s| s.name().to_string()).unwrap_or_else(|| "unknown".to_string());
+    reservation.commit(session);
+    Ok(session_id)
+}
 
-            match msg.view() {
-                MessageView::Eos(..) => {
-                    log_to_file(&format!("EOS from {}", src_name));
-                    break;
-                }
-                MessageView::Error(err) => {
-                    let error_msg = format!(
-                        "ERROR from {}: {} (debug: {:?})",
-                        src_name,
-                        err.error(),
-                        err.debug()
-                    );
-                    log_to_file(&error_msg);
-                    if let Ok(mut error) = shared.last_error.lock() {
-                        *error = Some(error_msg);
-                    }
-                    break;
-                }
-                MessageView::Warning(warning) => {
-                    log_to_file(&format!(
-                        "WARNING from {}: {} (debug: {:?})",
-                        src_name,
-                        warning.error(),
-                        warning.debug()
-                    ));
-                }
-                MessageView::StateChanged(state_changed) => {
-                    // Only log state changes for the pipeline itself
-                    if msg.src().map(|s| s.type_().name() == "GstPipeline").unwrap_or(false) {
-                        log_to_file(&format!(
-                            "STATE CHANGED: {:?} -> {:?} (pending: {:?})",
-                            state_changed.old(),
-                            state_changed.current(),
-                            state_changed.pending()
-                        ));
-                    }
-                }
-                MessageView::StreamStatus(status) => {
-                    log_to_file(&format!(
-                        "STREAM STATUS from {}: {:?}",
-                        src_name,
-                        status.type_()
-                    ));
-                }
-                MessageView::Element(element) => {
-                    // Log element-specific messages (like WHIP connection status)
-                    if let Some(structure) = element.structure() {
-                        log_to_file(&format!(
-                            "ELEMENT MSG from {}: {}",
-                            src_name,
-                            structure.name()
-                        ));
-                    }
-                }
-                MessageView::Latency(..) => {
-                    log_to_file(&format!("LATENCY update from {}", src_name));
-                }
-                MessageView::AsyncDone(..) => {
-                    log_to_file(&format!("ASYNC DONE from {} - pipeline is fully playing", src_name));
-                }
-                MessageView::NewClock(clock) => {
-                    if let Some(c) = clock.clock() {
-                        log_to_file(&format!("NEW CLOCK: {}", c.name()));
-                    }
-                }
-                MessageView::Qos(..) => {
-                    // QoS messages can be spammy, only log occasionally
-                }
-                _ => {}
-            }
+/// Stop one stream session, or every session when no id is given
+#[tauri::command]
+pub async fn stop_stream<R: Runtime>(app: AppHandle<R>, session_id: Option<String>) -> Result<(), String> {
+    let state = app.state::<StreamingState>();
 
-            // Check if we should stop
-            if let Ok(running) = shared.is_running.lock() {
-                if !*running {
-                    log_to_file("Stop requested, exiting message loop");
-                    break;
-                }
-            }
+    let stopping: Vec<Arc<StreamSession>> = {
+        let mut sessions = state.sessions.lock().unwrap();
+        match session_id {
+            Some(ref id) => vec![sessions.remove(id).ok_or_else(|| format!("No stream with id '{}'", id))?],
+            None => sessions.drain().map(|(_, s)| s).collect(),
         }
+    };
 
-        // Mark as not running when message loop exits
-        if let Ok(mut running) = shared.is_running.lock() {
-            *running = false;
-        }
-        log_to_file("=== MESSAGE HANDLER THREAD EXITED ===");
-    });
+    for session in stopping {
+        let _log = log_scope(&session.id);
+        lifecycle::transition(&app, &session.shared, StreamState::Stopping, None, None, None);
+        session.shutdown();
+        lifecycle::transition(&app, &session.shared, StreamState::Idle, None, None, None);
+    }
+
+    // Restore encoder ranks once the last WHIP session is gone
+    state.release_encoder_pin();
 
     Ok(())
 }
 
-/// Stop streaming
+/// Get the status of one stream session.
+/// Without an id, the only running session (or an idle status if none).
+#[tauri::command]
+pub async fn get_stream_status<R: Runtime>(app: AppHandle<R>, session_id: Option<String>) -> Result<StreamStatus, String> {
+    let state = app.state::<StreamingState>();
+
+    if session_id.is_none() && state.sessions.lock().unwrap().is_empty() {
+        return Ok(StreamStatus {
+            session_id: None,
+            active: false,
+            source_id: None,
+            whip_url: None,
+            duration_seconds: 0,
+            error: None,
+            state: StreamState::Idle,
+            paused: false,
+            recording: None,
+            outputs: Vec::new(),
+        });
+    }
+
+    Ok(state.session(session_id.as_deref())?.status())
+}
+
+/// List all stream sessions, oldest first
 #[tauri::command]
-pub async fn stop_stream(app: AppHandle) -> Result<(), String> {
+pub async fn list_stream_sessions<R: Runtime>(app: AppHandle<R>) -> Result<Vec<StreamStatus>, String> {
     let state = app.state::<StreamingState>();
+    let mut sessions: Vec<StreamStatus> = state.sessions.lock().unwrap()
+        .values()
+        .map(|s| s.status())
+        .collect();
+    sessions.sort_by(|a, b| b.duration_seconds.cmp(&a.duration_seconds));
+    Ok(sessions)
+}
+
+/// Swap the capture source of a running session. The WHIP session, tees and
+/// local outputs stay up; the new source is scaled to the configured size.
+#[tauri::command]
+pub async fn switch_capture_source<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: Option<String>,
+    source_id: String,
+) -> Result<(), String> {
+    let session = app.state::<StreamingState>().session(session_id.as_deref())?;
+
+    // The portal dialog and the pad swap both block
+    tauri::async_runtime::spawn_blocking(move || {
+        let _log = log_scope(&session.id);
+        let _switching = session.switching.lock().unwrap();
+        if *session.paused.lock().unwrap() {
+            return Err("Resume the stream before switching sources".to_string());
+        }
+
+        let mut config = session.config.lock().unwrap().clone();
+        if config.source_id == source_id {
+            return Ok(());
+        }
+        log_to_file(&format!("Switching capture source: {} -> {}", config.source_id, source_id));
+        config.source_id = source_id;
+
+        #[cfg(target_os = "linux")]
+        let screencast = {
+            let (source_id, screencast) = portal::resolve(&app, &config.source_id)?;
+            config.source_id = source_id;
+            screencast
+        };
+
+        switcher::switch_source(&session.pipeline, &build_video_source(&config))?;
+        session.config.lock().unwrap().source_id = config.source_id;
 
-    let pipeline_opt = {
-        let mut pipeline_lock = state.pipeline.lock().unwrap();
-        pipeline_lock.take()
+        // The old portal session (if any) is closed now that its source is gone
+        #[cfg(target_os = "linux")]
+        {
+            *session.screencast.lock().unwrap() = screencast;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("Switch source task failed: {}", e))?
+}
+
+/// Re-encode running recordings / replay buffers at the new bitrate
+fn update_local_encoders(session: &StreamSession, config: &StreamConfig) {
+    let (start_bitrate, _, _) = quality_bitrates(config);
+    let whip = session.pipeline.by_name("whip");
+    for element in session.pipeline.iterate_recurse().into_iter().flatten() {
+        let is_local_encoder = element.factory().map(|f| f.name() == session.encoder_factory).unwrap_or(false)
+            && !whip.as_ref().map(|w| element.has_as_ancestor(w)).unwrap_or(false);
+        if is_local_encoder && encoder::set_bitrate(&element, session.encoder_factory, start_bitrate / 1000) {
+            log_to_file(&format!("{} bitrate set to {} kbps", element.name(), start_bitrate / 1000));
+        }
+    }
+}
+
+/// Move a running WHIP sink to the bitrates of `config` without
+/// renegotiating: its own range (read by new sessions), each congestion
+/// controller's range, the running encoders and the simulcast layer caps.
+/// The encoder preset follows on the next renegotiation.
+fn update_whip_bitrates(session: &StreamSession, config: &StreamConfig) {
+    session.shared.layer_caps.set(&simulcast::layers(config));
+    // None while a reconnect rebuilds the sink; it reads the new config
+    let whip = match session.pipeline.by_name("whip") {
+        Some(whip) => whip,
+        None => return,
+    };
+    let (start_bitrate, min_bitrate, max_bitrate) = quality_bitrates(config);
+    let set = |element: &gst::Element, property: &str, value: u32| {
+        if let Err(e) = pipeline::set_property_from_str(element, property, &value.to_string()) {
+            log_to_file(&format!("WARNING: {}: {}, skipped", element.name(), e));
+        }
     };
+    set(&whip, "start-bitrate", start_bitrate);
+    set(&whip, "min-bitrate", min_bitrate);
+    set(&whip, "max-bitrate", max_bitrate);
+
+    if let Some(bin) = whip.downcast_ref::<gst::Bin>() {
+        for element in bin.iterate_recurse().into_iter().flatten() {
+            let factory = element.factory().map(|f| f.name().to_string()).unwrap_or_default();
+            if factory == "rtpgccbwe" {
+                set(&element, "min-bitrate", min_bitrate);
+                set(&element, "max-bitrate", max_bitrate);
+            } else if factory == session.encoder_factory {
+                // Lower simulcast layers are clamped back to their cap
+                encoder::set_bitrate(&element, session.encoder_factory, start_bitrate / 1000);
+            }
+        }
+    }
+    log_to_file(&format!(
+        "WHIP bitrate range set to {}-{} kbps (start {} kbps)",
+        min_bitrate / 1000,
+        max_bitrate / 1000,
+        start_bitrate / 1000
+    ));
+}
 
-    if let Some(pipeline) = pipeline_opt {
-        log_to_file("Stopping GStreamer pipeline");
+/// Change bitrate, framerate, resolution or quality mode of a running
+/// stream. Bitrate and quality mode are applied to the running encoders and
+/// congestion control. Framerate and resolution go through the source
+/// switcher; whipclientsink can't change its caps once negotiated, so with
+/// WHIP they need a new WHIP session. That only happens when the update sets
+/// `reconnect` (viewers see the stream reconnect); otherwise they are left
+/// out and listed in `requires_reconnect`. Recordings and the replay buffer
+/// keep the size and framerate they started with.
+#[tauri::command]
+pub async fn update_stream_config<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: Option<String>,
+    update: StreamConfigUpdate,
+) -> Result<ConfigUpdateReport, String> {
+    let session = app.state::<StreamingState>().session(session_id.as_deref())?;
+
+    // Source switches wait for the first new frame
+    tauri::async_runtime::spawn_blocking(move || {
+        let _log = log_scope(&session.id);
+        let _switching = session.switching.lock().unwrap();
+        apply_config_update(&app, &session, update)
+    })
+    .await
+    .map_err(|e| format!("Config update task failed: {}", e))?
+}
 
-        // Send EOS to gracefully stop
-        let eos_event: gst::Event = gst::event::Eos::new();
-        let _: bool = pipeline.send_event(eos_event);
+fn apply_config_update<R: Runtime>(app: &AppHandle<R>, session: &StreamSession, update: StreamConfigUpdate) -> Result<ConfigUpdateReport, String> {
+    let old = session.config.lock().unwrap().clone();
+    let mut config = old.clone();
+    config.bitrate = update.bitrate.unwrap_or(config.bitrate);
+    config.fps = update.fps.unwrap_or(config.fps);
+    config.width = update.width.unwrap_or(config.width);
+    config.height = update.height.unwrap_or(config.height);
+    if let Some(mode) = update.quality_mode {
+        config.quality_mode = mode;
+    }
+    if config.bitrate == 0 || config.fps == 0 || config.width < 16 || config.height < 16 {
+        return Err("Invalid bitrate, framerate or resolution".to_string());
+    }
 
-        // Wait briefly for EOS to propagate, then force stop
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    let mut report = ConfigUpdateReport::default();
+    let geometry_requested = (config.fps, config.width, config.height) != (old.fps, old.width, old.height);
+    if geometry_requested && config.has_whip() && !update.reconnect {
+        // whipclientsink fixes its video caps when negotiating
+        if config.fps != old.fps {
+            report.requires_reconnect.push("fps".to_string());
+        }
+        if (config.width, config.height) != (old.width, old.height) {
+            report.requires_reconnect.push("resolution".to_string());
+        }
+        config.fps = old.fps;
+        config.width = old.width;
+        config.height = old.height;
+    }
+
+    let mut changed = Vec::new();
+    if config.bitrate != old.bitrate {
+        changed.push("bitrate");
+    }
+    if config.quality_mode != old.quality_mode {
+        changed.push("quality_mode");
+    }
+    if config.fps != old.fps {
+        changed.push("fps");
+    }
+    if (config.width, config.height) != (old.width, old.height) {
+        changed.push("resolution");
+    }
+    if changed.is_empty() {
+        return Ok(report);
+    }
+    log_to_file(&format!("Updating stream config: {}", changed.join(", ")));
+
+    let rate_changed = changed.contains(&"bitrate") || changed.contains(&"quality_mode");
+    let geometry_changed = changed.contains(&"fps") || changed.contains(&"resolution");
+    if geometry_changed {
+        // The slate is fixed to the old caps
+        if *session.paused.lock().unwrap() {
+            return Err("Resume the stream before changing resolution or framerate".to_string());
+        }
+        if simulcast::layers(&config).len() != simulcast::layers(&old).len() {
+            return Err("New resolution would change the number of simulcast layers".to_string());
+        }
+    }
 
-        let _result = pipeline.set_state(gst::State::Null);
+    // A new WHIP session for the new caps (`update.reconnect` is set)
+    let mut detached = if geometry_changed && config.has_whip() {
+        let detached = reconnect::detach_whip(&session.pipeline)
+            .map_err(|e| format!("Cannot renegotiate while the WHIP sink is reconnecting: {}", e))?;
+        lifecycle::transition(app, &session.shared, StreamState::Reconnecting, Some("whip"), None, None);
+        Some(detached)
+    } else {
+        None
+    };
+    let reattach = |detached: &mut reconnect::DetachedSink, config: &StreamConfig| -> Result<(), String> {
+        reconnect::attach_whip(&session.pipeline, config, detached, |whip| {
+            setup_whip(whip, config, session.encoder_factory, &session.shared)
+        })?;
+        lifecycle::transition(app, &session.shared, StreamState::Connecting, Some("whip"), None, None);
+        Ok(())
+    };
 
-        log_to_file("GStreamer pipeline stopped");
+    if geometry_changed {
+        let resized = switcher::switch_source(&session.pipeline, &build_video_source(&config))
+            .and_then(|_| overlay::resize(&session.pipeline, config.width, config.height, config.fps))
+            .and_then(|_| simulcast::resize(&session.pipeline, &simulcast::layers(&config)));
+        if let Err(e) = resized {
+            if let Some(ref mut detached) = detached {
+                if let Err(e) = reattach(detached, &old) {
+                    log_to_file(&format!("WARNING: Failed to restore WHIP sink: {}", e));
+                }
+            }
+            return Err(format!("Failed to apply new resolution / framerate: {}", e));
+        }
     }
 
-    // Clear state
-    {
-        let mut config = state.current_config.lock().unwrap();
-        *config = None;
+    *session.config.lock().unwrap() = config.clone();
+    if rate_changed {
+        update_local_encoders(session, &config);
+        if detached.is_none() && config.has_whip() {
+            update_whip_bitrates(session, &config);
+        }
     }
-    {
-        let mut start = state.start_time.lock().unwrap();
-        *start = None;
+    if let Some(ref mut detached) = detached {
+        if let Err(e) = reattach(detached, &config) {
+            let msg = format!("Applied changes but failed to rebuild WHIP sink: {}", e);
+            session::fail(app, session, "whip", ErrorKind::Negotiation, msg.clone());
+            return Err(msg);
+        }
     }
-    {
-        let mut running = state.shared.is_running.lock().unwrap();
-        *running = false;
+
+    // Only the caps-bound fields needed the new WHIP session
+    for field in changed {
+        let target = if detached.is_some() && (field == "fps" || field == "resolution") {
+            &mut report.reconnected
+        } else {
+            &mut report.applied
+        };
+        target.push(field.to_string());
     }
+    log_to_file(&format!(
+        "Stream config updated (in place: {:?}, reconnected: {:?}, needs reconnect: {:?})",
+        report.applied, report.reconnected, report.requires_reconnect
+    ));
+    Ok(report)
+}
+
+/// Change the volume of a stream's audio input while streaming
+#[tauri::command]
+pub async fn set_audio_volume<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: Option<String>,
+    input: AudioInput,
+    volume: f64,
+) -> Result<(), String> {
+    update_audio_level(&app, session_id.as_deref(), input, |level| level.volume = volume)
+}
 
+/// Mute or unmute a stream's audio input while streaming
+#[tauri::command]
+pub async fn set_audio_mute<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: Option<String>,
+    input: AudioInput,
+    muted: bool,
+) -> Result<(), String> {
+    update_audio_level(&app, session_id.as_deref(), input, |level| level.muted = muted)
+}
+
+/// Apply `change` to an input's level in the running pipeline and the config
+fn update_audio_level<R: Runtime>(
+    app: &AppHandle<R>,
+    session_id: Option<&str>,
+    input: AudioInput,
+    change: impl FnOnce(&mut AudioLevel),
+) -> Result<(), String> {
+    let state = app.state::<StreamingState>();
+    let session = state.session(session_id)?;
+    let _log = log_scope(&session.id);
+
+    let mut config = session.config.lock().unwrap();
+    let level = match input {
+        AudioInput::Desktop if config.audio_enabled => &mut config.desktop_audio,
+        AudioInput::Microphone => match config.microphone {
+            Some(ref mut mic) => &mut mic.level,
+            None => return Err("Stream has no microphone input".to_string()),
+        },
+        AudioInput::Desktop => return Err("Stream has no desktop audio".to_string()),
+    };
+    let mut updated = *level;
+    change(&mut updated);
+    if !updated.volume.is_finite() || updated.volume < 0.0 {
+        return Err(format!("Invalid volume {}", updated.volume));
+    }
+    updated.volume = updated.volume.min(mixer::MAX_VOLUME);
+
+    // A paused stream stays silent; the level applies on resume
+    if !*session.paused.lock().unwrap() {
+        mixer::set_level(&session.pipeline, input, updated)?;
+    }
+    *level = updated;
+    log_to_file(&format!("Audio {:?}: volume {:.2}{}", input, updated.volume, if updated.muted { " (muted)" } else { "" }));
     Ok(())
 }
 
-/// Get current stream status
+/// Move, resize, fade or retitle an overlay layer while streaming
 #[tauri::command]
-pub async fn get_stream_status(app: AppHandle) -> Result<StreamStatus, String> {
+pub async fn update_overlay<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: Option<String>,
+    update: OverlayUpdate,
+) -> Result<(), String> {
     let state = app.state::<StreamingState>();
+    let session = state.session(session_id.as_deref())?;
+    let _log = log_scope(&session.id);
 
-    let pipeline_guard = state.pipeline.lock().unwrap();
-    let config_guard = state.current_config.lock().unwrap();
-    let start_time_guard = state.start_time.lock().unwrap();
-    let last_error_guard = state.shared.last_error.lock().unwrap();
+    let paused = *session.paused.lock().unwrap();
+    let mut config = session.config.lock().unwrap();
+    overlay::update(&session.pipeline, &mut config.overlays, &update, paused)
+}
 
-    let active = pipeline_guard.is_some();
+/// Audio inputs of `config` with their configured levels
+fn audio_levels(config: &StreamConfig) -> Vec<(AudioInput, AudioLevel)> {
+    let mut levels = Vec::new();
+    if config.audio_enabled {
+        levels.push((AudioInput::Desktop, config.desktop_audio));
+    }
+    if let Some(ref mic) = config.microphone {
+        levels.push((AudioInput::Microphone, mic.level));
+    }
+    levels
+}
 
-    // Calculate duration without complex type inference
-    let duration_seconds: u64 = get_elapsed_seconds(&start_time_guard);
+/// Replace the capture with a slate and mute all audio, keeping the
+/// session (WHIP, recordings, duration) running. `slate` overrides the
+/// configured one for this pause.
+#[tauri::command]
+pub async fn pause_stream<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: Option<String>,
+    slate: Option<SlateConfig>,
+) -> Result<(), String> {
+    let session = app.state::<StreamingState>().session(session_id.as_deref())?;
+
+    // Swapping the selector pad blocks until the slate is flowing
+    tauri::async_runtime::spawn_blocking(move || {
+        let _log = log_scope(&session.id);
+        let _switching = session.switching.lock().unwrap();
+        if *session.paused.lock().unwrap() {
+            return Ok(());
+        }
 
-    let source_id: Option<String> = if let Some(ref c) = *config_guard {
-        Some(c.source_id.clone())
-    } else {
-        None
-    };
+        let config = session.config.lock().unwrap().clone();
+        let slate = slate.unwrap_or_else(|| config.slate.clone());
+        switcher::show_slate(&session.pipeline, &slate::source(&slate, &config)?)?;
+        *session.paused.lock().unwrap() = true;
 
-    let whip_url: Option<String> = if let Some(ref c) = *config_guard {
-        Some(c.whip_url.clone())
-    } else {
-        None
-    };
+        for (input, level) in audio_levels(&config) {
+            let silent = AudioLevel { muted: true, ..level };
+            if let Err(e) = mixer::set_level(&session.pipeline, input, silent) {
+                log_to_file(&format!("WARNING: {}", e));
+            }
+        }
+        overlay::set_hidden(&session.pipeline, &config.overlays, true);
+        log_to_file("Stream paused");
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("Pause task failed: {}", e))?
+}
+
+/// Go back to the capture and the configured audio levels
+#[tauri::command]
+pub async fn resume_stream<R: Runtime>(app: AppHandle<R>, session_id: Option<String>) -> Result<(), String> {
+    let session = app.state::<StreamingState>().session(session_id.as_deref())?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let _log = log_scope(&session.id);
+        let _switching = session.switching.lock().unwrap();
+        if !*session.paused.lock().unwrap() {
+            return Ok(());
+        }
 
-    let error: Option<String> = last_error_guard.clone();
+        switcher::hide_slate(&session.pipeline)?;
+        *session.paused.lock().unwrap() = false;
 
-    Ok(StreamStatus {
-        active,
-        source_id,
-        whip_url,
-        duration_seconds,
-        error,
+        let config = session.config.lock().unwrap().clone();
+        for (input, level) in audio_levels(&config) {
+            if let Err(e) = mixer::set_level(&session.pipeline, input, level) {
+                log_to_file(&format!("WARNING: {}", e));
+            }
+        }
+        overlay::set_hidden(&session.pipeline, &config.overlays, false);
+        log_to_file("Stream resumed");
+        Ok(())
     })
+    .await
+    .map_err(|e| format!("Resume task failed: {}", e))?
 }
 
-/// Helper to get elapsed seconds from optional start time
-fn get_elapsed_seconds(start_time: &Option<std::time::Instant>) -> u64 {
-    match start_time {
-        Some(t) => t.elapsed().as_secs(),
-        None => 0,
+/// Retry an RTMP / SRT output that failed or was stopped
+#[tauri::command]
+pub async fn restart_output<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: Option<String>,
+    output_id: String,
+) -> Result<OutputStatus, String> {
+    let state = app.state::<StreamingState>();
+    let session = state.session(session_id.as_deref())?;
+    let _log = log_scope(&session.id);
+
+    let mut outputs = session.outputs.lock().unwrap();
+    let output = outputs
+        .iter_mut()
+        .find(|o| o.id == output_id)
+        .ok_or_else(|| format!("No output '{}'", output_id))?;
+    if let Err(e) = output.start(&session.pipeline) {
+        output.fail(&session.pipeline, e.clone());
+        return Err(e);
     }
+    Ok(output.status())
 }
 
-/// Get streaming debug log (last N lines)
+/// Play a stream from a WHEP endpoint. Returns the playback session id.
 #[tauri::command]
-pub async fn get_streaming_log(_app: AppHandle, lines: Option<u32>) -> Result<String, String> {
-    #[cfg(target_os = "windows")]
-    {
-        let log_path = std::env::var("TEMP")
-            .unwrap_or_else(|_| "C:\\Windows\\Temp".to_string());
-        let log_file = format!("{}\\cinny_streaming.log", log_path);
-
-        match std::fs::read_to_string(&log_file) {
-            Ok(content) => {
-                let max_lines = lines.unwrap_or(100) as usize;
-                let all_lines: Vec<&str> = content.lines().collect();
-                let start = if all_lines.len() > max_lines {
-                    all_lines.len() - max_lines
-                } else {
-                    0
-                };
-                Ok(all_lines[start..].join("\n"))
-            }
-            Err(e) => Err(format!("Failed to read log: {}", e))
-        }
+pub async fn start_playback<R: Runtime>(
+    app: AppHandle<R>,
+    config: PlaybackConfig,
+    session_id: Option<String>,
+) -> Result<String, String> {
+    let state = app.state::<StreamingState>();
+    let session_id = session_id
+        .filter(|id| !id.trim().is_empty())
+        .unwrap_or_else(|| format!("playback-{}", state.next_id.fetch_add(1, Ordering::Relaxed)));
+    validate_session_id(&session_id)?;
+    let _log = log_scope(&session_id);
+
+    log_to_file("=== START_PLAYBACK ===");
+    log_to_file(&format!("WHEP URL: {}", config.whep_url));
+    log_to_file(&format!("Output: {:?}, audio: {}, latency: {}ms", config.output, config.audio, config.latency_ms));
+
+    if config.whep_url.trim().is_empty() {
+        return Err("A WHEP URL is required".to_string());
     }
-    #[cfg(not(target_os = "windows"))]
-    {
-        Ok("Log file only available on Windows".to_string())
+    if state.playbacks.lock().unwrap().contains_key(&session_id) {
+        return Err(format!("Playback '{}' is already running", session_id));
+    }
+    if let Err(e) = gst::init() {
+        return Err(format!("GStreamer is not available: {}", e));
     }
+
+    let shared = SharedState {
+        session_id: session_id.clone(),
+        playback: true,
+        ..SharedState::default()
+    };
+    lifecycle::transition(&app, &shared, StreamState::Starting, None, None, None);
+
+    let session = playback::start(&app, config, shared.clone()).map_err(|e| {
+        log_to_file(&format!("ERROR: {}", e));
+        lifecycle::transition(&app, &shared, StreamState::Failed, None, None, Some(e.clone()));
+        e
+    })?;
+    lifecycle::transition(&app, &shared, StreamState::Connecting, None, None, None);
+
+    state.playbacks.lock().unwrap().insert(session_id.clone(), session);
+    Ok(session_id)
 }
 
-/// Clear streaming debug log
+/// Stop one playback, or every playback when no id is given
 #[tauri::command]
-pub async fn clear_streaming_log(_app: AppHandle) -> Result<(), String> {
-    #[cfg(target_os = "windows")]
-    {
-        let log_path = std::env::var("TEMP")
-            .unwrap_or_else(|_| "C:\\Windows\\Temp".to_string());
-        let log_file = format!("{}\\cinny_streaming.log", log_path);
-
-        match std::fs::write(&log_file, "") {
-            Ok(_) => {
-                log_to_file("=== LOG CLEARED ===");
-                Ok(())
-            }
-            Err(e) => Err(format!("Failed to clear log: {}", e))
+pub async fn stop_playback<R: Runtime>(app: AppHandle<R>, session_id: Option<String>) -> Result<(), String> {
+    let state = app.state::<StreamingState>();
+
+    let stopping: Vec<Arc<PlaybackSession>> = {
+        let mut playbacks = state.playbacks.lock().unwrap();
+        match session_id {
+            Some(ref id) => playbacks.remove(id).into_iter().collect(),
+            None => playbacks.drain().map(|(_, p)| p).collect(),
         }
+    };
+
+    for playback in stopping {
+        let _log = log_scope(&playback.id);
+        lifecycle::transition(&app, &playback.shared, StreamState::Stopping, None, None, None);
+        playback.shutdown();
+        lifecycle::transition(&app, &playback.shared, StreamState::Idle, None, None, None);
     }
-    #[cfg(not(target_os = "windows"))]
-    {
-        Ok(())
+    Ok(())
+}
+
+/// Get the status of one playback
+#[tauri::command]
+pub async fn get_playback_status<R: Runtime>(app: AppHandle<R>, session_id: Option<String>) -> Result<PlaybackStatus, String> {
+    let state = app.state::<StreamingState>();
+    Ok(state.playback(session_id.as_deref())?.status())
+}
+
+/// Pipeline of a stream or playback session
+fn session_pipeline(state: &StreamingState, session_id: Option<&str>) -> Result<(String, gst::Pipeline), String> {
+    match state.session(session_id) {
+        Ok(session) => Ok((session.id.clone(), session.pipeline.clone())),
+        Err(e) => state
+            .playback(session_id)
+            .map(|playback| (playback.id.clone(), playback.pipeline.clone()))
+            .map_err(|_| e),
     }
 }
 
-/// Check GStreamer availability and required plugins
+/// Dump a running session's pipeline: DOT graph with negotiated caps, plus
+/// every element's state, latency and pad caps
 #[tauri::command]
-pub async fn check_gstreamer(_app: AppHandle) -> Result<GStreamerInfo, String> {
-    // Get version - if this works, GStreamer is available
-    let (major, minor, micro, _nano) = gst::version();
-    let version = format!("{}.{}.{}", major, minor, micro);
-    log_to_file(&format!("GStreamer version: {}", version));
+pub async fn dump_stream_pipeline<R: Runtime>(app: AppHandle<R>, session_id: Option<String>) -> Result<PipelineDump, String> {
+    let state = app.state::<StreamingState>();
+    let (id, pipeline) = session_pipeline(&state, session_id.as_deref())?;
+    let _log = log_scope(&id);
+    Ok(diagnostics::dump(&id, &pipeline))
+}
 
-    // Actually check if plugins are loadable from the registry
-    let has_whip = gst::ElementFactory::find("whipclientsink").is_some();
+/// A running session's pipeline graph as SVG (needs Graphviz) or DOT text
+#[tauri::command]
+pub async fn render_stream_pipeline<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: Option<String>,
+    format: Option<GraphFormat>,
+) -> Result<String, String> {
+    let state = app.state::<StreamingState>();
+    let (id, pipeline) = session_pipeline(&state, session_id.as_deref())?;
+    let _log = log_scope(&id);
+    let dot = diagnostics::dot(&pipeline);
+    match format.unwrap_or_default() {
+        GraphFormat::Dot => Ok(dot),
+        GraphFormat::Svg => diagnostics::render_svg(&dot),
+    }
+}
 
-    #[cfg(target_os = "windows")]
-    let has_d3d11 = gst::ElementFactory::find("d3d11screencapturesrc").is_some();
+/// Start recording a running session (or local-only session) to disk
+#[tauri::command]
+pub async fn start_recording<R: Runtime>(
+    app: AppHandle<R>,
+    session_id: Option<String>,
+    target: Option<RecordingTarget>,
+) -> Result<String, String> {
+    let state = app.state::<StreamingState>();
+    let session = state.session(session_id.as_deref())?;
+    let _log = log_scope(&session.id);
+    let target = target.unwrap_or_default();
+    let config = session.config.lock().unwrap().clone();
+
+    let mut recorder_lock = session.recorder.lock().unwrap();
+    if recorder_lock.is_some() {
+        return Err("Already recording".to_string());
+    }
 
-    #[cfg(not(target_os = "windows"))]
-    let has_d3d11 = false;
+    let recorder = recording::start(&app, &session.id, &session.pipeline, &config, session.encoder_factory, &target)?;
+    let path = recorder.path.to_string_lossy().to_string();
+    *recorder_lock = Some(recorder);
+    Ok(path)
+}
 
-    // Check for encoder elements that whipclientsink needs
-    let has_x264enc = gst::ElementFactory::find("x264enc").is_some();
-    let has_openh264enc = gst::ElementFactory::find("openh264enc").is_some();
-    let has_rtph264pay = gst::ElementFactory::find("rtph264pay").is_some();
+/// Stop a session's recording; the live stream keeps running
+#[tauri::command]
+pub async fn stop_recording<R: Runtime>(app: AppHandle<R>, session_id: Option<String>) -> Result<RecordingInfo, String> {
+    let state = app.state::<StreamingState>();
+    let session = state.session(session_id.as_deref())?;
 
-    log_to_file(&format!(
-        "GStreamer plugins - whipclientsink: {}, d3d11: {}, x264enc: {}, openh264enc: {}, rtph264pay: {}",
-        has_whip, has_d3d11, has_x264enc, has_openh264enc, has_rtph264pay
-    ));
+    let recorder = session.recorder.lock().unwrap().take().ok_or("Not recording")?;
+    // Waits for the muxer to finalize the file
+    tauri::async_runtime::spawn_blocking(move || {
+        let _log = log_scope(&session.id);
+        recorder.stop(&session.pipeline)
+    })
+    .await
+    .map_err(|e| format!("Stop recording task failed: {}", e))?
+}
 
-    // Always log plugin info for debugging
-    if let Ok(plugin_path) = std::env::var("GST_PLUGIN_PATH") {
-        log_to_file(&format!("GST_PLUGIN_PATH: {}", plugin_path));
-    }
+/// List recordings in `directory` (default recordings folder if omitted)
+#[tauri::command]
+pub async fn list_recordings<R: Runtime>(app: AppHandle<R>, directory: Option<String>) -> Result<Vec<RecordingInfo>, String> {
+    let dir = recording::recordings_dir(&app, directory.as_deref())?;
+    recording::list(&dir)
+}
 
-    // List all loaded plugins for debugging
-    let registry = gst::Registry::get();
-    let plugins: Vec<String> = registry.plugins().iter()
-        .map(|p| p.plugin_name().to_string())
-        .collect();
-    log_to_file(&format!("Loaded plugins ({}): {:?}", plugins.len(), plugins));
+/// Save a session's instant replay buffer to an MP4 file in the recordings
+/// folder. The returned path can be passed to `native_upload_file_path`.
+#[tauri::command]
+pub async fn save_replay<R: Runtime>(app: AppHandle<R>, session_id: Option<String>) -> Result<ReplayClip, String> {
+    let state = app.state::<StreamingState>();
+    let session = state.session(session_id.as_deref())?;
+
+    // Snapshot under the lock, write without holding it
+    let snapshot = session.replay.lock().unwrap()
+        .as_ref()
+        .map(|buffer| buffer.snapshot())
+        .ok_or("Replay buffer is not running")?;
+
+    // Muxing the clip takes a while
+    let session_id = session.id.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let _log = log_scope(&session_id);
+        let dir = recording::recordings_dir(&app, None)?;
+        let path = recording::unique_path(&dir, "replay", &session_id, "mp4");
+        replay::save(&snapshot, &path)
+    })
+    .await
+    .map_err(|e| format!("Save replay task failed: {}", e))?
+}
 
-    Ok(GStreamerInfo {
-        available: true,
-        version: Some(version),
-        has_whip,
-        has_d3d11,
+/// Get the streaming log (last N lines), optionally only one session's
+/// lines and only those at or above `level`
+#[tauri::command]
+pub async fn get_streaming_log<R: Runtime>(
+    _app: AppHandle<R>,
+    lines: Option<u32>,
+    session_id: Option<String>,
+    level: Option<LogLevel>,
+) -> Result<String, String> {
+    logging::read(lines.unwrap_or(100) as usize, session_id.as_deref(), level)
+}
+
+/// Clear the streaming log
+#[tauri::command]
+pub async fn clear_streaming_log<R: Runtime>(_app: AppHandle<R>) -> Result<(), String> {
+    logging::clear()?;
+    log_to_file("=== LOG CLEARED ===");
+    Ok(())
+}
+
+/// Check GStreamer availability: capture backends, encoders, audio sources,
+/// muxers and WebRTC elements, and which missing plugins disable what
+#[tauri::command]
+pub async fn check_gstreamer<R: Runtime>(_app: AppHandle<R>) -> Result<GStreamerInfo, String> {
+    Ok(capabilities::report())
+}
+
+/// Encode a few seconds with each installed H.264 encoder at descending
+/// resolutions / framerates and recommend the settings this machine can
+/// sustain. Cached per machine unless `force`; progress goes to `benchmark-progress`.
+#[tauri::command]
+pub async fn benchmark_stream_settings<R: Runtime>(
+    app: AppHandle<R>,
+    options: Option<BenchmarkOptions>,
+) -> Result<BenchmarkResult, String> {
+    let options = options.unwrap_or_default();
+
+    // The portal dialog and the encode runs both block
+    tauri::async_runtime::spawn_blocking(move || {
+        let _log = log_scope("benchmark");
+        // A running stream would skew the numbers and compete for the encoder
+        let state = app.state::<StreamingState>();
+        let _reservation = state.reserve_benchmark()?;
+
+        let source_id = options.source_id.unwrap_or_else(|| testsrc::SOURCE_ID.to_string());
+        #[cfg(target_os = "linux")]
+        let (source_id, screencast) = portal::resolve(&app, &source_id)?;
+
+        let result = benchmark::run_all(&app, &source_id, options.force);
+        // Closes the portal session once capture is done
+        #[cfg(target_os = "linux")]
+        drop(screencast);
+        result
     })
+    .await
+    .map_err(|e| format!("Benchmark task failed: {}", e))?
 }
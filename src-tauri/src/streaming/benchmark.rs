@@ -0,0 +1,496 @@
+//! Encoder benchmark and recommended stream settings
+//!
+//! `run_all` encodes a few seconds of the test source (or a real capture) with
+//! every installed H.264 encoder at descending resolution / framerate steps,
+//! offline into a fakesink, and recommends the best step some encoder
+//! sustains. Results are cached per machine in `<app data>/benchmark.json`;
+//! a different GStreamer version or encoder set invalidates the cache.
+
+use gstreamer as gst;
+use gst::prelude::{ElementExt, GstBinExt, GstBinExtManual, GstObjectExt, PadExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager, Runtime};
+
+use super::encoder::{self, EncoderChoice, VideoCodec};
+use super::mixer::AudioLevel;
+use super::pipeline::ElementSpec;
+use super::slate::SlateConfig;
+use super::stats::QueueDrops;
+use super::{build_video_source, log_to_file, testsrc, QualityMode, StreamConfig};
+
+pub const PROGRESS_EVENT: &str = "benchmark-progress";
+
+/// Settings tried per encoder, best first: (width, height, fps, bitrate kbps)
+const STEPS: &[(u32, u32, u32, u32)] = &[
+    (1920, 1080, 60, 8000),
+    (1920, 1080, 30, 6000),
+    (1280, 720, 60, 5000),
+    (1280, 720, 30, 3500),
+    (854, 480, 30, 1500),
+];
+
+/// Not measured, lets the encoder and source settle
+const WARMUP: Duration = Duration::from_millis(500);
+const MEASURE: Duration = Duration::from_secs(3);
+
+/// Share of the target framerate a step must reach to count as sustained
+const MIN_FPS_RATIO: f64 = 0.95;
+/// Share of captured frames that may be dropped
+const MAX_DROP_RATIO: f64 = 0.02;
+
+const CACHE_FILE: &str = "benchmark.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BenchmarkOptions {
+    #[serde(default)]
+    pub source_id: Option<String>, // None = the "test" source
+    #[serde(default)]
+    pub force: bool, // Ignore a cached result
+}
+
+/// One encoder at one step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkRun {
+    pub encoder: String,
+    pub hardware: bool,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate: u32, // kbps
+    pub encoded_fps: f64,
+    pub dropped_frames: u64,
+    pub avg_encode_ms: Option<f64>, // Encoder input to output, None if nothing was encoded
+    pub sustained: bool,
+    pub error: Option<String>,
+}
+
+/// `StreamConfig` fields to apply, under the same names
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecommendedSettings {
+    pub encoder: EncoderChoice,
+    pub encoder_factory: String, // What `encoder` resolves to on this machine
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate: u32, // kbps
+    pub quality_mode: QualityMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    pub fingerprint: String, // Machine, GStreamer version and installed encoders
+    pub source_id: String,
+    pub created_at: u64, // Unix seconds
+    pub cached: bool,
+    pub runs: Vec<BenchmarkRun>,
+    pub recommended: Option<RecommendedSettings>, // None if no encoder sustained any step
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkProgress {
+    pub encoder: String,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub completed: usize, // Runs finished so far
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn hostname() -> String {
+    ["COMPUTERNAME", "HOSTNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Installed H.264 encoders, hardware first
+fn installed_encoders() -> Vec<(&'static str, bool)> {
+    let hardware = VideoCodec::H264.hardware_encoders().iter().map(|e| (*e, true));
+    let software = VideoCodec::H264.software_encoders().iter().map(|e| (*e, false));
+    hardware
+        .chain(software)
+        .filter(|(factory, _)| gst::ElementFactory::find(factory).is_some())
+        .collect()
+}
+
+fn fingerprint(encoders: &[(&'static str, bool)]) -> String {
+    format!(
+        "{} / GStreamer {} / {}",
+        hostname(),
+        gst::version_string(),
+        encoders.iter().map(|(factory, _)| *factory).collect::<Vec<_>>().join(",")
+    )
+}
+
+fn cache_path<R: Runtime>(app: &AppHandle<R>) -> Option<PathBuf> {
+    app.path_resolver().app_data_dir().map(|dir| dir.join(CACHE_FILE))
+}
+
+fn load_cache<R: Runtime>(app: &AppHandle<R>) -> Vec<BenchmarkResult> {
+    cache_path(app)
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Keep one result per machine and source
+fn save_cache<R: Runtime>(app: &AppHandle<R>, result: &BenchmarkResult) {
+    let path = match cache_path(app) {
+        Some(path) => path,
+        None => return,
+    };
+    let mut results = load_cache(app);
+    results.retain(|r| !(r.fingerprint == result.fingerprint && r.source_id == result.source_id));
+    results.push(result.clone());
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let written = serde_json::to_string_pretty(&results)
+        .map_err(|e| e.to_string())
+        .and_then(|json| std::fs::write(&path, json).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        log_to_file(&format!("WARNING: failed to save benchmark result: {}", e));
+    }
+}
+
+/// Capture-only config for one step: no WHIP, audio or extras
+fn step_config(source_id: &str, (width, height, fps, bitrate): (u32, u32, u32, u32)) -> StreamConfig {
+    StreamConfig {
+        source_id: source_id.to_string(),
+        whip_url: String::new(),
+        width,
+        height,
+        fps,
+        bitrate,
+        audio_enabled: false,
+        bearer_token: None,
+        turn_server: None,
+        quality_mode: QualityMode::Balanced,
+        codec: VideoCodec::H264,
+        encoder: EncoderChoice::Auto,
+        preset: None,
+        reconnect: None,
+        recording: None,
+        replay: None,
+        desktop_audio: AudioLevel::default(),
+        loopback_device: None,
+        microphone: None,
+        simulcast: None,
+        overlays: Vec::new(),
+        slate: SlateConfig::default(),
+        outputs: Vec::new(),
+    }
+}
+
+struct Counters {
+    queue: gst::Element,
+    queue_drops: QueueDrops, // Captured frames in, dropped ones derived
+    encoded: AtomicU64,
+    encode_ns: AtomicU64,
+    in_flight: Mutex<VecDeque<Instant>>, // Encoder input times, oldest first
+}
+
+#[derive(Clone, Copy)]
+struct Snapshot {
+    at: Instant,
+    captured: u64,
+    encoded: u64,
+    dropped: u64,
+    encode_ns: u64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            at: Instant::now(),
+            captured: self.queue_drops.received(),
+            encoded: self.encoded.load(Ordering::Relaxed),
+            dropped: self.queue_drops.dropped(&self.queue),
+            encode_ns: self.encode_ns.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Capture -> leaky queue -> videoconvert -> encoder -> fakesink, with
+/// probes counting frames around the queue and timing the encoder
+fn build(config: &StreamConfig, factory: &'static str) -> Result<(gst::Pipeline, Arc<Counters>), String> {
+    let mut specs = build_video_source(config);
+    #[cfg(target_os = "windows")]
+    specs.push(ElementSpec::new("d3d11download"));
+    specs.push(
+        ElementSpec::new("queue")
+            .named("benchmark_queue")
+            .int("max-size-buffers", 3)
+            .int("max-size-time", 0)
+            .int("max-size-bytes", 0)
+            .enum_nick("leaky", "downstream"),
+    );
+    specs.push(ElementSpec::new("videoconvert"));
+    specs.push(
+        encoder::standalone_encoder(factory, config.bitrate, config.fps * 2, None, &config.quality_mode)
+            .named("benchmark_encoder"),
+    );
+    specs.push(ElementSpec::new("fakesink").bool("sync", false));
+
+    let elements = specs
+        .iter()
+        .map(|spec| spec.make())
+        .collect::<Result<Vec<_>, _>>()?;
+    let pipeline = gst::Pipeline::new();
+    pipeline
+        .add_many(&elements)
+        .map_err(|e| format!("Failed to add benchmark elements: {}", e))?;
+    gst::Element::link_many(&elements)
+        .map_err(|e| format!("Failed to link benchmark pipeline: {}", e))?;
+
+    let element = |name: &str| pipeline.by_name(name).ok_or_else(|| format!("No {} in benchmark pipeline", name));
+    let (queue, encoder) = (element("benchmark_queue")?, element("benchmark_encoder")?);
+    let counters = Arc::new(Counters {
+        queue: queue.clone(),
+        queue_drops: QueueDrops::default(),
+        encoded: AtomicU64::new(0),
+        encode_ns: AtomicU64::new(0),
+        in_flight: Mutex::new(VecDeque::new()),
+    });
+    let pad = |element: &gst::Element, name: &str| {
+        element
+            .static_pad(name)
+            .ok_or_else(|| format!("{} has no {} pad", element.name(), name))
+    };
+
+    counters.queue_drops.watch(&queue);
+
+    // Encoders emit frames in input order, so output N matches input N
+    let c = counters.clone();
+    pad(&encoder, "sink")?.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+        c.in_flight.lock().unwrap().push_back(Instant::now());
+        gst::PadProbeReturn::Ok
+    });
+    let c = counters.clone();
+    pad(&encoder, "src")?.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+        if let Some(start) = c.in_flight.lock().unwrap().pop_front() {
+            c.encode_ns.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+        c.encoded.fetch_add(1, Ordering::Relaxed);
+        gst::PadProbeReturn::Ok
+    });
+
+    Ok((pipeline, counters))
+}
+
+/// Run the pipeline for WARMUP + MEASURE; counts cover MEASURE only
+fn measure(pipeline: &gst::Pipeline, counters: &Counters) -> Result<(Snapshot, Snapshot), String> {
+    let bus = pipeline.bus().ok_or("Benchmark pipeline has no bus")?;
+    pipeline
+        .set_state(gst::State::Playing)
+        .map_err(|e| format!("Failed to start: {:?}", e))?;
+
+    let start = Instant::now();
+    let mut baseline: Option<Snapshot> = None;
+    loop {
+        let elapsed = start.elapsed();
+        if baseline.is_none() && elapsed >= WARMUP {
+            baseline = Some(counters.snapshot());
+        }
+        if elapsed >= WARMUP + MEASURE {
+            break;
+        }
+        let until = if baseline.is_none() { WARMUP } else { WARMUP + MEASURE };
+        let wait = gst::ClockTime::from_nseconds((until - elapsed).as_nanos() as u64);
+        if let Some(msg) = bus.timed_pop_filtered(wait, &[gst::MessageType::Error]) {
+            if let gst::MessageView::Error(err) = msg.view() {
+                return Err(err.error().to_string());
+            }
+        }
+    }
+    let end = counters.snapshot();
+    Ok((baseline.unwrap_or(end), end))
+}
+
+fn run(source_id: &str, factory: &'static str, hardware: bool, step: (u32, u32, u32, u32)) -> BenchmarkRun {
+    let config = step_config(source_id, step);
+    let mut run = BenchmarkRun {
+        encoder: factory.to_string(),
+        hardware,
+        width: config.width,
+        height: config.height,
+        fps: config.fps,
+        bitrate: config.bitrate,
+        encoded_fps: 0.0,
+        dropped_frames: 0,
+        avg_encode_ms: None,
+        sustained: false,
+        error: None,
+    };
+
+    let measured = build(&config, factory).and_then(|(pipeline, counters)| {
+        let result = measure(&pipeline, &counters);
+        let _ = pipeline.set_state(gst::State::Null);
+        result
+    });
+    let (start, end) = match measured {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            log_to_file(&format!("WARNING: benchmark {} {}x{}@{} failed: {}", factory, run.width, run.height, run.fps, e));
+            run.error = Some(e);
+            return run;
+        }
+    };
+
+    let seconds = end.at.duration_since(start.at).as_secs_f64().max(0.001);
+    let captured = end.captured - start.captured;
+    let encoded = end.encoded - start.encoded;
+    run.encoded_fps = encoded as f64 / seconds;
+    run.dropped_frames = end.dropped.saturating_sub(start.dropped);
+    if encoded > 0 {
+        run.avg_encode_ms = Some((end.encode_ns - start.encode_ns) as f64 / encoded as f64 / 1_000_000.0);
+    }
+    run.sustained = run.encoded_fps >= run.fps as f64 * MIN_FPS_RATIO
+        && run.dropped_frames as f64 <= captured.max(1) as f64 * MAX_DROP_RATIO;
+
+    log_to_file(&format!(
+        "Benchmark {} {}x{}@{}: {:.1} fps, {} dropped, {} ms/frame{}",
+        factory,
+        run.width,
+        run.height,
+        run.fps,
+        run.encoded_fps,
+        run.dropped_frames,
+        run.avg_encode_ms.map(|ms| format!("{:.1}", ms)).unwrap_or_else(|| "-".to_string()),
+        if run.sustained { "" } else { " (not sustained)" }
+    ));
+    run
+}
+
+/// The most specific `EncoderChoice` for a factory; Auto for encoders
+/// without one of their own (VA-API, VideoToolbox)
+fn encoder_choice(factory: &str) -> EncoderChoice {
+    [
+        EncoderChoice::Nvenc,
+        EncoderChoice::Qsv,
+        EncoderChoice::Amf,
+        EncoderChoice::Mf,
+        EncoderChoice::Openh264,
+        EncoderChoice::X264,
+    ]
+    .iter()
+    .copied()
+    .find(|choice| choice.candidates(VideoCodec::H264).contains(&factory))
+    .unwrap_or(EncoderChoice::Auto)
+}
+
+/// More encoder headroom leaves room for a slower, better preset
+fn quality_mode(run: &BenchmarkRun) -> QualityMode {
+    let frame_ms = 1000.0 / run.fps as f64;
+    match run.avg_encode_ms.map(|ms| ms / frame_ms) {
+        Some(load) if load < 0.4 => QualityMode::Quality,
+        Some(load) if load < 0.7 => QualityMode::Balanced,
+        _ => QualityMode::Performance,
+    }
+}
+
+/// Best sustained step; among equals the preferred (earlier) encoder
+fn recommend(runs: &[BenchmarkRun]) -> Option<RecommendedSettings> {
+    let step_index = |run: &BenchmarkRun| {
+        STEPS
+            .iter()
+            .position(|&(w, h, fps, _)| (w, h, fps) == (run.width, run.height, run.fps))
+            .unwrap_or(STEPS.len())
+    };
+    let best = runs
+        .iter()
+        .filter(|run| run.sustained)
+        .min_by_key(|run| step_index(run))?;
+    Some(RecommendedSettings {
+        encoder: encoder_choice(&best.encoder),
+        encoder_factory: best.encoder.clone(),
+        width: best.width,
+        height: best.height,
+        fps: best.fps,
+        bitrate: best.bitrate,
+        quality_mode: quality_mode(best),
+    })
+}
+
+/// Benchmark every installed H.264 encoder, or return the cached result.
+/// Blocks for a few seconds per run; `source_id` must already be resolved.
+pub fn run_all<R: Runtime>(app: &AppHandle<R>, source_id: &str, force: bool) -> Result<BenchmarkResult, String> {
+    gst::init().map_err(|e| format!("GStreamer failed to initialize: {}", e))?;
+    let encoders = installed_encoders();
+    if encoders.is_empty() {
+        return Err("No H.264 encoder is installed".to_string());
+    }
+    let fingerprint = fingerprint(&encoders);
+    // Portal / pipewire ids change every session, so cache real captures by kind
+    let cache_source = if testsrc::is_test(source_id) { source_id } else { "capture" };
+
+    if !force {
+        let cached = load_cache(app)
+            .into_iter()
+            .find(|r| r.fingerprint == fingerprint && r.source_id == cache_source);
+        if let Some(mut cached) = cached {
+            log_to_file(&format!("Using cached benchmark from {}", cached.created_at));
+            cached.cached = true;
+            return Ok(cached);
+        }
+    }
+
+    log_to_file(&format!("=== BENCHMARK ({}) ===", fingerprint));
+    let mut runs: Vec<BenchmarkRun> = Vec::new();
+    // Index into STEPS of the best sustained step so far; only better ones are tried
+    let mut best = STEPS.len();
+    for &(factory, hardware) in &encoders {
+        for (index, &step) in STEPS.iter().enumerate().take(best) {
+            let _ = app.emit_all(PROGRESS_EVENT, BenchmarkProgress {
+                encoder: factory.to_string(),
+                width: step.0,
+                height: step.1,
+                fps: step.2,
+                completed: runs.len(),
+            });
+            let outcome = run(source_id, factory, hardware, step);
+            let (sustained, failed) = (outcome.sustained, outcome.error.is_some());
+            runs.push(outcome);
+            if sustained {
+                best = index;
+                break;
+            }
+            // A broken encoder won't work at a lower step either
+            if failed {
+                break;
+            }
+        }
+        if best == 0 {
+            break;
+        }
+    }
+
+    let result = BenchmarkResult {
+        fingerprint,
+        source_id: cache_source.to_string(),
+        created_at: now_secs(),
+        cached: false,
+        recommended: recommend(&runs),
+        runs,
+    };
+    match result.recommended {
+        Some(ref r) => log_to_file(&format!(
+            "Recommended: {} {}x{}@{} {} kbps ({:?})",
+            r.encoder_factory, r.width, r.height, r.fps, r.bitrate, r.quality_mode
+        )),
+        None => log_to_file("WARNING: no encoder sustained any benchmark step"),
+    }
+    save_cache(app, &result);
+    Ok(result)
+}
@@ -0,0 +1,272 @@
+//! What this machine's GStreamer install can do
+//!
+//! `report` checks every element the streaming features rely on against the
+//! registry, so the frontend can disable what cannot work and support can
+//! tell which plugin is missing instead of guessing from a failed pipeline.
+
+use gstreamer as gst;
+use serde::{Deserialize, Serialize};
+
+use super::encoder::{self, CodecSupport, VideoCodec};
+use super::log_to_file;
+
+/// An element a feature needs: (factory, plugin, feature)
+type Requirement = (&'static str, &'static str, &'static str);
+
+const WEBRTC: &[Requirement] = &[
+    ("whipclientsink", "rswebrtc", "WHIP publishing"),
+    ("whepclientsrc", "rswebrtc", "WHEP playback"),
+    ("webrtcbin", "webrtc", "WHIP publishing and WHEP playback"),
+    ("rtph264pay", "rtp", "H.264 over WebRTC"),
+    ("opusenc", "opus", "Stream audio"),
+];
+
+#[cfg(target_os = "windows")]
+const CAPTURE: &[Requirement] = &[
+    ("d3d11screencapturesrc", "d3d11", "Screen and window capture"),
+    ("videotestsrc", "videotestsrc", "Test source and pause slate"),
+];
+#[cfg(target_os = "linux")]
+const CAPTURE: &[Requirement] = &[
+    ("ximagesrc", "ximagesrc", "X11 screen and window capture"),
+    ("pipewiresrc", "pipewire", "Wayland screen capture"),
+    ("videotestsrc", "videotestsrc", "Test source and pause slate"),
+];
+#[cfg(target_os = "macos")]
+const CAPTURE: &[Requirement] = &[
+    ("avfvideosrc", "applemedia", "Screen capture"),
+    ("videotestsrc", "videotestsrc", "Test source and pause slate"),
+];
+
+#[cfg(target_os = "windows")]
+const AUDIO: &[Requirement] = &[
+    ("wasapisrc", "wasapi", "Desktop audio and microphone"),
+    ("audiomixer", "audiomixer", "Microphone mixed with desktop audio"),
+];
+#[cfg(target_os = "linux")]
+const AUDIO: &[Requirement] = &[
+    ("pulsesrc", "pulseaudio", "Desktop audio and microphone"),
+    ("audiomixer", "audiomixer", "Microphone mixed with desktop audio"),
+];
+#[cfg(target_os = "macos")]
+const AUDIO: &[Requirement] = &[
+    ("osxaudiosrc", "osxaudio", "Desktop audio and microphone"),
+    ("audiomixer", "audiomixer", "Microphone mixed with desktop audio"),
+];
+
+const MUXERS: &[Requirement] = &[
+    ("mp4mux", "isomp4", "MP4 recordings and replay clips"),
+    ("matroskamux", "matroska", "Matroska recordings"),
+    ("flvmux", "flv", "RTMP outputs"),
+    ("rtmp2sink", "rtmp2", "RTMP outputs"),
+    ("mpegtsmux", "mpegtsmux", "SRT outputs"),
+    ("srtsink", "srt", "SRT outputs"),
+];
+
+const EFFECTS: &[Requirement] = &[
+    ("compositor", "compositor", "Overlays"),
+    ("textoverlay", "pango", "Text overlays and slate text"),
+    ("clockoverlay", "pango", "Clock overlays"),
+    ("pngdec", "png", "Image overlays and slates"),
+    ("jpegenc", "jpeg", "Playback frame events"),
+    ("autovideosink", "autodetect", "Playback window"),
+];
+
+/// Software encoders whose absence is worth reporting as a missing plugin
+const SOFTWARE_ENCODERS: &[Requirement] = &[
+    ("openh264enc", "openh264", "Software H.264 encoding"),
+    ("x264enc", "x264", "Software H.264 encoding"),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElementCheck {
+    pub element: String,
+    pub plugin: String,
+    pub feature: String,
+    pub available: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncoderCheck {
+    pub element: String,
+    pub codec: VideoCodec,
+    pub hardware: bool,
+    pub available: bool,
+}
+
+/// A plugin with elements that could not be loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MissingPlugin {
+    pub plugin: String,
+    pub elements: Vec<String>,
+    pub disables: Vec<String>, // Features that do not work without it
+}
+
+/// GStreamer availability info
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GStreamerInfo {
+    pub available: bool,
+    pub error: Option<String>, // Why GStreamer is unavailable
+    pub version: Option<String>,
+    pub plugin_path: Option<String>, // GST_PLUGIN_PATH
+    pub plugin_dir: Option<String>, // Where the core plugins were loaded from
+    pub has_whip: bool,
+    pub has_whep: bool,
+    pub has_d3d11: bool,
+    pub has_x264: bool,
+    pub has_openh264: bool,
+    pub video_codecs: Vec<CodecSupport>, // Codecs that can be encoded and sent
+    pub webrtc: Vec<ElementCheck>,
+    pub capture: Vec<ElementCheck>,
+    pub audio_sources: Vec<ElementCheck>,
+    pub muxers: Vec<ElementCheck>, // Including the RTMP / SRT sinks
+    pub effects: Vec<ElementCheck>,
+    pub encoders: Vec<EncoderCheck>, // Hardware first, per codec
+    pub missing: Vec<MissingPlugin>,
+}
+
+fn loadable(element: &str) -> bool {
+    gst::ElementFactory::find(element).is_some()
+}
+
+fn check(requirements: &[Requirement]) -> Vec<ElementCheck> {
+    requirements
+        .iter()
+        .map(|&(element, plugin, feature)| ElementCheck {
+            element: element.to_string(),
+            plugin: plugin.to_string(),
+            feature: feature.to_string(),
+            available: loadable(element),
+        })
+        .collect()
+}
+
+fn encoders() -> Vec<EncoderCheck> {
+    VideoCodec::ALL
+        .iter()
+        .flat_map(|&codec| {
+            let hardware = codec.hardware_encoders().iter().map(move |e| (codec, *e, true));
+            let software = codec.software_encoders().iter().map(move |e| (codec, *e, false));
+            hardware.chain(software)
+        })
+        .map(|(codec, element, hardware)| EncoderCheck {
+            element: element.to_string(),
+            codec,
+            hardware,
+            available: loadable(element),
+        })
+        .collect()
+}
+
+/// Unavailable elements grouped by plugin, with the features they disable
+fn missing_plugins(checks: &[&[ElementCheck]]) -> Vec<MissingPlugin> {
+    let mut missing: Vec<MissingPlugin> = Vec::new();
+    for check in checks.iter().flat_map(|c| c.iter()).filter(|c| !c.available) {
+        let index = match missing.iter().position(|m| m.plugin == check.plugin) {
+            Some(i) => i,
+            None => {
+                missing.push(MissingPlugin {
+                    plugin: check.plugin.clone(),
+                    elements: Vec::new(),
+                    disables: Vec::new(),
+                });
+                missing.len() - 1
+            }
+        };
+        let entry = &mut missing[index];
+        if !entry.elements.contains(&check.element) {
+            entry.elements.push(check.element.clone());
+        }
+        if !entry.disables.contains(&check.feature) {
+            entry.disables.push(check.feature.clone());
+        }
+    }
+    missing
+}
+
+/// Directory the core elements were loaded from
+fn plugin_dir() -> Option<String> {
+    gst::Registry::get()
+        .find_plugin("coreelements")
+        .and_then(|plugin| plugin.filename())
+        .and_then(|file| file.parent().map(|dir| dir.to_string_lossy().to_string()))
+}
+
+pub fn report() -> GStreamerInfo {
+    let plugin_path = std::env::var("GST_PLUGIN_PATH").ok();
+    if let Err(e) = gst::init() {
+        log_to_file(&format!("ERROR: GStreamer failed to initialize: {}", e));
+        return GStreamerInfo {
+            error: Some(format!("GStreamer failed to initialize: {}", e)),
+            plugin_path,
+            ..GStreamerInfo::default()
+        };
+    }
+
+    let (major, minor, micro, _nano) = gst::version();
+    let version = format!("{}.{}.{}", major, minor, micro);
+    log_to_file(&format!("GStreamer version: {}", version));
+    if let Some(ref path) = plugin_path {
+        log_to_file(&format!("GST_PLUGIN_PATH: {}", path));
+    }
+
+    // Without coreelements no pipeline can be built, whatever else loads
+    if !loadable("queue") {
+        log_to_file("ERROR: GStreamer core elements not found");
+        return GStreamerInfo {
+            error: Some("GStreamer core elements not found; check the plugin path".to_string()),
+            version: Some(version),
+            plugin_path,
+            ..GStreamerInfo::default()
+        };
+    }
+
+    let webrtc = check(WEBRTC);
+    let capture = check(CAPTURE);
+    let audio_sources = check(AUDIO);
+    let muxers = check(MUXERS);
+    let effects = check(EFFECTS);
+    let software_encoders = check(SOFTWARE_ENCODERS);
+    let missing = missing_plugins(&[&webrtc, &capture, &audio_sources, &muxers, &effects, &software_encoders]);
+
+    let video_codecs = encoder::encodable_codecs();
+    log_to_file(&format!(
+        "Encodable codecs: {}",
+        video_codecs.iter().map(|c| format!("{} ({})", c.codec.as_str(), c.encoder)).collect::<Vec<_>>().join(", ")
+    ));
+    for plugin in &missing {
+        log_to_file(&format!(
+            "Missing plugin {} ({}): disables {}",
+            plugin.plugin,
+            plugin.elements.join(", "),
+            plugin.disables.join(", ")
+        ));
+    }
+
+    // List all loaded plugins for debugging
+    let plugins: Vec<String> = gst::Registry::get().plugins().iter()
+        .map(|p| p.plugin_name().to_string())
+        .collect();
+    log_to_file(&format!("Loaded plugins ({}): {:?}", plugins.len(), plugins));
+
+    GStreamerInfo {
+        available: true,
+        error: None,
+        version: Some(version),
+        plugin_path,
+        plugin_dir: plugin_dir(),
+        has_whip: loadable("whipclientsink"),
+        has_whep: loadable("whepclientsrc"),
+        has_d3d11: loadable("d3d11screencapturesrc"),
+        has_x264: loadable("x264enc"),
+        has_openh264: loadable("openh264enc"),
+        video_codecs,
+        webrtc,
+        capture,
+        audio_sources,
+        muxers,
+        effects,
+        encoders: encoders(),
+        missing,
+    }
+}
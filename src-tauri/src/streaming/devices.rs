@@ -0,0 +1,179 @@
+//! Audio device discovery
+//!
+//! Backed by `gst::DeviceMonitor`, so ids are whatever the capture element
+//! (pulsesrc / wasapisrc / osxaudiosrc) takes as its `device` property and
+//! can be passed straight into `StreamConfig`. Hotplug is reported through
+//! the `devices-changed` event once the device list has been requested.
+
+use gstreamer as gst;
+use gst::prelude::{DeviceExt, DeviceMonitorExt, DeviceMonitorExtManual, ObjectExt};
+use serde::Serialize;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, Runtime};
+
+use super::log_to_file;
+
+/// Event name for device hotplug
+pub const DEVICES_EVENT: &str = "devices-changed";
+
+/// What a device can be used for
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioDeviceKind {
+    Source,  // Microphone / line in
+    Sink,    // Speakers / headphones (loopback-capturable on Windows)
+    Monitor, // Loopback of a sink (PulseAudio / PipeWire)
+}
+
+/// An audio device as seen by GStreamer
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioDevice {
+    pub id: String, // Value for the capture element's `device` property
+    pub name: String,
+    pub kind: AudioDeviceKind,
+    pub is_default: bool,
+    pub api: Option<String>, // "pulse", "wasapi", "wasapi2", "osxaudio"
+}
+
+/// Device properties naming the device for its element, per provider
+const ID_PROPERTIES: &[&str] = &[
+    "internal-name", // pulse
+    "device",        // wasapi / wasapi2
+    "device-id",     // osxaudio
+];
+
+fn device_id(device: &gst::Device) -> Option<String> {
+    ID_PROPERTIES.iter().find_map(|name| {
+        let pspec = device.find_property(name)?;
+        let value = device.property_value(name);
+        if pspec.value_type() == gst::glib::Type::STRING {
+            value.get::<Option<String>>().ok().flatten()
+        } else {
+            value.transform::<String>().ok()?.get::<String>().ok()
+        }
+    })
+}
+
+fn to_audio_device(device: &gst::Device) -> Option<AudioDevice> {
+    let props = device.properties();
+    let class = device.device_class();
+    let is_monitor = props
+        .as_ref()
+        .and_then(|p| p.get::<String>("device.class").ok())
+        .map(|c| c == "monitor")
+        .unwrap_or(false);
+    let kind = if class.contains("Audio/Sink") {
+        AudioDeviceKind::Sink
+    } else if is_monitor {
+        AudioDeviceKind::Monitor
+    } else if class.contains("Audio/Source") {
+        AudioDeviceKind::Source
+    } else {
+        return None;
+    };
+
+    let is_default = props
+        .as_ref()
+        .map(|p| {
+            p.get::<bool>("is-default").unwrap_or(false)
+                || p.get::<bool>("device.default").unwrap_or(false)
+        })
+        .unwrap_or(false);
+    let api = props.as_ref().and_then(|p| p.get::<String>("device.api").ok());
+
+    Some(AudioDevice {
+        id: device_id(device).unwrap_or_else(|| device.display_name().to_string()),
+        name: device.display_name().to_string(),
+        kind,
+        is_default,
+        api,
+    })
+}
+
+fn new_monitor() -> gst::DeviceMonitor {
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter(Some("Audio/Source"), None);
+    monitor.add_filter(Some("Audio/Sink"), None);
+    monitor
+}
+
+fn collect(monitor: &gst::DeviceMonitor) -> Vec<AudioDevice> {
+    monitor.devices().iter().filter_map(to_audio_device).collect()
+}
+
+/// Running hotplug monitor (managed Tauri state)
+#[derive(Default)]
+pub struct DeviceWatcher {
+    monitor: Mutex<Option<gst::DeviceMonitor>>,
+}
+
+impl DeviceWatcher {
+    /// Start the monitor and its event thread if not running yet
+    fn ensure_started<R: Runtime>(&self, app: &AppHandle<R>) -> Result<gst::DeviceMonitor, String> {
+        let mut guard = self.monitor.lock().unwrap();
+        if let Some(ref monitor) = *guard {
+            return Ok(monitor.clone());
+        }
+
+        let monitor = new_monitor();
+        let bus = monitor.bus();
+        monitor
+            .start()
+            .map_err(|e| format!("Failed to start device monitor: {}", e))?;
+
+        let app = app.clone();
+        let weak_monitor = monitor.downgrade();
+        std::thread::spawn(move || {
+            log_to_file("Audio device monitor started");
+            loop {
+                let msg = bus.timed_pop_filtered(
+                    gst::ClockTime::from_seconds(1),
+                    &[
+                        gst::MessageType::DeviceAdded,
+                        gst::MessageType::DeviceRemoved,
+                        gst::MessageType::DeviceChanged,
+                    ],
+                );
+                let monitor = match weak_monitor.upgrade() {
+                    Some(m) => m,
+                    None => break,
+                };
+                if msg.is_some() {
+                    // Drain the burst a single replug produces
+                    while bus.pop().is_some() {}
+                    let devices = collect(&monitor);
+                    log_to_file(&format!("Audio devices changed ({} devices)", devices.len()));
+                    let _ = app.emit_all(DEVICES_EVENT, devices);
+                }
+            }
+        });
+
+        *guard = Some(monitor.clone());
+        Ok(monitor)
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        if let Ok(mut guard) = self.monitor.lock() {
+            if let Some(monitor) = guard.take() {
+                monitor.stop();
+            }
+        }
+    }
+}
+
+/// List audio sources, sinks and monitor sources. The first call also
+/// starts hotplug monitoring (`devices-changed` events).
+pub fn list<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<AudioDevice>, String> {
+    let watcher = app.state::<DeviceWatcher>();
+    let devices = match watcher.ensure_started(app) {
+        Ok(monitor) => collect(&monitor),
+        Err(e) => {
+            // No hotplug, but a one-off probe still works
+            log_to_file(&format!("WARNING: {}", e));
+            collect(&new_monitor())
+        }
+    };
+    Ok(devices)
+}
@@ -0,0 +1,215 @@
+//! Pipeline diagnostics for support reports
+//!
+//! `dump` captures the GStreamer DOT graph of a running pipeline (negotiated
+//! caps on every pad and element states) together with a per-element list of
+//! state, latency and pad caps. Property values stay out of the graph: sink
+//! URIs and credentials would end up in support reports. `render_svg`
+//! turns the DOT text into SVG with Graphviz when it is installed.
+
+use gstreamer as gst;
+use gst::prelude::{ElementExt, GstBinExt, GstObjectExt, PadExt, PipelineExt};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use super::log_to_file;
+
+/// What `render_stream_pipeline` returns
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    Dot,
+    Svg,
+}
+
+impl Default for GraphFormat {
+    fn default() -> Self {
+        GraphFormat::Svg
+    }
+}
+
+/// Latency reported by an element's latency query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyInfo {
+    pub live: bool,
+    pub min_ms: f64,
+    pub max_ms: Option<f64>, // None = unlimited
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PadInfo {
+    pub name: String,
+    pub direction: String, // "src" / "sink"
+    pub caps: Option<String>, // Negotiated caps, None if not negotiated
+    pub peer: Option<String>, // "element:pad"
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElementInfo {
+    pub path: String, // e.g. "/GstPipeline:pipeline0/GstBin:recorder/GstQueue:queue3"
+    pub factory: Option<String>,
+    pub state: String,
+    pub pending: Option<String>, // Set while a state change is in progress
+    pub latency: Option<LatencyInfo>,
+    pub pads: Vec<PadInfo>,
+}
+
+/// Snapshot of a running pipeline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineDump {
+    pub session_id: String,
+    pub state: String,
+    pub latency_ms: Option<f64>, // Latency the pipeline configured
+    pub dot: String,
+    pub elements: Vec<ElementInfo>,
+}
+
+fn state_name(state: gst::State) -> String {
+    format!("{:?}", state).to_lowercase()
+}
+
+fn millis(time: gst::ClockTime) -> f64 {
+    time.nseconds() as f64 / 1_000_000.0
+}
+
+fn latency(element: &gst::Element) -> Option<LatencyInfo> {
+    let mut query = gst::query::Latency::new();
+    if !element.query(query.query_mut()) {
+        return None;
+    }
+    let (live, min, max) = query.result();
+    Some(LatencyInfo {
+        live,
+        min_ms: millis(min),
+        max_ms: max.map(millis),
+    })
+}
+
+fn pad_info(pad: &gst::Pad) -> PadInfo {
+    PadInfo {
+        name: pad.name().to_string(),
+        direction: match pad.direction() {
+            gst::PadDirection::Src => "src".to_string(),
+            gst::PadDirection::Sink => "sink".to_string(),
+            _ => "unknown".to_string(),
+        },
+        caps: pad.current_caps().map(|caps| caps.to_string()),
+        peer: pad.peer().map(|peer| {
+            let parent = peer.parent().map(|p| p.name().to_string()).unwrap_or_default();
+            format!("{}:{}", parent, peer.name())
+        }),
+    }
+}
+
+fn element_info(element: &gst::Element) -> ElementInfo {
+    let pending = element.pending_state();
+    ElementInfo {
+        path: element.path_string().to_string(),
+        factory: element.factory().map(|f| f.name().to_string()),
+        state: state_name(element.current_state()),
+        pending: Some(pending).filter(|s| *s != gst::State::VoidPending).map(state_name),
+        latency: latency(element),
+        pads: element.pads().iter().map(pad_info).collect(),
+    }
+}
+
+/// DOT graph with caps and states. Properties are left out, they hold the
+/// RTMP stream key, SRT passphrase, TURN credentials and WHIP token.
+pub fn dot(pipeline: &gst::Pipeline) -> String {
+    let details = gst::DebugGraphDetails::ALL
+        - gst::DebugGraphDetails::NON_DEFAULT_PARAMS
+        - gst::DebugGraphDetails::PARAMS;
+    gst::debug_bin_to_dot_data(pipeline, details).to_string()
+}
+
+/// DOT graph and per-element diagnostics of `pipeline`
+pub fn dump(session_id: &str, pipeline: &gst::Pipeline) -> PipelineDump {
+    let dot = dot(pipeline);
+    let elements: Vec<ElementInfo> = pipeline
+        .iterate_recurse()
+        .into_iter()
+        .flatten()
+        .map(|element| element_info(&element))
+        .collect();
+    log_to_file(&format!("Pipeline dump: {} elements, {} bytes of DOT", elements.len(), dot.len()));
+
+    PipelineDump {
+        session_id: session_id.to_string(),
+        state: state_name(pipeline.current_state()),
+        latency_ms: pipeline.latency().map(millis),
+        dot,
+        elements,
+    }
+}
+
+/// Render DOT text to SVG with Graphviz's `dot`
+pub fn render_svg(dot: &str) -> Result<String, String> {
+    let mut command = Command::new("dot");
+    command
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    // Don't flash a console window
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(0x0800_0000); // CREATE_NO_WINDOW
+    }
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Graphviz 'dot' is needed to render SVG ({}); request the DOT text instead", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(dot.as_bytes())
+            .map_err(|e| format!("Failed to pass the graph to dot: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("dot failed: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("dot failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("dot produced invalid SVG: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::pipeline::set_property_from_str;
+    use gst::prelude::GstBinExtManual;
+
+    const SECRET: &str = "s3cr3t-key";
+
+    /// `factory` with `property` set to `value`, if the plugin is installed
+    fn element(factory: &str, property: &str, value: &str) -> Option<gst::Element> {
+        let element = gst::ElementFactory::make(factory).build().ok()?;
+        set_property_from_str(&element, property, value).ok()?;
+        Some(element)
+    }
+
+    #[test]
+    fn dot_leaves_out_secrets() {
+        gst::init().unwrap();
+        let pipeline = gst::Pipeline::new();
+        let candidates = [
+            ("filesrc", "location", format!("/tmp/{}", SECRET)),
+            ("rtmp2sink", "location", format!("rtmp://live.example.com/app/{}", SECRET)),
+            ("srtsink", "uri", format!("srt://example.com:9000?passphrase={}", SECRET)),
+            ("webrtcbin", "turn-server", format!("turn://user:{}@turn.example.com", SECRET)),
+            ("whipsink", "auth-token", SECRET.to_string()),
+        ];
+        let elements: Vec<gst::Element> = candidates
+            .iter()
+            .filter_map(|(factory, property, value)| element(factory, property, value))
+            .collect();
+        assert!(!elements.is_empty());
+        pipeline.add_many(&elements).unwrap();
+
+        let dot = dot(&pipeline);
+        assert!(dot.contains("filesrc"), "{}", dot);
+        assert!(!dot.contains(SECRET), "{}", dot);
+    }
+}
@@ -0,0 +1,523 @@
+//! Video codec and encoder selection for whipclientsink
+//!
+//! whipclientsink creates its encoder internally and picks the highest-ranked
+//! factory that can produce the negotiated `video-caps` (set from the
+//! configured `VideoCodec`). To honour the user's
+//! choice we temporarily re-rank the encoder factories while a stream runs,
+//! and apply the preset from the `encoder-setup` signal.
+
+use gstreamer as gst;
+use gst::glib;
+use gst::prelude::{ElementExt, GstObjectExt, ObjectExt, PluginFeatureExtManual, ToValue};
+use serde::{Deserialize, Serialize};
+
+use super::pipeline::{set_property_from_str, ElementSpec};
+use super::{log_to_file, QualityMode};
+
+/// Encoder requested by the frontend
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncoderChoice {
+    Auto,     // Best available: hardware -> openh264 -> x264
+    Nvenc,    // NVIDIA
+    Qsv,      // Intel Quick Sync
+    Amf,      // AMD
+    Mf,       // Windows Media Foundation
+    Openh264, // Cisco OpenH264 (software)
+    X264,     // x264 (software)
+}
+
+impl Default for EncoderChoice {
+    fn default() -> Self {
+        EncoderChoice::Auto
+    }
+}
+
+/// Video codec negotiated with the WHIP endpoint
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    H264,
+    Vp8,
+    Vp9,
+    Av1,
+}
+
+impl Default for VideoCodec {
+    fn default() -> Self {
+        VideoCodec::H264
+    }
+}
+
+impl VideoCodec {
+    pub const ALL: [VideoCodec; 4] = [VideoCodec::H264, VideoCodec::Vp8, VideoCodec::Vp9, VideoCodec::Av1];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264",
+            VideoCodec::Vp8 => "vp8",
+            VideoCodec::Vp9 => "vp9",
+            VideoCodec::Av1 => "av1",
+        }
+    }
+
+    fn media_type(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "video/x-h264",
+            VideoCodec::Vp8 => "video/x-vp8",
+            VideoCodec::Vp9 => "video/x-vp9",
+            VideoCodec::Av1 => "video/x-av1",
+        }
+    }
+
+    /// `video-caps` for whipclientsink
+    pub fn whip_caps(self) -> String {
+        match self {
+            // Baseline is what every WebRTC receiver can decode
+            VideoCodec::H264 => "video/x-h264,profile=constrained-baseline".to_string(),
+            _ => self.media_type().to_string(),
+        }
+    }
+
+    /// Parser for locally encoded streams (recordings), if the codec has one
+    pub fn parser(self) -> Option<&'static str> {
+        match self {
+            VideoCodec::H264 => Some("h264parse"),
+            VideoCodec::Vp8 => None,
+            VideoCodec::Vp9 => Some("vp9parse"),
+            VideoCodec::Av1 => Some("av1parse"),
+        }
+    }
+
+    /// Whether mp4mux can store this codec (VP8 needs Matroska)
+    pub fn fits_mp4(self) -> bool {
+        self != VideoCodec::Vp8
+    }
+
+    /// RTP payloader webrtcsink needs for this codec
+    fn payloader(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "rtph264pay",
+            VideoCodec::Vp8 => "rtpvp8pay",
+            VideoCodec::Vp9 => "rtpvp9pay",
+            VideoCodec::Av1 => "rtpav1pay",
+        }
+    }
+
+    /// Hardware encoders, in order of preference for `Auto`
+    pub fn hardware_encoders(self) -> &'static [&'static str] {
+        match self {
+            VideoCodec::H264 => &[
+                "nvd3d11h264enc",
+                "nvh264enc",
+                "qsvh264enc",
+                "amfh264enc",
+                "mfh264enc",
+                "vah264enc",
+                "vaapih264enc",
+                "vtenc_h264_hw",
+            ],
+            VideoCodec::Vp8 => &["vaapivp8enc"],
+            VideoCodec::Vp9 => &["qsvvp9enc", "mfvp9enc", "vavp9enc", "vaapivp9enc"],
+            VideoCodec::Av1 => &["nvd3d11av1enc", "nvav1enc", "qsvav1enc", "amfav1enc", "vaav1enc"],
+        }
+    }
+
+    /// Software fallbacks after hardware, in order
+    pub fn software_encoders(self) -> &'static [&'static str] {
+        match self {
+            VideoCodec::H264 => &["openh264enc", "x264enc"],
+            VideoCodec::Vp8 => &["vp8enc"],
+            VideoCodec::Vp9 => &["vp9enc"],
+            VideoCodec::Av1 => &["svtav1enc", "av1enc", "rav1enc"],
+        }
+    }
+}
+
+/// Encoding support for one codec, as reported by `check_gstreamer`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodecSupport {
+    pub codec: VideoCodec,
+    pub encoder: String, // What `auto` resolves to
+    pub hardware: bool,
+}
+
+/// Codecs that can actually be encoded and payloaded here
+pub fn encodable_codecs() -> Vec<CodecSupport> {
+    VideoCodec::ALL
+        .iter()
+        .filter(|codec| gst::ElementFactory::find(codec.payloader()).is_some())
+        .filter_map(|&codec| {
+            let encoder = EncoderChoice::Auto
+                .candidates(codec)
+                .into_iter()
+                .find(|name| gst::ElementFactory::find(name).is_some())?;
+            Some(CodecSupport {
+                codec,
+                encoder: encoder.to_string(),
+                hardware: codec.hardware_encoders().contains(&encoder),
+            })
+        })
+        .collect()
+}
+
+impl EncoderChoice {
+    /// Name as sent by the frontend
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EncoderChoice::Auto => "auto",
+            EncoderChoice::Nvenc => "nvenc",
+            EncoderChoice::Qsv => "qsv",
+            EncoderChoice::Amf => "amf",
+            EncoderChoice::Mf => "mf",
+            EncoderChoice::Openh264 => "openh264",
+            EncoderChoice::X264 => "x264",
+        }
+    }
+
+    /// Candidate factory names for this choice and codec, in order of preference
+    pub fn candidates(self, codec: VideoCodec) -> Vec<&'static str> {
+        use VideoCodec::*;
+        match (self, codec) {
+            (EncoderChoice::Auto, _) => codec
+                .hardware_encoders()
+                .iter()
+                .chain(codec.software_encoders().iter())
+                .copied()
+                .collect(),
+            (EncoderChoice::Nvenc, H264) => vec!["nvd3d11h264enc", "nvh264enc"],
+            (EncoderChoice::Nvenc, Av1) => vec!["nvd3d11av1enc", "nvav1enc"],
+            (EncoderChoice::Qsv, H264) => vec!["qsvh264enc"],
+            (EncoderChoice::Qsv, Vp9) => vec!["qsvvp9enc"],
+            (EncoderChoice::Qsv, Av1) => vec!["qsvav1enc"],
+            (EncoderChoice::Amf, H264) => vec!["amfh264enc"],
+            (EncoderChoice::Amf, Av1) => vec!["amfav1enc"],
+            (EncoderChoice::Mf, H264) => vec!["mfh264enc"],
+            (EncoderChoice::Mf, Vp9) => vec!["mfvp9enc"],
+            (EncoderChoice::Openh264, H264) => vec!["openh264enc"],
+            (EncoderChoice::X264, H264) => vec!["x264enc"],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Resolve the encoder factory to use, or a clear error if it is not installed
+pub fn select_encoder(choice: EncoderChoice, codec: VideoCodec) -> Result<&'static str, String> {
+    let candidates = choice.candidates(codec);
+    if candidates.is_empty() {
+        return Err(format!(
+            "Encoder '{}' cannot encode {}",
+            choice.as_str(),
+            codec.as_str().to_uppercase()
+        ));
+    }
+    for name in &candidates {
+        if gst::ElementFactory::find(name).is_some() {
+            log_to_file(&format!("Encoder {} resolved to {}", choice.as_str(), name));
+            return Ok(name);
+        }
+        log_to_file(&format!("Encoder {} not available", name));
+    }
+
+    match choice {
+        EncoderChoice::Auto => Err(format!(
+            "No {} encoder available (tried: {})",
+            codec.as_str().to_uppercase(),
+            candidates.join(", ")
+        )),
+        _ => Err(format!(
+            "Encoder '{}' was requested but its GStreamer element is missing (tried: {})",
+            choice.as_str(),
+            candidates.join(", ")
+        )),
+    }
+}
+
+/// Makes `factory` the only encoder for its codec webrtcsink will consider.
+/// Original ranks are restored when dropped.
+pub struct EncoderPin {
+    pub factory: &'static str,
+    saved_ranks: Vec<(gst::ElementFactory, gst::Rank)>,
+}
+
+impl EncoderPin {
+    pub fn new(factory: &'static str, codec: VideoCodec) -> Self {
+        let codec_caps = gst::Caps::new_empty_simple(codec.media_type());
+        let encoders = gst::ElementFactory::factories_with_type(
+            gst::ElementFactoryType::VIDEO_ENCODER,
+            gst::Rank::NONE,
+        );
+
+        let mut saved_ranks = Vec::new();
+        for f in encoders.iter() {
+            if !f.can_src_any_caps(&codec_caps) {
+                continue;
+            }
+            saved_ranks.push((f.clone(), f.rank()));
+            if f.name().as_str() == factory {
+                f.set_rank(gst::Rank::PRIMARY);
+            } else {
+                f.set_rank(gst::Rank::NONE);
+            }
+        }
+
+        Self { factory, saved_ranks }
+    }
+}
+
+impl Drop for EncoderPin {
+    fn drop(&mut self) {
+        for (factory, rank) in self.saved_ranks.drain(..) {
+            factory.set_rank(rank);
+        }
+    }
+}
+
+/// Property that controls the speed/quality trade-off for an encoder, with
+/// values for performance, balanced and quality
+fn preset_property(factory: &str) -> Option<(&'static str, [&'static str; 3])> {
+    match factory {
+        "x264enc" => Some(("speed-preset", ["ultrafast", "veryfast", "medium"])),
+        "nvd3d11h264enc" | "nvh264enc" => Some(("preset", ["p1", "p4", "p7"])),
+        "qsvh264enc" => Some(("target-usage", ["7", "4", "1"])),
+        "amfh264enc" => Some(("preset", ["speed", "balanced", "quality"])),
+        "mfh264enc" => Some(("quality-vs-speed", ["0", "50", "100"])),
+        "openh264enc" => Some(("complexity", ["low", "medium", "high"])),
+        "vah264enc" | "vaapih264enc" => Some(("target-usage", ["7", "4", "1"])),
+        "nvd3d11av1enc" | "nvav1enc" => Some(("preset", ["p1", "p4", "p7"])),
+        "qsvvp9enc" | "qsvav1enc" => Some(("target-usage", ["7", "4", "1"])),
+        "amfav1enc" => Some(("preset", ["speed", "balanced", "quality"])),
+        "mfvp9enc" => Some(("quality-vs-speed", ["0", "50", "100"])),
+        "vavp9enc" | "vaav1enc" | "vaapivp8enc" | "vaapivp9enc" => Some(("target-usage", ["7", "4", "1"])),
+        // libvpx / libaom: higher cpu-used is faster
+        "vp8enc" => Some(("cpu-used", ["8", "5", "2"])),
+        "vp9enc" => Some(("cpu-used", ["8", "6", "4"])),
+        "av1enc" => Some(("cpu-used", ["9", "8", "6"])),
+        "svtav1enc" => Some(("preset", ["12", "10", "8"])),
+        "rav1enc" => Some(("speed-preset", ["10", "9", "7"])),
+        _ => None,
+    }
+}
+
+/// Settings every live encode needs, whatever the quality mode
+fn realtime_properties(factory: &str) -> &'static [(&'static str, &'static str)] {
+    match factory {
+        "vp8enc" => &[("deadline", "1")],
+        "vp9enc" => &[("deadline", "1"), ("row-mt", "true")],
+        "av1enc" => &[("usage-profile", "realtime"), ("row-mt", "true")],
+        _ => &[],
+    }
+}
+
+/// x264 speed presets, fastest first
+const X264_PRESETS: &[&str] = &[
+    "ultrafast", "superfast", "veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow", "placebo",
+];
+
+/// Translate a preset name into the value for `factory`'s preset property.
+///
+/// Accepts the encoder's own value (e.g. "p4"), x264 speed presets, and the
+/// generic "performance" / "balanced" / "quality" names. With no preset, the
+/// value is derived from the quality mode.
+fn resolve_preset(factory: &str, preset: Option<&str>, mode: &QualityMode) -> Option<(&'static str, String)> {
+    let (property, tiers) = preset_property(factory)?;
+    let tier = |i: usize| Some((property, tiers[i].to_string()));
+
+    match preset.map(|p| p.trim().to_lowercase()) {
+        Some(p) if p == "performance" => tier(0),
+        Some(p) if p == "balanced" => tier(1),
+        Some(p) if p == "quality" => tier(2),
+        // x264-style names are mapped onto the other encoders' scales
+        Some(p) if factory != "x264enc" && X264_PRESETS.contains(&p.as_str()) => match p.as_str() {
+            "ultrafast" | "superfast" | "veryfast" | "faster" | "fast" => tier(0),
+            "slow" | "slower" | "veryslow" | "placebo" => tier(2),
+            _ => tier(1),
+        },
+        Some(p) if !p.is_empty() => Some((property, p)),
+        _ => match mode {
+            QualityMode::Performance => tier(0),
+            QualityMode::Balanced => tier(1),
+            QualityMode::Quality | QualityMode::Lossless => tier(2),
+        },
+    }
+}
+
+/// Check that `value` is acceptable for the encoder's preset property by
+/// probing a throwaway instance, so a bad preset fails start_stream instead of
+/// silently being ignored when the encoder is created later.
+pub fn validate_preset(factory: &'static str, preset: Option<&str>, mode: &QualityMode) -> Result<(), String> {
+    let (property, value) = match resolve_preset(factory, preset, mode) {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+
+    let probe = gst::ElementFactory::make(factory)
+        .build()
+        .map_err(|e| format!("Failed to create {}: {}", factory, e))?;
+    let pspec = match probe.find_property(property) {
+        Some(p) => p,
+        None => {
+            log_to_file(&format!("{} has no '{}' property, preset ignored", factory, property));
+            return Ok(());
+        }
+    };
+
+    let valid = if let Some(enum_spec) = pspec.downcast_ref::<glib::ParamSpecEnum>() {
+        enum_spec.enum_class().value_by_nick(&value).is_some()
+    } else {
+        value.parse::<i64>().is_ok()
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid preset '{}' for encoder {} ({})", value, factory, property))
+    }
+}
+
+/// Bitrate property of `factory`, and whether it takes bits (not kbit) per second
+fn bitrate_property(factory: &str) -> Option<(&'static str, bool)> {
+    match factory {
+        "openh264enc" | "rav1enc" => Some(("bitrate", true)),
+        "vp8enc" | "vp9enc" => Some(("target-bitrate", true)),
+        "svtav1enc" | "av1enc" => Some(("target-bitrate", false)),
+        "x264enc" | "nvd3d11h264enc" | "nvh264enc" | "qsvh264enc" | "amfh264enc" | "mfh264enc" | "vah264enc"
+        | "vaapih264enc" | "vtenc_h264_hw" | "nvd3d11av1enc" | "nvav1enc" | "qsvvp9enc" | "qsvav1enc"
+        | "amfav1enc" | "mfvp9enc" | "vavp9enc" | "vaav1enc" | "vaapivp8enc" | "vaapivp9enc" => Some(("bitrate", false)),
+        _ => None,
+    }
+}
+
+/// Keyframe interval property of `factory`
+fn keyframe_property(factory: &str) -> Option<&'static str> {
+    match factory {
+        "x264enc" | "vah264enc" | "vavp9enc" | "vaav1enc" => Some("key-int-max"),
+        "vaapih264enc" | "vaapivp8enc" | "vaapivp9enc" => Some("keyframe-period"),
+        "vtenc_h264_hw" => Some("max-keyframe-interval"),
+        "vp8enc" | "vp9enc" | "av1enc" => Some("keyframe-max-dist"),
+        "svtav1enc" => Some("intra-period-length"),
+        "rav1enc" => Some("max-key-frame-interval"),
+        "nvd3d11h264enc" | "nvh264enc" | "qsvh264enc" | "amfh264enc" | "mfh264enc" | "openh264enc"
+        | "nvd3d11av1enc" | "nvav1enc" | "qsvvp9enc" | "qsvav1enc" | "amfav1enc" | "mfvp9enc" => Some("gop-size"),
+        _ => None,
+    }
+}
+
+/// Encoder for branches outside whipclientsink (local recordings).
+/// Uses a fixed bitrate and a keyframe every `keyframe_interval` frames so
+/// fragmented files can be cut and played from any fragment.
+pub fn standalone_encoder(
+    factory: &'static str,
+    bitrate_kbps: u32,
+    keyframe_interval: u32,
+    preset: Option<&str>,
+    mode: &QualityMode,
+) -> ElementSpec {
+    let mut spec = ElementSpec::new(factory);
+    if let Some((property, in_bits)) = bitrate_property(factory) {
+        let bitrate = if in_bits { bitrate_kbps as i64 * 1000 } else { bitrate_kbps as i64 };
+        spec = spec.int(property, bitrate);
+    }
+    if let Some(property) = keyframe_property(factory) {
+        spec = spec.int(property, keyframe_interval.max(1) as i64);
+    }
+    if let Some((property, value)) = resolve_preset(factory, preset, mode) {
+        spec = spec.enum_nick(property, &value);
+    }
+    for (property, value) in realtime_properties(factory) {
+        spec = spec.enum_nick(property, value);
+    }
+    spec
+}
+
+/// Set an encoder property, logging instead of panicking when this build of
+/// the encoder lacks it or rejects the value
+fn set_encoder_property(encoder: &gst::Element, factory: &str, property: &str, value: &str) -> bool {
+    match set_property_from_str(encoder, property, value) {
+        Ok(()) => true,
+        Err(e) => {
+            log_to_file(&format!("WARNING: {}: {}, skipped", factory, e));
+            false
+        }
+    }
+}
+
+/// Change a running encoder's bitrate. Returns false if the bitrate
+/// property of `factory` is unknown or rejected the value.
+pub fn set_bitrate(encoder: &gst::Element, factory: &str, bitrate_kbps: u32) -> bool {
+    match bitrate_property(factory) {
+        Some((property, in_bits)) => {
+            let bitrate = if in_bits { bitrate_kbps as u64 * 1000 } else { bitrate_kbps as u64 };
+            set_encoder_property(encoder, factory, property, &bitrate.to_string())
+        }
+        None => false,
+    }
+}
+
+/// Keep `encoder`'s bitrate at or below the cap returned by `max_bps` (bits
+/// per second), also when congestion control raises it later. The cap is
+/// read on every change, so it can be moved while the encoder runs.
+/// Returns false if the bitrate property of `factory` is unknown or
+/// rejected the cap.
+pub fn cap_bitrate(
+    encoder: &gst::Element,
+    factory: &str,
+    max_bps: impl Fn() -> Option<u32> + Send + Sync + 'static,
+) -> bool {
+    let (property, in_bits) = match bitrate_property(factory) {
+        Some(p) => p,
+        None => return false,
+    };
+    let factory = factory.to_string();
+    let clamp = move |encoder: &gst::Element| {
+        let cap = match max_bps() {
+            Some(bps) if in_bits => bps as u64,
+            Some(bps) => bps as u64 / 1000,
+            None => return true,
+        };
+        let current = encoder
+            .property_value(property)
+            .transform::<u64>()
+            .ok()
+            .and_then(|v| v.get::<u64>().ok());
+        match current {
+            Some(c) if c > cap => set_encoder_property(encoder, &factory, property, &cap.to_string()),
+            _ => true,
+        }
+    };
+    if encoder.find_property(property).is_none() || !clamp(encoder) {
+        return false;
+    }
+    encoder.connect_notify(Some(property), move |encoder, _| {
+        clamp(encoder);
+    });
+    true
+}
+
+/// Apply the preset to encoders created by whipclientsink.
+/// Connected after the default handler so our preset wins over its defaults.
+pub fn connect_encoder_setup(whip: &gst::Element, factory: &'static str, preset: Option<String>, mode: QualityMode) {
+    whip.connect("encoder-setup", true, move |args| {
+        let encoder = args.get(3).and_then(|v| v.get::<gst::Element>().ok());
+        if let Some(encoder) = encoder {
+            let encoder_factory = encoder.factory().map(|f| f.name().to_string()).unwrap_or_default();
+            if encoder_factory == factory {
+                for (property, value) in realtime_properties(factory) {
+                    set_encoder_property(&encoder, factory, property, value);
+                }
+                if let Some((property, value)) = resolve_preset(factory, preset.as_deref(), &mode) {
+                    if set_encoder_property(&encoder, factory, property, &value) {
+                        log_to_file(&format!("Applied preset {}={} to {}", property, value, factory));
+                    }
+                }
+            } else {
+                log_to_file(&format!(
+                    "WARNING: whipclientsink created {} instead of {}",
+                    encoder_factory, factory
+                ));
+            }
+        }
+        // Not fully configured: keep whipclientsink's low-latency defaults
+        Some(false.to_value())
+    });
+}
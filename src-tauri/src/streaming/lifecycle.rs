@@ -0,0 +1,396 @@
+//! Stream lifecycle state machine
+//!
+//! Every transition, error and warning is pushed to the frontend as a
+//! `stream-state` event so the UI doesn't have to poll `get_stream_status`.
+//! WHEP playback sessions use the same states under `playback-state`.
+
+use gstreamer as gst;
+use gst::prelude::{Cast, ElementExt, GstBinExt, ObjectExt};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, Runtime};
+
+use super::{log_to_file, SharedState};
+
+/// Event name for lifecycle updates
+pub const STATE_EVENT: &str = "stream-state";
+
+/// Event name for playback lifecycle updates
+pub const PLAYBACK_STATE_EVENT: &str = "playback-state";
+
+/// Lifecycle of a native stream
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamState {
+    Idle,         // No pipeline
+    Starting,     // Building the pipeline
+    Connecting,   // Pipeline going to PLAYING, WHIP/WHEP/ICE negotiation in progress
+    Live,         // WHIP peer connection up, or pipeline PLAYING without WHIP (playback: first frame decoded)
+    Reconnecting, // WHIP side is being rebuilt after a failure
+    Stopping,     // stop_stream in progress
+    Failed,       // Pipeline hit a fatal error
+}
+
+impl Default for StreamState {
+    fn default() -> Self {
+        StreamState::Idle
+    }
+}
+
+impl StreamState {
+    /// Whether the state machine allows moving from `self` to `next`
+    pub fn can_transition_to(self, next: StreamState) -> bool {
+        use StreamState::*;
+        match (self, next) {
+            (Idle, Starting) => true,
+            (Starting, Connecting) | (Starting, Failed) | (Starting, Idle) => true,
+            (Connecting, Live) | (Connecting, Reconnecting) | (Connecting, Failed) => true,
+            (Live, Reconnecting) | (Live, Failed) => true,
+            (Reconnecting, Connecting) | (Reconnecting, Live) | (Reconnecting, Failed) => true,
+            (Failed, Starting) => true,
+            // A stream can be stopped from any active or failed state
+            (Connecting, Stopping) | (Live, Stopping) | (Reconnecting, Stopping) | (Failed, Stopping) => true,
+            (Stopping, Idle) | (Failed, Idle) => true,
+            // EOS ends the stream without a stop request
+            (Connecting, Idle) | (Live, Idle) | (Reconnecting, Idle) => true,
+            _ => false,
+        }
+    }
+}
+
+/// What a `stream-state` event reports
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StateEventKind {
+    Transition,
+    Error,
+    Warning,
+    Reconnect, // A WHIP reconnect attempt is scheduled
+}
+
+/// Coarse classification of pipeline errors for the UI
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    Capture,     // Screen/window/audio capture failed
+    Encoder,     // Encoder missing or failed
+    Negotiation, // Caps negotiation failed
+    Auth,        // Ingress rejected the bearer token
+    Ingress,     // Ingress returned a server error
+    Ice,         // ICE/DTLS connectivity failed
+    Network,     // Connection refused, timeout, DNS
+    Recording,   // Local recording failed (disk full, muxer error)
+    Unknown,
+}
+
+/// `stream-state` event payload
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamStateEvent {
+    pub session_id: String,
+    pub kind: StateEventKind,
+    pub state: StreamState,
+    pub previous: StreamState,
+    pub source: Option<String>, // Element that posted the message
+    pub error_kind: Option<ErrorKind>,
+    pub message: Option<String>,
+    pub attempt: Option<u32>, // Reconnect attempt number (1-based)
+}
+
+/// Capture source element factories
+const CAPTURE_ELEMENTS: &[&str] = &[
+    "d3d11screencapturesrc",
+    "ximagesrc",
+    "pipewiresrc",
+    "avfvideosrc",
+    "wasapisrc",
+    "pulsesrc",
+    "osxaudiosrc",
+];
+
+/// Peer connection, ICE and DTLS element factories
+const ICE_ELEMENTS: &[&str] = &["webrtcbin", "nicesrc", "nicesink", "dtlsenc", "dtlsdec", "dtlssrtpenc", "dtlssrtpdec"];
+
+/// Element factories talking to a remote server
+const NETWORK_ELEMENTS: &[&str] = &["whipclientsink", "whepclientsrc", "rtmp2sink", "srtsink"];
+
+/// Classify a pipeline error from its GError domain and code and the
+/// factory of the element that posted it
+pub fn classify_error(factory: Option<&str>, error: &gst::glib::Error) -> ErrorKind {
+    let factory = factory.unwrap_or("");
+    let network = NETWORK_ELEMENTS.contains(&factory);
+
+    if CAPTURE_ELEMENTS.contains(&factory) {
+        return ErrorKind::Capture;
+    }
+    if ICE_ELEMENTS.contains(&factory) {
+        return ErrorKind::Ice;
+    }
+    if let Some(code) = error.kind::<gst::ResourceError>() {
+        match code {
+            gst::ResourceError::NotAuthorized => return ErrorKind::Auth,
+            gst::ResourceError::NoSpaceLeft => return ErrorKind::Recording,
+            _ if network => return ErrorKind::Network,
+            _ => {}
+        }
+    }
+    if let Some(code) = error.kind::<gst::StreamError>() {
+        match code {
+            gst::StreamError::Encode | gst::StreamError::CodecNotFound => return ErrorKind::Encoder,
+            gst::StreamError::Format | gst::StreamError::WrongType => return ErrorKind::Negotiation,
+            // whipclientsink / whepclientsrc report a failed signalling
+            // exchange (HTTP error, rejected offer) as a stream error
+            gst::StreamError::Failed if network => return ErrorKind::Ingress,
+            _ => {}
+        }
+    }
+    if matches!(error.kind::<gst::CoreError>(), Some(gst::CoreError::Negotiation)) {
+        return ErrorKind::Negotiation;
+    }
+    if factory.ends_with("enc") {
+        return ErrorKind::Encoder;
+    }
+    if network {
+        return ErrorKind::Network;
+    }
+
+    ErrorKind::Unknown
+}
+
+/// Structure name of the bus message posted when a WHIP peer connection is up
+pub const CONNECTED_MESSAGE: &str = "cinny-peer-connected";
+
+/// What a webrtcbin state change means for the stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerEvent {
+    Connected,
+    Failed,
+}
+
+/// Map a webrtcbin `connection-state` / `ice-connection-state` value (by
+/// nick) to a stream event. Only the peer connection as a whole (ICE and
+/// DTLS) counts as connected; either one failing fails the connection.
+pub fn peer_event(property: &str, nick: &str) -> Option<PeerEvent> {
+    match (property, nick) {
+        ("connection-state", "connected") => Some(PeerEvent::Connected),
+        ("connection-state", "failed") | ("ice-connection-state", "failed") => Some(PeerEvent::Failed),
+        _ => None,
+    }
+}
+
+/// Watch the webrtcbin whipclientsink creates once it negotiates. Its
+/// connection coming up is posted as a `CONNECTED_MESSAGE` application
+/// message, a failure as an error from the webrtcbin (see `classify_error`).
+pub fn watch_peer_connection(whip: &gst::Element) {
+    let bin = match whip.downcast_ref::<gst::Bin>() {
+        Some(bin) => bin,
+        None => return,
+    };
+    bin.connect_deep_element_added(|_, _, element| {
+        if !element.factory().map(|f| f.name() == "webrtcbin").unwrap_or(false) {
+            return;
+        }
+        for property in ["connection-state", "ice-connection-state"] {
+            element.connect_notify(Some(property), move |webrtcbin, _| {
+                let value = webrtcbin.property_value(property);
+                let nick = match gst::glib::EnumValue::from_value(&value) {
+                    Some((_, value)) => value.nick().to_string(),
+                    None => return,
+                };
+                log_to_file(&format!("WebRTC {}: {}", property, nick));
+                let message = match peer_event(property, &nick) {
+                    Some(PeerEvent::Connected) => gst::message::Application::builder(gst::Structure::new_empty(CONNECTED_MESSAGE))
+                        .src(webrtcbin)
+                        .build(),
+                    Some(PeerEvent::Failed) => gst::message::Error::builder(gst::ResourceError::Failed, "WebRTC connection failed")
+                        .src(webrtcbin)
+                        .debug(&format!("{}: {}", property, nick))
+                        .build(),
+                    None => return,
+                };
+                let _ = webrtcbin.post_message(message);
+            });
+        }
+    });
+}
+
+fn emit<R: Runtime>(app: &AppHandle<R>, shared: &SharedState, event: StreamStateEvent) {
+    let name = if shared.playback { PLAYBACK_STATE_EVENT } else { STATE_EVENT };
+    let _ = app.emit_all(name, event);
+}
+
+/// Move the stream to `next` and emit a transition event.
+/// Invalid transitions are logged and ignored.
+pub fn transition<R: Runtime>(
+    app: &AppHandle<R>,
+    shared: &SharedState,
+    next: StreamState,
+    source: Option<&str>,
+    error_kind: Option<ErrorKind>,
+    message: Option<String>,
+) {
+    let previous = {
+        let mut state = match shared.state.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        let previous = *state;
+        if previous == next {
+            return;
+        }
+        if !previous.can_transition_to(next) {
+            log_to_file(&format!("Ignoring invalid state transition {:?} -> {:?}", previous, next));
+            return;
+        }
+        *state = next;
+        previous
+    };
+
+    log_to_file(&format!("STREAM STATE: {:?} -> {:?}", previous, next));
+    emit(app, shared, StreamStateEvent {
+        session_id: shared.session_id.clone(),
+        kind: StateEventKind::Transition,
+        state: next,
+        previous,
+        source: source.map(|s| s.to_string()),
+        error_kind,
+        message,
+        attempt: None,
+    });
+}
+
+/// Report an error (the caller decides on the resulting transition)
+pub fn report_error<R: Runtime>(app: &AppHandle<R>, shared: &SharedState, source: &str, error_kind: ErrorKind, message: String) {
+    let state = shared.state.lock().map(|s| *s).unwrap_or_default();
+    emit(app, shared, StreamStateEvent {
+        session_id: shared.session_id.clone(),
+        kind: StateEventKind::Error,
+        state,
+        previous: state,
+        source: Some(source.to_string()),
+        error_kind: Some(error_kind),
+        message: Some(message),
+        attempt: None,
+    });
+}
+
+/// Report a warning; the state does not change
+pub fn report_warning<R: Runtime>(app: &AppHandle<R>, shared: &SharedState, source: &str, message: String) {
+    let state = shared.state.lock().map(|s| *s).unwrap_or_default();
+    emit(app, shared, StreamStateEvent {
+        session_id: shared.session_id.clone(),
+        kind: StateEventKind::Warning,
+        state,
+        previous: state,
+        source: Some(source.to_string()),
+        error_kind: None,
+        message: Some(message),
+        attempt: None,
+    });
+}
+
+/// Report a scheduled WHIP reconnect attempt
+pub fn report_reconnect<R: Runtime>(app: &AppHandle<R>, shared: &SharedState, attempt: u32, max_attempts: u32, delay_ms: u64) {
+    let state = shared.state.lock().map(|s| *s).unwrap_or_default();
+    emit(app, shared, StreamStateEvent {
+        session_id: shared.session_id.clone(),
+        kind: StateEventKind::Reconnect,
+        state,
+        previous: state,
+        source: Some("whip".to_string()),
+        error_kind: None,
+        message: Some(format!("Reconnect attempt {}/{} in {}ms", attempt, max_attempts, delay_ms)),
+        attempt: Some(attempt),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use StreamState::*;
+
+    fn error<T: gst::glib::error::ErrorDomain>(code: T) -> gst::glib::Error {
+        gst::init().unwrap();
+        gst::glib::Error::new(code, "test")
+    }
+
+    #[test]
+    fn errors_are_classified_by_domain_and_factory() {
+        let cases = [
+            (Some("pipewiresrc"), error(gst::ResourceError::Failed), ErrorKind::Capture),
+            (Some("webrtcbin"), error(gst::ResourceError::Failed), ErrorKind::Ice),
+            (Some("dtlsenc"), error(gst::StreamError::Encode), ErrorKind::Ice),
+            (Some("whipclientsink"), error(gst::ResourceError::NotAuthorized), ErrorKind::Auth),
+            (Some("whipclientsink"), error(gst::StreamError::Failed), ErrorKind::Ingress),
+            (Some("whipclientsink"), error(gst::ResourceError::OpenWrite), ErrorKind::Network),
+            (Some("rtmp2sink"), error(gst::ResourceError::Write), ErrorKind::Network),
+            (Some("filesink"), error(gst::ResourceError::NoSpaceLeft), ErrorKind::Recording),
+            (Some("x264enc"), error(gst::StreamError::Encode), ErrorKind::Encoder),
+            (Some("nvh264enc"), error(gst::LibraryError::Init), ErrorKind::Encoder),
+            (Some("capsfilter"), error(gst::CoreError::Negotiation), ErrorKind::Negotiation),
+            (Some("mp4mux"), error(gst::StreamError::Format), ErrorKind::Negotiation),
+            (Some("queue"), error(gst::StreamError::Failed), ErrorKind::Unknown),
+            (None, error(gst::ResourceError::Write), ErrorKind::Unknown),
+        ];
+        for (factory, error, expected) in cases {
+            assert_eq!(classify_error(factory, &error), expected, "{:?} {}", factory, error);
+        }
+    }
+
+    #[test]
+    fn numbers_in_messages_do_not_change_the_class() {
+        gst::init().unwrap();
+        let error = gst::glib::Error::new(gst::StreamError::Failed, "Internal data stream error (500 buffers, connection 401)");
+        assert_eq!(classify_error(Some("queue"), &error), ErrorKind::Unknown);
+    }
+
+    #[test]
+    fn peer_connection_drives_live_and_failure() {
+        assert_eq!(peer_event("connection-state", "connected"), Some(PeerEvent::Connected));
+        assert_eq!(peer_event("connection-state", "failed"), Some(PeerEvent::Failed));
+        assert_eq!(peer_event("ice-connection-state", "failed"), Some(PeerEvent::Failed));
+        // ICE alone is not enough: DTLS still has to finish
+        assert_eq!(peer_event("ice-connection-state", "connected"), None);
+        assert_eq!(peer_event("ice-connection-state", "completed"), None);
+        assert_eq!(peer_event("connection-state", "connecting"), None);
+        assert_eq!(peer_event("connection-state", "disconnected"), None);
+    }
+
+    #[test]
+    fn transition_table() {
+        let allowed = [
+            (Idle, Starting),
+            (Starting, Connecting),
+            (Starting, Failed),
+            (Starting, Idle),
+            (Connecting, Live),
+            (Connecting, Reconnecting),
+            (Connecting, Failed),
+            (Connecting, Stopping),
+            (Connecting, Idle),
+            (Live, Reconnecting),
+            (Live, Failed),
+            (Live, Stopping),
+            (Live, Idle),
+            (Reconnecting, Connecting),
+            (Reconnecting, Live),
+            (Reconnecting, Failed),
+            (Reconnecting, Stopping),
+            (Reconnecting, Idle),
+            (Stopping, Idle),
+            (Failed, Starting),
+            (Failed, Stopping),
+            (Failed, Idle),
+        ];
+        let all = [Idle, Starting, Connecting, Live, Reconnecting, Stopping, Failed];
+        for from in all {
+            for to in all {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from, to)),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Streaming log file
+//!
+//! Every `log_to_file` line goes to `<app data>/logs/streaming.log` as
+//! `<ISO-8601 UTC> <LEVEL> [<session id or ->] <message>`. The file is
+//! rotated to `streaming.1.log` ... once it passes `MAX_FILE_SIZE`, and
+//! `read` filters the current and rotated files by session and level,
+//! newest first.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Runtime};
+
+/// Rotate once the current file is this large
+const MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Rotated files kept next to the current one
+const MAX_ROTATED: u32 = 3;
+
+const FILE_STEM: &str = "streaming";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "DEBUG" => Some(LogLevel::Debug),
+            "INFO" => Some(LogLevel::Info),
+            "WARN" => Some(LogLevel::Warn),
+            "ERROR" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+
+    /// Level of a message by its conventional "ERROR..." / "WARNING..." prefix
+    pub fn of(message: &str) -> Self {
+        if message.starts_with("ERROR") || message.contains(" ERROR:") {
+            LogLevel::Error
+        } else if message.starts_with("WARNING") {
+            LogLevel::Warn
+        } else {
+            LogLevel::Info
+        }
+    }
+}
+
+struct LogFile {
+    dir: PathBuf,
+    file: Option<File>,
+    size: u64,
+}
+
+/// None until `init`; lines logged before that only go to the console
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+fn path(dir: &Path, index: u32) -> PathBuf {
+    match index {
+        0 => dir.join(format!("{}.log", FILE_STEM)),
+        n => dir.join(format!("{}.{}.log", FILE_STEM, n)),
+    }
+}
+
+/// Start writing to the app's log directory
+pub fn init<R: Runtime>(app: &AppHandle<R>) {
+    let dir = app
+        .path_resolver()
+        .app_data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("logs");
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::warn!("[Stream] Failed to create log directory {}: {}", dir.display(), e);
+        return;
+    }
+    log::info!("[Stream] Logging to {}", path(&dir, 0).display());
+    *LOG_FILE.lock().unwrap() = Some(LogFile { dir, file: None, size: 0 });
+}
+
+impl LogFile {
+    fn open(&mut self) -> Option<&mut File> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path(&self.dir, 0))
+                .ok()?;
+            self.size = file.metadata().map(|m| m.len()).unwrap_or(0);
+            self.file = Some(file);
+        }
+        self.file.as_mut()
+    }
+
+    /// streaming.log -> streaming.1.log -> ... dropping the oldest
+    fn rotate(&mut self) {
+        self.file = None;
+        let _ = std::fs::remove_file(path(&self.dir, MAX_ROTATED));
+        for index in (0..MAX_ROTATED).rev() {
+            let _ = std::fs::rename(path(&self.dir, index), path(&self.dir, index + 1));
+        }
+        self.size = 0;
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.size + line.len() as u64 + 1 > MAX_FILE_SIZE {
+            self.rotate();
+        }
+        if let Some(file) = self.open() {
+            if writeln!(file, "{}", line).is_ok() {
+                self.size += line.len() as u64 + 1;
+            }
+        }
+    }
+}
+
+/// "2026-01-31T12:34:56.789Z"
+fn iso_timestamp(now: SystemTime) -> String {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (hour, minute, second) = ((secs / 3600) % 24, (secs / 60) % 60, secs % 60);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, hour, minute, second, since_epoch.subsec_millis()
+    )
+}
+
+/// Write one line to the console and, once initialized, the log file
+pub fn write(level: LogLevel, session_id: Option<&str>, message: &str) {
+    let session = session_id.unwrap_or("-");
+    match level {
+        LogLevel::Debug => log::debug!("[Stream] [{}] {}", session, message),
+        LogLevel::Info => log::info!("[Stream] [{}] {}", session, message),
+        LogLevel::Warn => log::warn!("[Stream] [{}] {}", session, message),
+        LogLevel::Error => log::error!("[Stream] [{}] {}", session, message),
+    }
+
+    if let Ok(mut log_file) = LOG_FILE.lock() {
+        if let Some(ref mut log_file) = *log_file {
+            // One line per entry, so GStreamer debug strings can't break filtering
+            let line = format!(
+                "{} {} [{}] {}",
+                iso_timestamp(SystemTime::now()),
+                level.as_str(),
+                session,
+                message.replace('\n', " ")
+            );
+            log_file.write(&line);
+        }
+    }
+}
+
+/// Level and session of a log line, None for lines not in our format
+fn parse_line(line: &str) -> Option<(LogLevel, &str)> {
+    let mut fields = line.splitn(4, ' ');
+    let _timestamp = fields.next()?;
+    let level = LogLevel::parse(fields.next()?)?;
+    let session = fields.next()?.strip_prefix('[')?.strip_suffix(']')?;
+    Some((level, session))
+}
+
+/// Last `lines` lines across the current and rotated files, optionally only
+/// one session's and only those at or above `min_level`
+pub fn read(lines: usize, session_id: Option<&str>, min_level: Option<LogLevel>) -> Result<String, String> {
+    let dir = match *LOG_FILE.lock().unwrap() {
+        Some(ref log_file) => log_file.dir.clone(),
+        None => return Err("Streaming log is not initialized".to_string()),
+    };
+    read_dir(&dir, lines, session_id, min_level)
+}
+
+/// `read` on the files in `dir`. Files are read newest first and older ones
+/// are not opened once `lines` matches are found.
+fn read_dir(dir: &Path, lines: usize, session_id: Option<&str>, min_level: Option<LogLevel>) -> Result<String, String> {
+    let wanted = |line: &str| match parse_line(line) {
+        Some((level, session)) => {
+            session_id.map(|id| id == session).unwrap_or(true) && min_level.map(|min| level >= min).unwrap_or(true)
+        }
+        None => session_id.is_none() && min_level.is_none(),
+    };
+
+    // Newest line first
+    let mut matching: Vec<String> = Vec::new();
+    for index in 0..=MAX_ROTATED {
+        if matching.len() >= lines {
+            break;
+        }
+        let content = match std::fs::read_to_string(path(dir, index)) {
+            Ok(c) => c,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Failed to read log: {}", e)),
+        };
+        let remaining = lines - matching.len();
+        matching.extend(
+            content
+                .lines()
+                .rev()
+                .filter(|line| wanted(line))
+                .take(remaining)
+                .map(|line| line.to_string()),
+        );
+    }
+
+    matching.reverse();
+    Ok(matching.join("\n"))
+}
+
+/// Empty the current file and delete rotated ones
+pub fn clear() -> Result<(), String> {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    let log_file = log_file.as_mut().ok_or("Streaming log is not initialized")?;
+    log_file.file = None;
+    for index in 1..=MAX_ROTATED {
+        let _ = std::fs::remove_file(path(&log_file.dir, index));
+    }
+    std::fs::write(path(&log_file.dir, 0), "").map_err(|e| format!("Failed to clear log: {}", e))?;
+    log_file.size = 0;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Empty folder for log files
+    fn log_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cinny-log-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn at(secs: u64, millis: u64) -> String {
+        iso_timestamp(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn timestamps_are_iso_8601_utc() {
+        assert_eq!(at(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(at(1_704_067_199, 999), "2023-12-31T23:59:59.999Z");
+        assert_eq!(at(1_709_210_096, 789), "2024-02-29T12:34:56.789Z");
+        // 2000 is a leap year, 2100 is not
+        assert_eq!(at(951_782_400, 0), "2000-02-29T00:00:00.000Z");
+        assert_eq!(at(4_107_542_399, 0), "2100-02-28T23:59:59.000Z");
+        assert_eq!(at(4_107_542_400, 0), "2100-03-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn lines_are_parsed_by_level_and_session() {
+        assert_eq!(
+            parse_line("2024-02-29T12:34:56.789Z WARN [stream-1] WARNING: slow"),
+            Some((LogLevel::Warn, "stream-1"))
+        );
+        assert_eq!(parse_line("2024-02-29T12:34:56.789Z DEBUG [-] tick"), Some((LogLevel::Debug, "-")));
+        assert_eq!(parse_line("2024-02-29T12:34:56.789Z NOTICE [-] tick"), None);
+        assert_eq!(parse_line("2024-02-29T12:34:56.789Z INFO stream-1 no brackets"), None);
+        assert_eq!(parse_line("free text"), None);
+    }
+
+    #[test]
+    fn rotates_past_the_size_limit() {
+        let dir = log_dir("rotate");
+        let mut log_file = LogFile { dir: dir.clone(), file: None, size: 0 };
+        log_file.write("first");
+        // Pretend the file is almost full instead of writing 5 MiB
+        log_file.size = MAX_FILE_SIZE - 3;
+        log_file.write("second");
+
+        assert_eq!(std::fs::read_to_string(path(&dir, 1)).unwrap(), "first\n");
+        assert_eq!(std::fs::read_to_string(path(&dir, 0)).unwrap(), "second\n");
+        assert_eq!(log_file.size, "second\n".len() as u64);
+
+        // The oldest file is dropped past MAX_ROTATED
+        for n in 0..MAX_ROTATED + 1 {
+            log_file.size = MAX_FILE_SIZE;
+            log_file.write(&format!("line {}", n));
+        }
+        assert!(!path(&dir, MAX_ROTATED + 1).exists());
+        assert_eq!(std::fs::read_to_string(path(&dir, MAX_ROTATED)).unwrap(), "line 0\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn read_filters_by_session_and_level_newest_last() {
+        let dir = log_dir("read");
+        std::fs::write(
+            path(&dir, 1),
+            "T INFO [a] old a\nT ERROR [b] old b error\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path(&dir, 0),
+            "T DEBUG [a] a debug\nT WARN [a] a warn\nT INFO [b] b info\nnot our format\nT ERROR [a] a error\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_dir(&dir, 10, Some("a"), None).unwrap(),
+            "T INFO [a] old a\nT DEBUG [a] a debug\nT WARN [a] a warn\nT ERROR [a] a error"
+        );
+        assert_eq!(
+            read_dir(&dir, 10, None, Some(LogLevel::Warn)).unwrap(),
+            "T ERROR [b] old b error\nT WARN [a] a warn\nT ERROR [a] a error"
+        );
+        assert_eq!(read_dir(&dir, 10, Some("b"), Some(LogLevel::Error)).unwrap(), "T ERROR [b] old b error");
+        // Unparsable lines only show up unfiltered
+        assert!(read_dir(&dir, 10, None, None).unwrap().contains("not our format"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn read_keeps_only_the_newest_lines() {
+        let dir = log_dir("newest");
+        std::fs::write(path(&dir, 2), "T INFO [a] oldest\n").unwrap();
+        std::fs::write(path(&dir, 1), "T INFO [a] older\n").unwrap();
+        std::fs::write(path(&dir, 0), "T INFO [a] new 1\nT INFO [a] new 2\n").unwrap();
+
+        assert_eq!(read_dir(&dir, 2, None, None).unwrap(), "T INFO [a] new 1\nT INFO [a] new 2");
+        assert_eq!(read_dir(&dir, 3, None, None).unwrap(), "T INFO [a] older\nT INFO [a] new 1\nT INFO [a] new 2");
+        assert_eq!(read_dir(&dir, 0, None, None).unwrap(), "");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
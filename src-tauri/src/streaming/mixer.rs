@@ -0,0 +1,93 @@
+//! Desktop audio + microphone mixing
+//!
+//! Each audio input is its own chain ending in a named `volume` element.
+//! With a microphone configured, the inputs meet in an `audiomixer` ahead of
+//! `opusenc`; the volume elements stay reachable by name so levels can be
+//! changed while streaming.
+
+use gstreamer as gst;
+use gst::prelude::{GstBinExt, ObjectExt};
+use serde::{Deserialize, Serialize};
+
+use super::pipeline::ElementSpec;
+
+/// Name of the mixer heading the audio branch
+pub const MIXER_NAME: &str = "audio_mixer";
+
+/// An audio input of the stream
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioInput {
+    Desktop,    // System loopback
+    Microphone,
+}
+
+impl AudioInput {
+    /// Name of the input's volume element
+    pub fn volume_name(self) -> &'static str {
+        match self {
+            AudioInput::Desktop => "desktop_volume",
+            AudioInput::Microphone => "mic_volume",
+        }
+    }
+}
+
+/// Upper bound for input volume (2.0 = +6 dB)
+pub const MAX_VOLUME: f64 = 2.0;
+
+/// Volume and mute of one input
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct AudioLevel {
+    #[serde(default = "default_volume")]
+    pub volume: f64, // 0.0 - 2.0, 1.0 = unchanged
+    #[serde(default)]
+    pub muted: bool,
+}
+
+fn default_volume() -> f64 {
+    1.0
+}
+
+impl Default for AudioLevel {
+    fn default() -> Self {
+        Self {
+            volume: default_volume(),
+            muted: false,
+        }
+    }
+}
+
+/// Microphone mixed into the stream audio
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MicrophoneConfig {
+    #[serde(default)]
+    pub device: Option<String>, // None = system default input
+    #[serde(default, flatten)]
+    pub level: AudioLevel,
+}
+
+/// Volume element for an input at its initial level
+pub fn volume_element(input: AudioInput, level: AudioLevel) -> ElementSpec {
+    ElementSpec::new("volume")
+        .named(input.volume_name())
+        .float("volume", level.volume.clamp(0.0, MAX_VOLUME))
+        .bool("mute", level.muted)
+}
+
+/// Mixer joining all inputs. Its own latency keeps a late microphone
+/// from holding back desktop audio.
+pub fn mixer() -> ElementSpec {
+    ElementSpec::new("audiomixer")
+        .named(MIXER_NAME)
+        .int("latency", 20_000_000)
+}
+
+/// Apply `level` to a running pipeline's input
+pub fn set_level(pipeline: &gst::Pipeline, input: AudioInput, level: AudioLevel) -> Result<(), String> {
+    let volume = pipeline
+        .by_name(input.volume_name())
+        .ok_or_else(|| format!("Stream has no {:?} audio input", input))?;
+    volume.set_property("volume", level.volume.clamp(0.0, MAX_VOLUME));
+    volume.set_property("mute", level.muted);
+    Ok(())
+}
@@ -0,0 +1,380 @@
+//! RTMP and SRT outputs next to WHIP
+//!
+//! A stream with WHIP and RTMP / SRT outputs encodes its video twice:
+//! whipclientsink runs its own encoder, which can't be shared from outside
+//! the sink, and the outputs get a second H.264 encode from `video_tee`
+//! (plus AAC transcoded from the Opus on `audio_tee`). That second encode is
+//! shared by all RTMP / SRT destinations, each a consumer bin on its tees
+//! (see `tap`), so a dead endpoint is detached on its own while WHIP and the
+//! other outputs keep going. Expect roughly double the encoder load of a
+//! WHIP-only stream.
+
+use gstreamer as gst;
+use gst::prelude::{ElementExt, GstBinExt, GstBinExtManual, PadExt};
+use serde::{Deserialize, Serialize};
+
+use super::encoder::{self, VideoCodec};
+use super::pipeline::{link_to_request_pad, ElementSpec};
+use super::tap::{self, TeeLink};
+use super::{log_to_file, StreamConfig};
+
+/// Tees carrying the shared encode
+const VIDEO_TEE: &str = "output_video_tee";
+const AUDIO_TEE: &str = "output_audio_tee";
+
+/// AAC encoders in order of preference
+const AAC_ENCODERS: &[&str] = &["fdkaacenc", "avenc_aac", "voaacenc"];
+
+/// A destination for the stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum OutputConfig {
+    /// Same as `whip_url` / `bearer_token`; at most one per stream
+    Whip {
+        url: String,
+        #[serde(default)]
+        bearer_token: Option<String>,
+    },
+    /// rtmp:// or rtmps:// URL including the stream key
+    Rtmp { url: String },
+    /// srt:// URI, e.g. "srt://relay:9000?mode=caller"
+    Srt {
+        uri: String,
+        #[serde(default)]
+        latency_ms: Option<u32>,
+    },
+}
+
+impl OutputConfig {
+    fn kind(&self) -> &'static str {
+        match self {
+            OutputConfig::Whip { .. } => "whip",
+            OutputConfig::Rtmp { .. } => "rtmp",
+            OutputConfig::Srt { .. } => "srt",
+        }
+    }
+
+    /// Destination with stream keys and passphrases cut off
+    fn redacted_target(&self) -> String {
+        match self {
+            OutputConfig::Whip { url, .. } => url.clone(),
+            OutputConfig::Rtmp { url } => match url.rfind('/') {
+                Some(i) if i > url.find("://").map(|s| s + 2).unwrap_or(0) => format!("{}/***", &url[..i]),
+                _ => url.clone(),
+            },
+            OutputConfig::Srt { uri, .. } => uri.split('?').next().unwrap_or(uri).to_string(),
+        }
+    }
+}
+
+/// Move a WHIP entry of `config.outputs` into `whip_url` / `bearer_token`
+pub fn apply_whip_output(config: &mut StreamConfig) -> Result<(), String> {
+    let mut whip = config.outputs.iter().filter_map(|output| match output {
+        OutputConfig::Whip { url, bearer_token } => Some((url.clone(), bearer_token.clone())),
+        _ => None,
+    });
+    let first = whip.next();
+    if whip.next().is_some() {
+        return Err("Only one WHIP output per stream is supported".to_string());
+    }
+    if let Some((url, bearer_token)) = first {
+        if config.has_whip() && config.whip_url != url {
+            return Err("whip_url and the WHIP output point to different endpoints".to_string());
+        }
+        config.whip_url = url;
+        if bearer_token.is_some() {
+            config.bearer_token = bearer_token;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `config` has RTMP / SRT destinations
+pub fn has_outputs(config: &StreamConfig) -> bool {
+    config.outputs.iter().any(|o| !matches!(o, OutputConfig::Whip { .. }))
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputState {
+    Running,
+    Failed,
+    Stopped,
+}
+
+/// Per-destination status reported in `StreamStatus`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputStatus {
+    pub id: String, // "<kind>-<index in outputs>"
+    pub kind: String,
+    pub target: String, // Without stream key / passphrase
+    pub state: OutputState,
+    pub error: Option<String>,
+}
+
+/// A running RTMP / SRT destination
+pub struct Output {
+    pub id: String,
+    config: OutputConfig,
+    sink: Option<(gst::Bin, Vec<TeeLink>)>,
+    state: OutputState,
+    error: Option<String>,
+}
+
+impl Output {
+    fn bin_name(&self) -> String {
+        format!("output_{}", self.id)
+    }
+
+    pub fn status(&self) -> OutputStatus {
+        OutputStatus {
+            id: self.id.clone(),
+            kind: self.config.kind().to_string(),
+            target: self.config.redacted_target(),
+            state: self.state,
+            error: self.error.clone(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state == OutputState::Running
+    }
+
+    /// Whether a bus message source is (inside) this output
+    pub fn is_from(&self, pipeline: &gst::Pipeline, src: &gst::Object) -> bool {
+        self.sink.is_some() && tap::is_from(pipeline, &self.bin_name(), src)
+    }
+
+    /// Build the muxer / sink bin and attach it to the shared encode
+    pub fn start(&mut self, pipeline: &gst::Pipeline) -> Result<(), String> {
+        if self.sink.is_some() {
+            return Ok(());
+        }
+        let bin = build_bin(&self.bin_name(), &self.config, pipeline.by_name(AUDIO_TEE).is_some())?;
+
+        // Start the destination's timestamps at zero, like a recording
+        let offset = pipeline
+            .current_running_time()
+            .map(|t| t.nseconds() as i64)
+            .unwrap_or(0);
+        for pad in bin.sink_pads() {
+            pad.set_offset(-offset);
+        }
+
+        let links = tap::attach(pipeline, &bin)?;
+        log_to_file(&format!("Output {} started ({})", self.id, self.config.redacted_target()));
+        self.sink = Some((bin, links));
+        self.state = OutputState::Running;
+        self.error = None;
+        Ok(())
+    }
+
+    /// Detach after an error; the rest of the stream keeps running
+    pub fn fail(&mut self, pipeline: &gst::Pipeline, error: String) {
+        if let Some((bin, links)) = self.sink.take() {
+            tap::detach(pipeline, &bin, &links);
+        }
+        log_to_file(&format!("Output {} failed: {}", self.id, error));
+        self.state = OutputState::Failed;
+        self.error = Some(error);
+    }
+
+    pub fn stop(&mut self, pipeline: &gst::Pipeline) {
+        if let Some((bin, links)) = self.sink.take() {
+            tap::detach(pipeline, &bin, &links);
+            log_to_file(&format!("Output {} stopped", self.id));
+        }
+        self.state = OutputState::Stopped;
+    }
+}
+
+fn aac_encoder() -> Option<&'static str> {
+    AAC_ENCODERS
+        .iter()
+        .copied()
+        .find(|name| gst::ElementFactory::find(name).is_some())
+}
+
+/// Muxer, sink and the ghost pads ("output_video" / "output_audio") that
+/// `tap::attach` links to the shared encode tees
+fn build_bin(name: &str, config: &OutputConfig, with_audio: bool) -> Result<gst::Bin, String> {
+    let (mux, sink, video_pad, audio_pad) = match config {
+        OutputConfig::Rtmp { url } => (
+            ElementSpec::new("flvmux").bool("streamable", true),
+            ElementSpec::new("rtmp2sink").str("location", url),
+            "video",
+            "audio",
+        ),
+        OutputConfig::Srt { uri, latency_ms } => {
+            let mut sink = ElementSpec::new("srtsink")
+                .str("uri", uri)
+                .bool("wait-for-connection", false);
+            if let Some(latency) = latency_ms {
+                sink = sink.int("latency", *latency as i64);
+            }
+            (
+                ElementSpec::new("mpegtsmux").int("alignment", 7),
+                sink,
+                "sink_%d",
+                "sink_%d",
+            )
+        }
+        OutputConfig::Whip { .. } => return Err("WHIP is not a muxed output".to_string()),
+    };
+
+    let bin = gst::Bin::builder().name(name).build();
+    let mux = mux.make()?;
+    // Not async: the bin joins a pipeline that is already PLAYING
+    let sink = sink.bool("async", false).make()?;
+    bin.add_many([&mux, &sink])
+        .map_err(|e| format!("Failed to add muxer to {}: {}", name, e))?;
+    mux.link(&sink)
+        .map_err(|e| format!("Failed to link {} muxer: {}", name, e))?;
+
+    let video = tap::add_branch(&bin, "output_video", &[tap::consumer_queue(), ElementSpec::new("h264parse")])?;
+    link_to_request_pad(&video, &mux, video_pad)?;
+    if with_audio {
+        let audio = tap::add_branch(&bin, "output_audio", &[tap::consumer_queue(), ElementSpec::new("aacparse")])?;
+        link_to_request_pad(&audio, &mux, audio_pad)?;
+    }
+    Ok(bin)
+}
+
+/// Start the shared H.264 / AAC encode and every RTMP / SRT destination.
+/// This is a second encoder instance next to whipclientsink's; only the
+/// factory (`encoder_factory`, if it already is H.264) is reused.
+pub fn start_all(pipeline: &gst::Pipeline, config: &StreamConfig, encoder_factory: &'static str) -> Result<Vec<Output>, String> {
+    if !has_outputs(config) {
+        return Ok(Vec::new());
+    }
+
+    let h264_factory = if config.codec == VideoCodec::H264 {
+        encoder_factory
+    } else {
+        encoder::select_encoder(config.encoder, VideoCodec::H264)?
+    };
+    let preset = config.preset.as_deref().filter(|_| h264_factory == encoder_factory);
+    tap::add_shared_chain(pipeline, "video_tee", &[
+        tap::consumer_queue(),
+        ElementSpec::new("videoconvert"),
+        // Keyframe every 2s, as RTMP ingests expect
+        encoder::standalone_encoder(h264_factory, config.bitrate, config.fps * 2, preset, &config.quality_mode),
+        ElementSpec::new("tee").named(VIDEO_TEE).bool("allow-not-linked", true),
+    ])?;
+
+    if pipeline.by_name("audio_tee").is_some() {
+        match aac_encoder() {
+            Some(aac) => tap::add_shared_chain(pipeline, "audio_tee", &[
+                tap::consumer_queue(),
+                ElementSpec::new("opusdec"),
+                ElementSpec::new("audioconvert"),
+                ElementSpec::new("audioresample"),
+                ElementSpec::new(aac).int("bitrate", 160_000),
+                ElementSpec::new("tee").named(AUDIO_TEE).bool("allow-not-linked", true),
+            ])?,
+            None => log_to_file("WARNING: No AAC encoder (fdkaacenc / avenc_aac / voaacenc), outputs carry no audio"),
+        }
+    }
+    log_to_file(&format!("Shared output encode: {} + AAC", h264_factory));
+
+    let mut outputs = Vec::new();
+    for (index, output) in config.outputs.iter().enumerate() {
+        if matches!(output, OutputConfig::Whip { .. }) {
+            continue;
+        }
+        let mut output = Output {
+            id: format!("{}-{}", output.kind(), index),
+            config: output.clone(),
+            sink: None,
+            state: OutputState::Stopped,
+            error: None,
+        };
+        output.start(pipeline)?;
+        outputs.push(output);
+    }
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::pipeline::tests::config;
+    use serde_json::json;
+
+    fn rtmp(url: &str) -> OutputConfig {
+        OutputConfig::Rtmp { url: url.to_string() }
+    }
+
+    fn whip_output(url: &str, bearer_token: Option<&str>) -> serde_json::Value {
+        json!({ "kind": "whip", "url": url, "bearer_token": bearer_token })
+    }
+
+    #[test]
+    fn redacted_target_hides_keys_and_passphrases() {
+        assert_eq!(rtmp("rtmp://live.example.com/app/secret-key").redacted_target(), "rtmp://live.example.com/app/***");
+        assert_eq!(rtmp("rtmps://live.example.com:443/app/key").redacted_target(), "rtmps://live.example.com:443/app/***");
+        // Nothing after the host to hide
+        assert_eq!(rtmp("rtmp://live.example.com").redacted_target(), "rtmp://live.example.com");
+
+        let srt = OutputConfig::Srt {
+            uri: "srt://relay:9000?mode=caller&passphrase=hunter22".to_string(),
+            latency_ms: None,
+        };
+        assert_eq!(srt.redacted_target(), "srt://relay:9000");
+    }
+
+    #[test]
+    fn whip_output_moves_into_whip_url() {
+        let mut config = config(json!({
+            "whip_url": "",
+            "outputs": [whip_output("https://sfu.example.com/whip/a", Some("token"))],
+        }));
+        apply_whip_output(&mut config).unwrap();
+        assert_eq!(config.whip_url, "https://sfu.example.com/whip/a");
+        assert_eq!(config.bearer_token.as_deref(), Some("token"));
+        assert!(!has_outputs(&config));
+    }
+
+    #[test]
+    fn whip_output_keeps_a_matching_whip_url_and_its_token() {
+        let mut config = config(json!({
+            "whip_url": "https://sfu.example.com/whip/a",
+            "bearer_token": "old",
+            "outputs": [whip_output("https://sfu.example.com/whip/a", None)],
+        }));
+        apply_whip_output(&mut config).unwrap();
+        assert_eq!(config.bearer_token.as_deref(), Some("old"));
+    }
+
+    #[test]
+    fn duplicate_or_conflicting_whip_is_rejected() {
+        let mut duplicate = config(json!({
+            "whip_url": "",
+            "outputs": [
+                whip_output("https://sfu.example.com/whip/a", None),
+                whip_output("https://sfu.example.com/whip/b", None),
+            ],
+        }));
+        assert!(apply_whip_output(&mut duplicate).is_err());
+
+        let mut conflicting = config(json!({
+            "whip_url": "https://sfu.example.com/whip/a",
+            "outputs": [whip_output("https://sfu.example.com/whip/b", None)],
+        }));
+        assert!(apply_whip_output(&mut conflicting).is_err());
+    }
+
+    #[test]
+    fn rtmp_and_srt_count_as_outputs() {
+        let config = config(json!({
+            "outputs": [
+                { "kind": "rtmp", "url": "rtmp://live.example.com/app/key" },
+                { "kind": "srt", "uri": "srt://relay:9000" },
+            ],
+        }));
+        assert!(has_outputs(&config));
+        let mut config = config;
+        apply_whip_output(&mut config).unwrap();
+        assert_eq!(config.outputs.len(), 2);
+    }
+}
@@ -0,0 +1,303 @@
+//! Overlay compositing
+//!
+//! With overlays configured, a `compositor` sits behind the source selector:
+//! the captured screen is its bottom pad and every overlay layer (webcam,
+//! PNG watermark, text, clock) is a source bin on a further pad. Position,
+//! size and opacity are compositor pad properties, so layers can be moved,
+//! resized, faded and retitled while streaming.
+
+use gstreamer as gst;
+use gst::prelude::{ElementExt, ElementExtManual, GstBinExt, ObjectExt, PadExt};
+use serde::{Deserialize, Serialize};
+
+use super::pipeline::{source_bin, update_element, ElementSpec};
+use super::log_to_file;
+
+/// Name of the compositor in the video branch
+pub const COMPOSITOR_NAME: &str = "overlay_compositor";
+
+/// Capsfilter fixing the compositor's output size
+const OUTPUT_CAPS_NAME: &str = "overlay_caps";
+
+/// What an overlay layer shows
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum OverlaySource {
+    /// Camera (v4l2src / mfvideosrc / avfvideosrc)
+    Webcam {
+        #[serde(default)]
+        device: Option<String>, // None = first camera
+    },
+    /// PNG file
+    Image { path: String },
+    /// Static text, e.g. the stream title
+    Text {
+        text: String,
+        #[serde(default)]
+        font: Option<String>, // Pango font description
+    },
+    /// Wall clock
+    Clock {
+        #[serde(default = "default_clock_format")]
+        format: String, // strftime
+        #[serde(default)]
+        font: Option<String>,
+    },
+}
+
+fn default_clock_format() -> String {
+    "%H:%M:%S".to_string()
+}
+
+/// One overlay layer, placed in output pixels. Later layers are drawn on top.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverlayLayer {
+    pub id: String,
+    #[serde(flatten)]
+    pub source: OverlaySource,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default = "default_opacity")]
+    pub opacity: f64, // 0.0 - 1.0
+}
+
+fn default_opacity() -> f64 {
+    1.0
+}
+
+/// Runtime change to a layer; omitted fields are left as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OverlayUpdate {
+    pub id: String,
+    #[serde(default)]
+    pub x: Option<i32>,
+    #[serde(default)]
+    pub y: Option<i32>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub opacity: Option<f64>,
+    #[serde(default)]
+    pub text: Option<String>, // Text layers only
+}
+
+const DEFAULT_FONT: &str = "Sans Bold 24";
+
+fn bin_name(index: usize) -> String {
+    format!("overlay_{}", index)
+}
+
+fn text_name(index: usize) -> String {
+    format!("overlay_{}_text", index)
+}
+
+#[cfg(target_os = "linux")]
+fn webcam_source(device: Option<&str>) -> ElementSpec {
+    let src = ElementSpec::new("v4l2src");
+    match device {
+        Some(device) => src.str("device", device),
+        None => src,
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn webcam_source(device: Option<&str>) -> ElementSpec {
+    let src = ElementSpec::new("mfvideosrc");
+    match device {
+        Some(device) => src.str("device-path", device),
+        None => src,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn webcam_source(device: Option<&str>) -> ElementSpec {
+    let src = ElementSpec::new("avfvideosrc");
+    match device.and_then(|d| d.parse::<i64>().ok()) {
+        Some(index) => src.int("device-index", index),
+        None => src,
+    }
+}
+
+/// Transparent canvas of the layer's size for text to be drawn on
+fn text_canvas(layer: &OverlayLayer, fps: u32) -> Vec<ElementSpec> {
+    vec![
+        ElementSpec::new("videotestsrc")
+            .bool("is-live", true)
+            .enum_nick("pattern", "solid-color")
+            .int("foreground-color", 0),
+        ElementSpec::caps(format!(
+            "video/x-raw,format=BGRA,width={},height={},framerate={}/1",
+            layer.width.max(1),
+            layer.height.max(1),
+            fps.max(1)
+        )),
+    ]
+}
+
+fn text_style(spec: ElementSpec, font: Option<&str>) -> ElementSpec {
+    spec.str("font-desc", font.unwrap_or(DEFAULT_FONT))
+        .enum_nick("halignment", "center")
+        .enum_nick("valignment", "center")
+        .bool("shaded-background", false)
+}
+
+/// Elements producing layer `index`, ending in raw video
+fn layer_chain(index: usize, layer: &OverlayLayer, fps: u32) -> Result<Vec<ElementSpec>, String> {
+    let chain = match layer.source {
+        OverlaySource::Webcam { ref device } => vec![
+            webcam_source(device.as_deref()),
+            ElementSpec::new("queue").enum_nick("leaky", "downstream").int("max-size-buffers", 2),
+            ElementSpec::new("videoconvert"),
+        ],
+        OverlaySource::Image { ref path } => {
+            if !std::path::Path::new(path).is_file() {
+                return Err(format!("Overlay image not found: {}", path));
+            }
+            vec![
+                ElementSpec::new("filesrc").str("location", path),
+                ElementSpec::new("pngdec"),
+                ElementSpec::new("videoconvert"),
+                ElementSpec::new("imagefreeze").bool("is-live", true),
+            ]
+        }
+        OverlaySource::Text { ref text, ref font } => {
+            let mut chain = text_canvas(layer, fps);
+            chain.push(text_style(ElementSpec::new("textoverlay").named(&text_name(index)), font.as_deref()).str("text", text));
+            chain
+        }
+        OverlaySource::Clock { ref format, ref font } => {
+            let mut chain = text_canvas(layer, fps);
+            chain.push(text_style(ElementSpec::new("clockoverlay"), font.as_deref()).str("time-format", format));
+            chain
+        }
+    };
+    Ok(chain)
+}
+
+/// Compositor and fixed output caps for the video branch. Overlays outside
+/// the frame are clipped instead of growing the output.
+pub fn compositor(width: u32, height: u32, fps: u32) -> Vec<ElementSpec> {
+    vec![
+        ElementSpec::new("compositor")
+            .named(COMPOSITOR_NAME)
+            .enum_nick("background", "black"),
+        output_caps(width, height, fps),
+    ]
+}
+
+fn output_caps(width: u32, height: u32, fps: u32) -> ElementSpec {
+    ElementSpec::caps(format!(
+        "video/x-raw,width={},height={},framerate={}/1,pixel-aspect-ratio=1/1",
+        width, height, fps.max(1)
+    ))
+    .named(OUTPUT_CAPS_NAME)
+}
+
+/// Change the compositor's output size of a running pipeline (no-op
+/// without overlays)
+pub fn resize(pipeline: &gst::Pipeline, width: u32, height: u32, fps: u32) -> Result<(), String> {
+    match pipeline.by_name(OUTPUT_CAPS_NAME) {
+        Some(caps) => update_element(&caps, &output_caps(width, height, fps)),
+        None => Ok(()),
+    }
+}
+
+fn apply_placement(pad: &gst::Pad, layer: &OverlayLayer, hidden: bool) {
+    pad.set_property("xpos", layer.x);
+    pad.set_property("ypos", layer.y);
+    pad.set_property("width", layer.width as i32);
+    pad.set_property("height", layer.height as i32);
+    pad.set_property("alpha", if hidden { 0.0 } else { layer.opacity.clamp(0.0, 1.0) });
+}
+
+/// Compositor pad of layer `index`
+fn layer_pad(pipeline: &gst::Pipeline, index: usize) -> Option<gst::Pad> {
+    pipeline
+        .by_name(&bin_name(index))
+        .and_then(|bin| bin.static_pad("src"))
+        .and_then(|src| src.peer())
+}
+
+/// Build every layer and link it above the captured screen
+pub fn attach(pipeline: &gst::Pipeline, layers: &[OverlayLayer], fps: u32) -> Result<(), String> {
+    if layers.is_empty() {
+        return Ok(());
+    }
+    let compositor = pipeline
+        .by_name(COMPOSITOR_NAME)
+        .ok_or("Pipeline has no overlay compositor")?;
+
+    for (index, layer) in layers.iter().enumerate() {
+        let bin = source_bin(&bin_name(index), &layer_chain(index, layer, fps)?)?;
+        pipeline
+            .add(&bin)
+            .map_err(|e| format!("Failed to add overlay {}: {}", layer.id, e))?;
+        let pad = compositor
+            .request_pad_simple("sink_%u")
+            .ok_or("Overlay compositor has no free sink pad")?;
+        pad.set_property("zorder", index as u32 + 1);
+        apply_placement(&pad, layer, false);
+        bin.static_pad("src")
+            .ok_or_else(|| format!("Overlay {} has no src pad", layer.id))?
+            .link(&pad)
+            .map_err(|e| format!("Failed to link overlay {}: {:?}", layer.id, e))?;
+        log_to_file(&format!(
+            "Overlay {} ({:?}) at {},{} {}x{} opacity {:.2}",
+            layer.id, layer.source, layer.x, layer.y, layer.width, layer.height, layer.opacity
+        ));
+    }
+    Ok(())
+}
+
+/// Apply `update` to `layers` and the running pipeline. Hidden layers
+/// (stream paused) take the new values but stay invisible.
+pub fn update(pipeline: &gst::Pipeline, layers: &mut [OverlayLayer], update: &OverlayUpdate, hidden: bool) -> Result<(), String> {
+    let index = layers
+        .iter()
+        .position(|l| l.id == update.id)
+        .ok_or_else(|| format!("No overlay '{}'", update.id))?;
+    if let Some(opacity) = update.opacity.filter(|o| !o.is_finite()) {
+        return Err(format!("Invalid opacity {}", opacity));
+    }
+
+    let mut layer = layers[index].clone();
+    layer.x = update.x.unwrap_or(layer.x);
+    layer.y = update.y.unwrap_or(layer.y);
+    layer.width = update.width.unwrap_or(layer.width);
+    layer.height = update.height.unwrap_or(layer.height);
+    layer.opacity = update.opacity.map(|o| o.clamp(0.0, 1.0)).unwrap_or(layer.opacity);
+    if let Some(ref new_text) = update.text {
+        match layer.source {
+            OverlaySource::Text { ref mut text, .. } => *text = new_text.clone(),
+            _ => return Err(format!("Overlay '{}' is not a text overlay", update.id)),
+        }
+    }
+
+    let pad = layer_pad(pipeline, index).ok_or_else(|| format!("Overlay '{}' is not running", update.id))?;
+    apply_placement(&pad, &layer, hidden);
+    if let Some(ref text) = update.text {
+        if let Some(overlay) = pipeline.by_name(&text_name(index)) {
+            overlay.set_property("text", text);
+        }
+    }
+
+    log_to_file(&format!(
+        "Overlay {} moved to {},{} {}x{} opacity {:.2}",
+        layer.id, layer.x, layer.y, layer.width, layer.height, layer.opacity
+    ));
+    layers[index] = layer;
+    Ok(())
+}
+
+/// Hide or show every layer without changing its configured opacity
+pub fn set_hidden(pipeline: &gst::Pipeline, layers: &[OverlayLayer], hidden: bool) {
+    for (index, layer) in layers.iter().enumerate() {
+        if let Some(pad) = layer_pad(pipeline, index) {
+            apply_placement(&pad, layer, hidden);
+        }
+    }
+}
@@ -0,0 +1,643 @@
+//! Typed GStreamer pipeline construction
+//!
+//! Pipelines are described as plain data (`ElementSpec` / `PipelineSpec`) and
+//! only turned into real elements by `PipelineSpec::instantiate`. This keeps
+//! user-supplied values (WHIP URL, bearer token, TURN server) out of any
+//! parser: they are set as typed properties, never spliced into a launch string.
+
+use gstreamer as gst;
+use gst::glib;
+use gst::prelude::{Cast, ChildProxyExt, ElementExt, ElementExtManual, GstBinExtManual, GstObjectExt, GstValueExt, IsA, ObjectExt, PadExt, ToSendValue};
+
+/// A single property value for an element
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropValue {
+    Bool(bool),
+    /// Numeric value, converted to the property's own integer type on apply
+    Int(i64),
+    /// Floating point value (`gfloat` or `gdouble` property)
+    Float(f64),
+    /// Enum nick (e.g. "downstream" for queue's `leaky`)
+    Enum(String),
+    /// Free-form string, set verbatim (never parsed)
+    Str(String),
+    /// Caps string built from config numbers only
+    Caps(String),
+    /// GstValueArray of strings (e.g. webrtcsink `turn-servers`)
+    StrArray(Vec<String>),
+}
+
+/// Description of one element to create
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementSpec {
+    pub factory: &'static str,
+    pub name: Option<String>,
+    /// Properties in the order they are applied. Names containing `::` are
+    /// child-proxy properties (e.g. `signaller::whip-endpoint`).
+    pub properties: Vec<(String, PropValue)>,
+}
+
+impl ElementSpec {
+    pub fn new(factory: &'static str) -> Self {
+        Self {
+            factory,
+            name: None,
+            properties: Vec::new(),
+        }
+    }
+
+    /// Shorthand for a `capsfilter` element
+    pub fn caps(caps: impl Into<String>) -> Self {
+        Self::new("capsfilter").prop("caps", PropValue::Caps(caps.into()))
+    }
+
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn prop(mut self, name: &str, value: PropValue) -> Self {
+        self.properties.push((name.to_string(), value));
+        self
+    }
+
+    pub fn bool(self, name: &str, value: bool) -> Self {
+        self.prop(name, PropValue::Bool(value))
+    }
+
+    pub fn int(self, name: &str, value: i64) -> Self {
+        self.prop(name, PropValue::Int(value))
+    }
+
+    pub fn float(self, name: &str, value: f64) -> Self {
+        self.prop(name, PropValue::Float(value))
+    }
+
+    pub fn enum_nick(self, name: &str, nick: &str) -> Self {
+        self.prop(name, PropValue::Enum(nick.to_string()))
+    }
+
+    pub fn str(self, name: &str, value: &str) -> Self {
+        self.prop(name, PropValue::Str(value.to_string()))
+    }
+
+    /// Look up a property value by name
+    pub fn get(&self, name: &str) -> Option<&PropValue> {
+        self.properties.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// Create the element and apply all properties
+    pub fn make(&self) -> Result<gst::Element, String> {
+        let mut builder = gst::ElementFactory::make(self.factory);
+        if let Some(ref name) = self.name {
+            builder = builder.name(name.as_str());
+        }
+        let element = builder
+            .build()
+            .map_err(|e| format!("Missing GStreamer element '{}': {}", self.factory, e))?;
+
+        for (name, value) in &self.properties {
+            apply_property(&element, self.factory, name, value)?;
+        }
+
+        Ok(element)
+    }
+}
+
+/// Set a single property on an element, checking that it exists first so a
+/// typo or a plugin version mismatch surfaces as an error instead of a panic.
+fn apply_property(element: &gst::Element, factory: &str, name: &str, value: &PropValue) -> Result<(), String> {
+    let (target, prop): (gst::glib::Object, &str) = match name.split_once("::") {
+        Some((child, prop)) => {
+            let child_obj = element
+                .dynamic_cast_ref::<gst::ChildProxy>()
+                .and_then(|proxy| proxy.child_by_name(child))
+                .ok_or_else(|| format!("{} has no child '{}'", factory, child))?;
+            (child_obj, prop)
+        }
+        None => (element.clone().upcast(), name),
+    };
+
+    if target.find_property(prop).is_none() {
+        return Err(format!("{} has no property '{}'", factory, name));
+    }
+
+    let from_str = |text: &str| set_property_from_str(&target, prop, text).map_err(|e| format!("{}: {}", factory, e));
+    match value {
+        // Integer width and enum type vary per element, so let GStreamer
+        // convert from the textual form into the property's real type.
+        PropValue::Int(i) => from_str(&i.to_string())?,
+        PropValue::Float(f) => from_str(&f.to_string())?,
+        PropValue::Enum(nick) => from_str(nick)?,
+        PropValue::Bool(b) => target.set_property(prop, *b),
+        PropValue::Str(s) => target.set_property(prop, s.as_str()),
+        PropValue::Caps(s) => {
+            let caps = s
+                .parse::<gst::Caps>()
+                .map_err(|e| format!("Invalid caps '{}' for {}: {}", s, factory, e))?;
+            target.set_property(prop, &caps);
+        }
+        PropValue::StrArray(items) => {
+            target.set_property(prop, gst::Array::new(items.iter().map(|s| s.to_send_value())));
+        }
+    }
+
+    Ok(())
+}
+
+/// Set a property from its textual form, converted into the property's own
+/// type. Unlike glib's `set_property_from_str`, which panics, a missing or
+/// read-only property and a value the property would reject (unknown enum
+/// nick, out of range number) are returned as errors.
+pub fn set_property_from_str(target: &impl IsA<glib::Object>, name: &str, value: &str) -> Result<(), String> {
+    let pspec = target
+        .find_property(name)
+        .ok_or_else(|| format!("no property '{}'", name))?;
+    if !pspec.flags().contains(glib::ParamFlags::WRITABLE) {
+        return Err(format!("property '{}' is not writable", name));
+    }
+    let parsed = glib::Value::deserialize(value, pspec.value_type())
+        .ok()
+        .filter(|parsed| in_range(&pspec, parsed))
+        .ok_or_else(|| format!("invalid value '{}' for '{}'", value, name))?;
+    target.set_property_from_value(name, &parsed);
+    Ok(())
+}
+
+/// glib panics when a numeric value lies outside the property's range
+fn in_range(pspec: &glib::ParamSpec, value: &glib::Value) -> bool {
+    fn within<T: PartialOrd>(value: Option<T>, min: T, max: T) -> bool {
+        value.map(|v| v >= min && v <= max).unwrap_or(false)
+    }
+    if let Some(spec) = pspec.downcast_ref::<glib::ParamSpecInt>() {
+        within(value.get::<i32>().ok(), spec.minimum(), spec.maximum())
+    } else if let Some(spec) = pspec.downcast_ref::<glib::ParamSpecUInt>() {
+        within(value.get::<u32>().ok(), spec.minimum(), spec.maximum())
+    } else if let Some(spec) = pspec.downcast_ref::<glib::ParamSpecInt64>() {
+        within(value.get::<i64>().ok(), spec.minimum(), spec.maximum())
+    } else if let Some(spec) = pspec.downcast_ref::<glib::ParamSpecUInt64>() {
+        within(value.get::<u64>().ok(), spec.minimum(), spec.maximum())
+    } else if let Some(spec) = pspec.downcast_ref::<glib::ParamSpecFloat>() {
+        within(value.get::<f32>().ok(), spec.minimum(), spec.maximum())
+    } else if let Some(spec) = pspec.downcast_ref::<glib::ParamSpecDouble>() {
+        within(value.get::<f64>().ok(), spec.minimum(), spec.maximum())
+    } else {
+        true
+    }
+}
+
+/// A linear chain of elements ending in a named `tee`, which feeds one
+/// request pad on the sink. Extra consumers (e.g. recordings) attach to the
+/// tee at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchSpec {
+    pub elements: Vec<ElementSpec>,
+    /// Extra source chains feeding request pads ("sink_%u") of the first
+    /// element, e.g. audio inputs into a mixer. Empty for linear branches.
+    pub inputs: Vec<Vec<ElementSpec>>,
+    /// Extra chains from the tee into further sink pads, e.g. simulcast
+    /// layers. Each gets its own queue. Ignored without a sink.
+    pub layers: Vec<Vec<ElementSpec>>,
+    /// Name of the tee appended to the branch (e.g. "video_tee")
+    pub tee: &'static str,
+    /// Request pad template on the sink this branch links into (e.g. "video_%u")
+    pub sink_pad: &'static str,
+}
+
+/// Full pipeline description: capture branches feeding a single sink.
+/// Without a sink the branches end at their tees (recording-only mode).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineSpec {
+    pub sink: Option<ElementSpec>,
+    pub branches: Vec<BranchSpec>,
+}
+
+impl PipelineSpec {
+    /// Find the first element created from `factory` in any branch or the sink
+    pub fn find(&self, factory: &str) -> Option<&ElementSpec> {
+        if let Some(sink) = self.sink.as_ref().filter(|s| s.factory == factory) {
+            return Some(sink);
+        }
+        self.branches
+            .iter()
+            .flat_map(|b| b.elements.iter())
+            .find(|e| e.factory == factory)
+    }
+
+    /// Human-readable, gst-launch-like description for logging.
+    /// Secrets (auth token) are redacted.
+    pub fn describe(&self) -> String {
+        let describe_element = |e: &ElementSpec| {
+            let mut out = e.factory.to_string();
+            if let Some(ref name) = e.name {
+                out.push_str(&format!(" name={}", name));
+            }
+            for (name, value) in &e.properties {
+                let shown = match value {
+                    _ if name.ends_with("auth-token") => "<redacted>".to_string(),
+                    PropValue::Bool(b) => b.to_string(),
+                    PropValue::Int(i) => i.to_string(),
+                    PropValue::Float(f) => f.to_string(),
+                    PropValue::Enum(n) => n.clone(),
+                    PropValue::Str(s) | PropValue::Caps(s) => format!("\"{}\"", s),
+                    PropValue::StrArray(items) => format!("<{}>", items.join(", ")),
+                };
+                out.push_str(&format!(" {}={}", name, shown));
+            }
+            out
+        };
+
+        let mut parts = Vec::new();
+        if let Some(ref sink) = self.sink {
+            parts.push(describe_element(sink));
+        }
+        for branch in &self.branches {
+            let head = branch.elements.first().map(|e| e.name.as_deref().unwrap_or(e.factory)).unwrap_or("");
+            for input in &branch.inputs {
+                let chain: Vec<String> = input.iter().map(describe_element).collect();
+                parts.push(format!("{} ! {}.", chain.join(" ! "), head));
+            }
+            let chain: Vec<String> = branch.elements.iter().map(describe_element).collect();
+            let mut line = format!("{} ! tee name={} allow-not-linked=true", chain.join(" ! "), branch.tee);
+            if let Some(ref sink) = self.sink {
+                let sink_name = sink.name.as_deref().unwrap_or(sink.factory);
+                line.push_str(&format!(" {}. ! queue ! {}.{}", branch.tee, sink_name, branch.sink_pad));
+                for layer in &branch.layers {
+                    let chain: Vec<String> = layer.iter().map(describe_element).collect();
+                    line.push_str(&format!(
+                        " {}. ! queue ! {} ! {}.{}",
+                        branch.tee,
+                        chain.join(" ! "),
+                        sink_name,
+                        branch.sink_pad
+                    ));
+                }
+            }
+            parts.push(line);
+        }
+        parts.join(" ")
+    }
+
+    /// Create every element, add them to a new pipeline and link them
+    pub fn instantiate(&self) -> Result<gst::Pipeline, String> {
+        let pipeline = gst::Pipeline::new();
+        let sink = match self.sink {
+            Some(ref spec) => {
+                let sink = spec.make()?;
+                pipeline
+                    .add(&sink)
+                    .map_err(|e| format!("Failed to add {} to pipeline: {}", spec.factory, e))?;
+                Some(sink)
+            }
+            None => None,
+        };
+
+        for branch in &self.branches {
+            if branch.elements.is_empty() {
+                return Err(format!("Empty {} branch", branch.sink_pad));
+            }
+            let mut elements = branch
+                .elements
+                .iter()
+                .map(|spec| spec.make())
+                .collect::<Result<Vec<_>, _>>()?;
+            // Consumers may come and go at runtime, so the tee must never
+            // fail with not-linked while it has no src pads
+            elements.push(
+                ElementSpec::new("tee")
+                    .named(branch.tee)
+                    .bool("allow-not-linked", true)
+                    .make()?,
+            );
+            pipeline
+                .add_many(&elements)
+                .map_err(|e| format!("Failed to add elements to pipeline: {}", e))?;
+            gst::Element::link_many(&elements)
+                .map_err(|e| format!("Failed to link {} branch: {}", branch.sink_pad, e))?;
+
+            for input in &branch.inputs {
+                let chain = input
+                    .iter()
+                    .map(|spec| spec.make())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let last = chain
+                    .last()
+                    .ok_or_else(|| format!("Empty input in {} branch", branch.sink_pad))?;
+                pipeline
+                    .add_many(&chain)
+                    .map_err(|e| format!("Failed to add elements to pipeline: {}", e))?;
+                gst::Element::link_many(&chain)
+                    .map_err(|e| format!("Failed to link {} input: {}", branch.sink_pad, e))?;
+                link_to_request_pad(last, &elements[0], "sink_%u")?;
+            }
+
+            if let Some(ref sink) = sink {
+                let tee = &elements[elements.len() - 1];
+                let main = Vec::new();
+                for layer in std::iter::once(&main).chain(branch.layers.iter()) {
+                    let mut chain = vec![ElementSpec::new("queue").make()?];
+                    for spec in layer {
+                        chain.push(spec.make()?);
+                    }
+                    pipeline
+                        .add_many(&chain)
+                        .map_err(|e| format!("Failed to add elements to pipeline: {}", e))?;
+                    gst::Element::link_many(std::iter::once(tee).chain(chain.iter()))
+                        .map_err(|e| format!("Failed to link {} to {}: {}", branch.tee, branch.sink_pad, e))?;
+                    link_to_request_pad(&chain[chain.len() - 1], sink, branch.sink_pad)?;
+                }
+            }
+        }
+
+        Ok(pipeline)
+    }
+}
+
+/// Link `src`'s static src pad into a freshly requested pad on `sink`
+pub fn link_to_request_pad(src: &gst::Element, sink: &gst::Element, template: &str) -> Result<gst::Pad, String> {
+    let sink_pad = sink
+        .request_pad_simple(template)
+        .ok_or_else(|| format!("{} has no request pad '{}'", sink.name(), template))?;
+    let src_pad = src
+        .static_pad("src")
+        .ok_or_else(|| format!("{} has no src pad", src.name()))?;
+    src_pad
+        .link(&sink_pad)
+        .map_err(|e| format!("Failed to link {} to {}:{}: {:?}", src.name(), sink.name(), sink_pad.name(), e))?;
+    Ok(sink_pad)
+}
+
+/// Build `specs` into a bin called `name`, exposing the last element's src
+/// pad as ghost pad "src"
+pub fn source_bin(name: &str, specs: &[ElementSpec]) -> Result<gst::Bin, String> {
+    if specs.is_empty() {
+        return Err(format!("Empty source {}", name));
+    }
+    let bin = gst::Bin::builder().name(name).build();
+
+    let elements = specs
+        .iter()
+        .map(|spec| spec.make())
+        .collect::<Result<Vec<_>, _>>()?;
+    bin.add_many(&elements)
+        .map_err(|e| format!("Failed to add elements to {}: {}", name, e))?;
+    gst::Element::link_many(&elements)
+        .map_err(|e| format!("Failed to link {}: {}", name, e))?;
+
+    let target = elements[elements.len() - 1]
+        .static_pad("src")
+        .ok_or_else(|| format!("{} has no src pad", name))?;
+    let ghost = gst::GhostPad::builder_with_target(&target)
+        .map_err(|e| format!("Failed to create {} src pad: {}", name, e))?
+        .name("src")
+        .build();
+    bin.add_pad(&ghost)
+        .map_err(|e| format!("Failed to add {} src pad: {}", name, e))?;
+
+    Ok(bin)
+}
+
+/// Apply `spec`'s properties to an element that already exists, e.g. new
+/// caps on a running capsfilter
+pub fn update_element(element: &gst::Element, spec: &ElementSpec) -> Result<(), String> {
+    for (name, value) in &spec.properties {
+        apply_property(element, spec.factory, name, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::streaming::{build_gstreamer_pipeline, build_video_source, QualityMode, StreamConfig};
+    use serde_json::json;
+
+    /// 1080p60 at 6 Mbps from the default capture to a WHIP endpoint, with
+    /// `overrides` merged in. Shared with the other modules' tests.
+    pub(crate) fn config(overrides: serde_json::Value) -> StreamConfig {
+        let mut base = json!({
+            "source_id": "default",
+            "whip_url": "https://sfu.example.com/whip/room",
+            "width": 1920,
+            "height": 1080,
+            "fps": 60,
+            "bitrate": 6000,
+            "audio_enabled": true,
+            "bearer_token": null,
+            "turn_server": null,
+        });
+        for (key, value) in overrides.as_object().unwrap() {
+            base[key] = value.clone();
+        }
+        serde_json::from_value(base).unwrap()
+    }
+
+    fn whip(spec: &PipelineSpec) -> &ElementSpec {
+        spec.sink.as_ref().expect("WHIP session without a sink")
+    }
+
+    fn int(element: &ElementSpec, name: &str) -> i64 {
+        match element.get(name) {
+            Some(PropValue::Int(i)) => *i,
+            other => panic!("{} {} is {:?}", element.factory, name, other),
+        }
+    }
+
+    fn factories(elements: &[ElementSpec]) -> Vec<&'static str> {
+        elements.iter().map(|e| e.factory).collect()
+    }
+
+    #[test]
+    fn element_spec_keeps_properties_in_order() {
+        let queue = ElementSpec::new("queue")
+            .named("video_queue")
+            .int("max-size-buffers", 3)
+            .enum_nick("leaky", "downstream")
+            .bool("silent", true);
+        assert_eq!(queue.name.as_deref(), Some("video_queue"));
+        let names: Vec<&str> = queue.properties.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["max-size-buffers", "leaky", "silent"]);
+        assert_eq!(int(&queue, "max-size-buffers"), 3);
+        assert!(matches!(queue.get("leaky"), Some(PropValue::Enum(_))));
+        assert_eq!(queue.get("missing"), None);
+
+        let caps = ElementSpec::caps("audio/x-raw,rate=48000");
+        assert_eq!(caps.factory, "capsfilter");
+        assert_eq!(caps.get("caps"), Some(&PropValue::Caps("audio/x-raw,rate=48000".to_string())));
+    }
+
+    #[test]
+    fn quality_modes_set_bitrate_range() {
+        let cases = [
+            (QualityMode::Performance, 6_000_000, 500_000, 9_000_000),
+            (QualityMode::Balanced, 6_000_000, 1_000_000, 12_000_000),
+            (QualityMode::Quality, 6_000_000, 2_000_000, 18_000_000),
+            (QualityMode::Lossless, 12_000_000, 5_000_000, 24_000_000),
+        ];
+        for (mode, start, min, max) in cases {
+            let spec = build_gstreamer_pipeline(&config(json!({ "quality_mode": mode })));
+            let sink = whip(&spec);
+            assert_eq!(sink.factory, "whipclientsink");
+            assert_eq!(sink.name.as_deref(), Some("whip"));
+            assert_eq!(int(sink, "start-bitrate"), start, "{:?}", mode);
+            assert_eq!(int(sink, "min-bitrate"), min, "{:?}", mode);
+            assert_eq!(int(sink, "max-bitrate"), max, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn branches_feed_sink_pads() {
+        let spec = build_gstreamer_pipeline(&config(json!({})));
+        let pads: Vec<_> = spec.branches.iter().map(|b| b.sink_pad).collect();
+        assert_eq!(pads, ["video_%u", "audio_%u"]);
+        assert!(factories(&spec.branches[1].elements).ends_with(&["opusenc", "capsfilter"]));
+
+        let spec = build_gstreamer_pipeline(&config(json!({ "audio_enabled": false })));
+        let pads: Vec<_> = spec.branches.iter().map(|b| b.sink_pad).collect();
+        assert_eq!(pads, ["video_%u"]);
+    }
+
+    #[test]
+    fn source_sits_in_front_of_selector() {
+        let config = config(json!({}));
+        let source = build_video_source(&config);
+        let spec = build_gstreamer_pipeline(&config);
+        let video = &spec.branches[0].elements;
+        assert_eq!(video[..source.len()], source[..]);
+        assert_eq!(video[source.len()].factory, "input-selector");
+        assert_eq!(video[source.len() + 1].name.as_deref(), Some("video_queue"));
+    }
+
+    #[test]
+    fn branches_end_in_tees() {
+        let spec = build_gstreamer_pipeline(&config(json!({})));
+        let tees: Vec<_> = spec.branches.iter().map(|b| b.tee).collect();
+        assert_eq!(tees, ["video_tee", "audio_tee"]);
+        assert!(spec.describe().contains("tee name=video_tee allow-not-linked=true video_tee. ! queue ! whip.video_%u"));
+    }
+
+    #[test]
+    fn local_only_session_has_no_sink() {
+        let spec = build_gstreamer_pipeline(&config(json!({ "whip_url": "", "audio_enabled": false })));
+        assert!(spec.sink.is_none());
+        assert_eq!(spec.branches.len(), 1);
+        assert!(spec.describe().ends_with("tee name=video_tee allow-not-linked=true"));
+    }
+
+    #[test]
+    fn microphone_is_mixed_with_desktop_audio() {
+        let spec = build_gstreamer_pipeline(&config(json!({ "microphone": {} })));
+        let audio = &spec.branches[1];
+        assert_eq!(audio.elements[0].factory, "audiomixer");
+        let volumes: Vec<_> = audio.inputs.iter().map(|input| input.last().unwrap().name.as_deref()).collect();
+        assert_eq!(volumes, [Some("desktop_volume"), Some("mic_volume")]);
+
+        // A single input runs straight into the encoder
+        let spec = build_gstreamer_pipeline(&config(json!({ "audio_enabled": false, "microphone": {} })));
+        let audio = &spec.branches[1];
+        assert!(audio.inputs.is_empty());
+        assert!(spec.find("audiomixer").is_none());
+        assert!(audio.elements.iter().any(|e| e.name.as_deref() == Some("mic_volume")));
+    }
+
+    #[test]
+    fn simulcast_adds_layer_chains() {
+        let spec = build_gstreamer_pipeline(&config(json!({ "simulcast": { "layers": 3 } })));
+        let layers = &spec.branches[0].layers;
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0][0].name.as_deref(), Some("simulcast_scale_h"));
+        assert_eq!(layers[1][1].get("caps"), Some(&PropValue::Caps("video/x-raw,width=480,height=270,pixel-aspect-ratio=1/1".to_string())));
+        assert!(spec.describe().contains("video_tee. ! queue ! videoscale name=simulcast_scale_h"));
+
+        let spec = build_gstreamer_pipeline(&config(json!({ "whip_url": "", "simulcast": { "layers": 3 } })));
+        assert!(spec.branches[0].layers.is_empty());
+    }
+
+    #[test]
+    fn test_source_needs_no_desktop() {
+        let spec = build_gstreamer_pipeline(&config(json!({ "source_id": "test" })));
+        assert_eq!(spec.branches[0].elements[0].factory, "videotestsrc");
+        assert_eq!(spec.branches[1].elements[0].factory, "audiotestsrc");
+        assert!(spec.find("ximagesrc").is_none());
+        assert!(spec.find("d3d11screencapturesrc").is_none());
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn windows_capture_segment() {
+        let spec = build_gstreamer_pipeline(&config(json!({ "source_id": "hwnd:4242" })));
+        let video = &spec.branches[0].elements;
+        assert_eq!(video[0].factory, "d3d11screencapturesrc");
+        assert_eq!(int(&video[0], "window-handle"), 4242);
+        assert!(factories(video).ends_with(&["queue", "d3d11download"]));
+
+        let spec = build_gstreamer_pipeline(&config(json!({ "source_id": "monitor:0" })));
+        assert_eq!(int(&spec.branches[0].elements[0], "monitor-index"), 0);
+
+        let audio = &spec.branches[1].elements[0];
+        assert_eq!(audio.factory, "wasapisrc");
+        assert_eq!(audio.get("loopback"), Some(&PropValue::Bool(true)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn linux_capture_segment() {
+        let spec = build_gstreamer_pipeline(&config(json!({})));
+        let video = &spec.branches[0].elements;
+        assert_eq!(factories(&video[..3]), ["ximagesrc", "videoconvert", "videoscale"]);
+        assert_eq!(spec.branches[1].elements[0].factory, "pulsesrc");
+
+        let spec = build_gstreamer_pipeline(&config(json!({ "source_id": "pipewire:5:42" })));
+        let video = &spec.branches[0].elements;
+        assert_eq!(factories(&video[..2]), ["pipewiresrc", "videorate"]);
+        assert_eq!(int(&video[0], "fd"), 5);
+        assert_eq!(video[0].get("path"), Some(&PropValue::Str("42".to_string())));
+
+        let spec = build_gstreamer_pipeline(&config(json!({ "source_id": "xid:123" })));
+        let video = &spec.branches[0].elements;
+        assert_eq!(factories(&video[..3]), ["ximagesrc", "videoconvert", "videoscale"]);
+        assert_eq!(int(&video[0], "xid"), 123);
+
+        let desktop = &spec.branches[1].elements[0];
+        assert_eq!(desktop.get("device"), Some(&PropValue::Str("@DEFAULT_MONITOR@".to_string())));
+        let spec = build_gstreamer_pipeline(&config(json!({ "loopback_device": "alsa_output.hdmi.monitor" })));
+        let desktop = &spec.branches[1].elements[0];
+        assert_eq!(desktop.get("device"), Some(&PropValue::Str("alsa_output.hdmi.monitor".to_string())));
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn macos_capture_segment() {
+        let spec = build_gstreamer_pipeline(&config(json!({})));
+        let video = &spec.branches[0].elements;
+        assert_eq!(factories(&video[..3]), ["avfvideosrc", "videoconvert", "videoscale"]);
+        assert_eq!(spec.branches[1].elements[0].factory, "osxaudiosrc");
+    }
+
+    #[test]
+    fn user_values_stay_single_properties() {
+        let token = "abc\" ! filesink location=/tmp/x name=\"y";
+        let url = "https://sfu.example.com/whip/my room?x=\"1\" ! fakesink";
+        let turn = "turn://user:p w@turn.example.com:3478";
+        let spec = build_gstreamer_pipeline(&config(json!({
+            "whip_url": url,
+            "bearer_token": token,
+            "turn_server": turn,
+        })));
+        let sink = whip(&spec);
+        assert_eq!(sink.get("signaller::auth-token"), Some(&PropValue::Str(token.to_string())));
+        assert_eq!(sink.get("signaller::whip-endpoint"), Some(&PropValue::Str(url.to_string())));
+        assert_eq!(sink.get("turn-servers"), Some(&PropValue::StrArray(vec![turn.to_string()])));
+        assert!(spec.find("filesink").is_none());
+        assert!(spec.find("fakesink").is_none());
+    }
+
+    #[test]
+    fn describe_redacts_token() {
+        let spec = build_gstreamer_pipeline(&config(json!({ "bearer_token": "s3cret-token" })));
+        let described = spec.describe();
+        assert!(described.contains("signaller::auth-token=<redacted>"));
+        assert!(!described.contains("s3cret-token"));
+        assert!(described.contains("signaller::whip-endpoint=\"https://sfu.example.com/whip/room\""));
+    }
+}
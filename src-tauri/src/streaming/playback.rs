@@ -0,0 +1,382 @@
+//! Native WHEP playback
+//!
+//! The receiving side of a native stream: `whepclientsrc` pulls the stream
+//! from a WHEP endpoint and every decoded pad it exposes is played in a
+//! native window / on the default audio device, or (video) JPEG-encoded
+//! into `playback-frame` events for the WebView to draw. Lifecycle and stats
+//! events mirror the publishing side under `playback-state` /
+//! `playback-stats`.
+
+use gstreamer as gst;
+use gst::prelude::{Cast, ElementExt, GstBinExt, GstObjectExt, ObjectExt, PadExt, PadExtManual, ToValue};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tauri::{AppHandle, Manager, Runtime};
+
+use super::lifecycle::{self, StreamState};
+use super::pipeline::{ElementSpec, PropValue};
+use super::{log_scope, log_to_file, stats, tap, SharedState};
+
+/// Event name for decoded frames (`PlaybackOutput::Frames`)
+pub const FRAME_EVENT: &str = "playback-frame";
+
+/// Where decoded video goes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum PlaybackOutput {
+    /// Native video window (autovideosink)
+    Window,
+    /// JPEG frames as `playback-frame` events
+    Frames {
+        #[serde(default = "default_max_fps")]
+        max_fps: u32,
+        #[serde(default)]
+        width: Option<u32>, // Scaled keeping the aspect ratio, None = as received
+    },
+}
+
+fn default_max_fps() -> u32 {
+    30
+}
+
+impl Default for PlaybackOutput {
+    fn default() -> Self {
+        PlaybackOutput::Window
+    }
+}
+
+/// What to play and how
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackConfig {
+    pub whep_url: String,
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    #[serde(default)]
+    pub turn_server: Option<String>,
+    #[serde(default)]
+    pub output: PlaybackOutput,
+    #[serde(default = "default_audio")]
+    pub audio: bool, // false = audio tracks are received but not played
+    #[serde(default = "default_latency_ms")]
+    pub latency_ms: u32, // Jitter buffer size
+}
+
+fn default_audio() -> bool {
+    true
+}
+
+fn default_latency_ms() -> u32 {
+    100
+}
+
+/// `playback-frame` event payload
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaybackFrame {
+    pub session_id: String,
+    pub width: i32,
+    pub height: i32,
+    pub data: String, // data:image/jpeg;base64,...
+}
+
+/// Playback status
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackStatus {
+    pub session_id: String,
+    pub whep_url: String,
+    pub state: StreamState,
+    pub duration_seconds: u64,
+    pub frames_decoded: u64,
+    pub error: Option<String>,
+}
+
+/// One running WHEP playback
+pub struct PlaybackSession {
+    pub id: String,
+    pub pipeline: gst::Pipeline,
+    pub config: PlaybackConfig,
+    pub shared: SharedState,
+    /// Video frames that reached the output
+    frames_decoded: Arc<AtomicU64>,
+    start_time: Instant,
+}
+
+impl PlaybackSession {
+    pub fn status(&self) -> PlaybackStatus {
+        PlaybackStatus {
+            session_id: self.id.clone(),
+            whep_url: self.config.whep_url.clone(),
+            state: *self.shared.state.lock().unwrap(),
+            duration_seconds: self.start_time.elapsed().as_secs(),
+            frames_decoded: self.frames_decoded.load(Ordering::Relaxed),
+            error: self.shared.last_error.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn shutdown(&self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+        if let Ok(mut running) = self.shared.is_running.lock() {
+            *running = false;
+        }
+        log_to_file("Playback pipeline stopped");
+    }
+}
+
+fn build_source(config: &PlaybackConfig) -> ElementSpec {
+    let mut src = ElementSpec::new("whepclientsrc")
+        .named("whep")
+        .str("signaller::whep-endpoint", &config.whep_url);
+    if let Some(ref token) = config.bearer_token {
+        src = src.str("signaller::auth-token", token);
+    }
+    if let Some(ref turn) = config.turn_server {
+        src = src.prop("turn-servers", PropValue::StrArray(vec![turn.clone()]));
+    }
+    src
+}
+
+/// Small leaky queue: late frames are dropped rather than adding latency
+fn playback_queue() -> ElementSpec {
+    ElementSpec::new("queue")
+        .int("max-size-buffers", 2)
+        .int("max-size-bytes", 0)
+        .int("max-size-time", 0)
+        .enum_nick("leaky", "downstream")
+}
+
+fn video_chain(output: &PlaybackOutput) -> Vec<ElementSpec> {
+    match output {
+        PlaybackOutput::Window => vec![
+            playback_queue(),
+            ElementSpec::new("videoconvert"),
+            ElementSpec::new("autovideosink"),
+        ],
+        PlaybackOutput::Frames { max_fps, width } => {
+            let caps = match width {
+                Some(width) => format!("video/x-raw,width={},pixel-aspect-ratio=1/1", width),
+                None => "video/x-raw,pixel-aspect-ratio=1/1".to_string(),
+            };
+            vec![
+                playback_queue(),
+                ElementSpec::new("videoconvert"),
+                ElementSpec::new("videoscale"),
+                ElementSpec::new("videorate")
+                    .bool("drop-only", true)
+                    .int("max-rate", (*max_fps).max(1) as i64),
+                ElementSpec::caps(caps),
+                ElementSpec::new("jpegenc").int("quality", 80),
+                ElementSpec::new("appsink")
+                    .bool("emit-signals", true)
+                    .int("max-buffers", 1)
+                    .bool("drop", true)
+                    .bool("sync", false),
+            ]
+        }
+    }
+}
+
+fn audio_chain(play: bool) -> Vec<ElementSpec> {
+    if !play {
+        return vec![ElementSpec::new("fakesink").bool("sync", false)];
+    }
+    vec![
+        playback_queue(),
+        ElementSpec::new("audioconvert"),
+        ElementSpec::new("audioresample"),
+        ElementSpec::new("autoaudiosink"),
+    ]
+}
+
+fn emit_frame<R: Runtime>(app: &AppHandle<R>, session_id: &str, sample: &gst::Sample) {
+    let (width, height) = sample
+        .caps()
+        .and_then(|caps| caps.structure(0))
+        .map(|s| (s.get::<i32>("width").unwrap_or(0), s.get::<i32>("height").unwrap_or(0)))
+        .unwrap_or((0, 0));
+    if let Some(map) = sample.buffer().and_then(|b| b.map_readable().ok()) {
+        use base64::Engine;
+        let b64 = base64::engine::general_purpose::STANDARD.encode(map.as_slice());
+        let _ = app.emit_all(FRAME_EVENT, PlaybackFrame {
+            session_id: session_id.to_string(),
+            width,
+            height,
+            data: format!("data:image/jpeg;base64,{}", b64),
+        });
+    }
+}
+
+/// Emit every JPEG the appsink receives as a `playback-frame` event
+fn connect_frames<R: Runtime>(app: AppHandle<R>, session_id: String, appsink: &gst::Element) {
+    appsink.connect("new-sample", false, move |args| {
+        let sample = args[0]
+            .get::<gst::Element>()
+            .ok()
+            .and_then(|sink| sink.emit_by_name::<Option<gst::Sample>>("pull-sample", &[]));
+        if let Some(sample) = sample {
+            emit_frame(&app, &session_id, &sample);
+        }
+        Some(gst::FlowReturn::Ok.to_value())
+    });
+}
+
+/// Play one decoded pad of `whepclientsrc`
+fn link_pad<R: Runtime>(
+    app: &AppHandle<R>,
+    pipeline: &gst::Pipeline,
+    pad: &gst::Pad,
+    config: &PlaybackConfig,
+    shared: &SharedState,
+    frames_decoded: &Arc<AtomicU64>,
+) -> Result<(), String> {
+    let name = pad.name();
+    let video = name.starts_with("video");
+    let specs = if video {
+        video_chain(&config.output)
+    } else if name.starts_with("audio") {
+        audio_chain(config.audio)
+    } else {
+        vec![ElementSpec::new("fakesink").bool("sync", false)]
+    };
+
+    let bin = gst::Bin::builder().name(format!("playback_{}", name)).build();
+    let last = tap::add_branch(&bin, "sink", &specs)?;
+    if video && matches!(config.output, PlaybackOutput::Frames { .. }) {
+        connect_frames(app.clone(), shared.session_id.clone(), &last);
+    }
+    pipeline
+        .add(&bin)
+        .map_err(|e| format!("Failed to add {}: {}", bin.name(), e))?;
+    bin.sync_state_with_parent()
+        .map_err(|e| format!("Failed to start {}: {}", bin.name(), e))?;
+    let sink = bin.static_pad("sink").ok_or("Playback branch has no sink pad")?;
+    pad.link(&sink)
+        .map_err(|e| format!("Failed to link {}: {:?}", name, e))?;
+
+    // Live once the first video frame is through
+    if video {
+        let app = app.clone();
+        let shared = shared.clone();
+        let frames_decoded = frames_decoded.clone();
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+            if frames_decoded.fetch_add(1, Ordering::Relaxed) == 0 {
+                lifecycle::transition(&app, &shared, StreamState::Live, Some("whep"), None, None);
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+    log_to_file(&format!("Playing {}", name));
+    Ok(())
+}
+
+/// Build the playback pipeline and set it to PLAYING
+pub fn start<R: Runtime>(app: &AppHandle<R>, config: PlaybackConfig, shared: SharedState) -> Result<Arc<PlaybackSession>, String> {
+    let pipeline = gst::Pipeline::new();
+    let src = build_source(&config).make()?;
+    pipeline
+        .add(&src)
+        .map_err(|e| format!("Failed to add whepclientsrc: {}", e))?;
+
+    // webrtcbin is created once negotiation starts; keep its jitter buffer short
+    let latency = config.latency_ms;
+    pipeline.connect_deep_element_added(move |_, _, element| {
+        if element.factory().map(|f| f.name() == "webrtcbin").unwrap_or(false) {
+            element.set_property("latency", latency);
+        }
+    });
+
+    let frames_decoded = Arc::new(AtomicU64::new(0));
+    let weak_pipeline = pipeline.downgrade();
+    let pad_app = app.clone();
+    let pad_config = config.clone();
+    let pad_shared = shared.clone();
+    let pad_frames = frames_decoded.clone();
+    src.connect_pad_added(move |_, pad| {
+        if let Some(pipeline) = weak_pipeline.upgrade() {
+            if let Err(e) = link_pad(&pad_app, &pipeline, pad, &pad_config, &pad_shared, &pad_frames) {
+                log_to_file(&format!("ERROR: {}", e));
+            }
+        }
+    });
+
+    log_to_file("Setting playback pipeline to PLAYING state...");
+    if let Err(e) = pipeline.set_state(gst::State::Playing) {
+        let _ = pipeline.set_state(gst::State::Null);
+        return Err(format!("Failed to start playback: {:?}", e));
+    }
+    if let Ok(mut running) = shared.is_running.lock() {
+        *running = true;
+    }
+
+    let session = Arc::new(PlaybackSession {
+        id: shared.session_id.clone(),
+        pipeline,
+        config,
+        shared,
+        frames_decoded,
+        start_time: Instant::now(),
+    });
+    stats::spawn_playback_collector(app.clone(), &session.pipeline, session.shared.clone(), session.frames_decoded.clone());
+    watch_bus(app.clone(), &session)?;
+    Ok(session)
+}
+
+/// Spawn the bus message handler for a playback session
+fn watch_bus<R: Runtime>(app_handle: AppHandle<R>, session: &Arc<PlaybackSession>) -> Result<(), String> {
+    let bus = session.pipeline.bus().ok_or("Failed to get pipeline bus")?;
+    let shared = session.shared.clone();
+
+    std::thread::spawn(move || {
+        let _log = log_scope(&shared.session_id);
+        log_to_file("Playback message handler started");
+
+        loop {
+            let running = shared.is_running.lock().map(|r| *r).unwrap_or(false);
+            if !running {
+                break;
+            }
+            let msg = match bus.timed_pop(gst::ClockTime::from_seconds(1)) {
+                Some(m) => m,
+                None => continue,
+            };
+
+            use gst::MessageView;
+            let src_name = msg.src().map(|s| s.name().to_string()).unwrap_or_else(|| "unknown".to_string());
+            match msg.view() {
+                MessageView::Eos(..) => {
+                    log_to_file("Playback reached end of stream");
+                    lifecycle::transition(&app_handle, &shared, StreamState::Idle, Some(&src_name), None, None);
+                    break;
+                }
+                MessageView::Error(err) => {
+                    let error_msg = format!("ERROR from {}: {} (debug: {:?})", src_name, err.error(), err.debug());
+                    log_to_file(&error_msg);
+                    let factory = msg.src()
+                        .and_then(|s| s.downcast_ref::<gst::Element>())
+                        .and_then(|e| e.factory())
+                        .map(|f| f.name().to_string());
+                    let kind = lifecycle::classify_error(factory.as_deref(), &err.error());
+                    lifecycle::report_error(&app_handle, &shared, &src_name, kind, error_msg.clone());
+                    lifecycle::transition(&app_handle, &shared, StreamState::Failed, Some(&src_name), Some(kind), Some(error_msg.clone()));
+                    if let Ok(mut error) = shared.last_error.lock() {
+                        *error = Some(error_msg);
+                    }
+                    break;
+                }
+                MessageView::Warning(warning) => {
+                    let warning_msg = format!("WARNING from {}: {} (debug: {:?})", src_name, warning.error(), warning.debug());
+                    log_to_file(&warning_msg);
+                    lifecycle::report_warning(&app_handle, &shared, &src_name, warning_msg);
+                }
+                _ => {}
+            }
+        }
+
+        if let Ok(mut running) = shared.is_running.lock() {
+            *running = false;
+        }
+        log_to_file("Playback message handler exited");
+    });
+
+    Ok(())
+}
@@ -0,0 +1,709 @@
+//! Screen capture through xdg-desktop-portal on Linux
+//!
+//! Wayland compositors don't let clients read the screen, so ximagesrc only
+//! sees XWayland windows. The ScreenCast portal asks the user what to share
+//! and hands back a PipeWire remote plus a node id for `pipewiresrc`.
+//!
+//! Portal calls go through the `ScreenCastPortal` trait. `DbusPortal` is the
+//! real implementation; it takes its connection and bus name so it can be
+//! pointed at a mock portal service on a private bus.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use tauri::{AppHandle, Runtime};
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{OwnedFd, OwnedObjectPath, OwnedValue, Value};
+
+use super::{log_to_file, testsrc};
+
+const PORTAL_DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREENCAST_INTERFACE: &str = "org.freedesktop.portal.ScreenCast";
+
+/// Error returned when the user dismisses the portal dialog
+pub const CANCELLED: &str = "Screen sharing was cancelled";
+
+/// How long the user gets to answer a portal dialog
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// `source_id` prefix asking for a portal screencast ("portal:monitor" / "portal:window")
+pub const PORTAL_PREFIX: &str = "portal:";
+/// `source_id` prefix of a negotiated PipeWire stream ("pipewire:<fd>:<node>")
+pub const PIPEWIRE_PREFIX: &str = "pipewire:";
+
+/// What the portal dialog offers
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    Monitor,
+    Window,
+}
+
+impl SourceKind {
+    /// ScreenCast `types` bitmask
+    fn mask(self) -> u32 {
+        match self {
+            SourceKind::Monitor => 1,
+            SourceKind::Window => 2,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SourceKind::Monitor => "monitor",
+            SourceKind::Window => "window",
+        }
+    }
+}
+
+/// Result of a successful `Start`
+#[derive(Debug, Clone)]
+pub struct Started {
+    pub node_id: u32,
+    pub restore_token: Option<String>,
+}
+
+/// The ScreenCast portal calls we need, in the order they are made
+pub trait ScreenCastPortal: Send + Sync {
+    /// Returns the session handle
+    fn create_session(&self) -> Result<String, String>;
+    fn select_sources(&self, session: &str, kind: SourceKind, restore_token: Option<&str>) -> Result<(), String>;
+    fn start(&self, session: &str) -> Result<Started, String>;
+    fn open_pipewire_remote(&self, session: &str) -> Result<OwnedFd, String>;
+    fn close_session(&self, session: &str);
+}
+
+/// ScreenCast portal over D-Bus
+pub struct DbusPortal {
+    connection: Connection,
+    destination: String,
+    response_timeout: Duration,
+}
+
+/// Unique suffix for request and session handle tokens
+static NEXT_TOKEN: AtomicU32 = AtomicU32::new(1);
+
+fn handle_token() -> String {
+    format!("cinny{}_{}", std::process::id(), NEXT_TOKEN.fetch_add(1, Ordering::Relaxed))
+}
+
+impl DbusPortal {
+    /// Portal on the user's session bus
+    pub fn session() -> Result<Self, String> {
+        let connection = Connection::session()
+            .map_err(|e| format!("Failed to connect to the session bus: {}", e))?;
+        Ok(Self::with_connection(connection, PORTAL_DESTINATION))
+    }
+
+    /// Portal served by `destination` on an existing connection
+    pub fn with_connection(connection: Connection, destination: &str) -> Self {
+        Self {
+            connection,
+            destination: destination.to_string(),
+            response_timeout: RESPONSE_TIMEOUT,
+        }
+    }
+
+    fn screencast(&self) -> Result<Proxy<'_>, String> {
+        Proxy::new(&self.connection, self.destination.as_str(), PORTAL_PATH, SCREENCAST_INTERFACE)
+            .map_err(|e| format!("ScreenCast portal unavailable: {}", e))
+    }
+
+    /// Call a portal method that answers through a Request object and wait
+    /// for its `Response`. `options` gets the `handle_token` added.
+    fn request<'a, B>(
+        &self,
+        method: &str,
+        body: impl FnOnce(HashMap<&'a str, Value<'a>>) -> B,
+        mut options: HashMap<&'a str, Value<'a>>,
+    ) -> Result<HashMap<String, OwnedValue>, String>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        // Subscribe before calling: the Response may arrive before the reply
+        let token = handle_token();
+        let sender = self
+            .connection
+            .unique_name()
+            .ok_or("Session bus connection has no unique name")?
+            .trim_start_matches(':')
+            .replace('.', "_");
+        let request_path = format!("{}/request/{}/{}", PORTAL_PATH, sender, token);
+        let request = Proxy::new(
+            &self.connection,
+            self.destination.as_str(),
+            request_path.as_str(),
+            "org.freedesktop.portal.Request",
+        )
+        .map_err(|e| format!("{}: {}", method, e))?;
+        let mut responses = request
+            .receive_signal("Response")
+            .map_err(|e| format!("{}: {}", method, e))?;
+
+        options.insert("handle_token", Value::from(token.clone()));
+        let _: OwnedObjectPath = self
+            .screencast()?
+            .call(method, &body(options))
+            .map_err(|e| format!("{} failed: {}", method, e))?;
+
+        // The signal iterator can't time out, so it is drained on its own
+        // thread. After a timeout that thread stays parked on the request.
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = tx.send(responses.next());
+        });
+        let message = match rx.recv_timeout(self.response_timeout) {
+            Ok(Some(message)) => message,
+            Ok(None) | Err(RecvTimeoutError::Disconnected) => {
+                return Err(format!("{}: portal closed the request", method));
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // Dismiss the dialog so it doesn't linger without a caller
+                let _: Result<(), _> = request.call("Close", &());
+                return Err(format!(
+                    "{}: no answer from the portal after {}s",
+                    method,
+                    self.response_timeout.as_secs()
+                ));
+            }
+        };
+        let (code, results): (u32, HashMap<String, OwnedValue>) = message
+            .body()
+            .map_err(|e| format!("{}: invalid response: {}", method, e))?;
+        match code {
+            0 => Ok(results),
+            1 => Err(CANCELLED.to_string()),
+            _ => Err(format!("{} was rejected by the portal", method)),
+        }
+    }
+}
+
+impl ScreenCastPortal for DbusPortal {
+    fn create_session(&self) -> Result<String, String> {
+        let mut options = HashMap::new();
+        options.insert("session_handle_token", Value::from(handle_token()));
+        let results = self.request("CreateSession", |options| (options,), options)?;
+        results
+            .get("session_handle")
+            .and_then(|v| String::try_from(v.clone()).ok())
+            .ok_or_else(|| "CreateSession returned no session handle".to_string())
+    }
+
+    fn select_sources(&self, session: &str, kind: SourceKind, restore_token: Option<&str>) -> Result<(), String> {
+        let session = OwnedObjectPath::try_from(session).map_err(|e| e.to_string())?;
+        let mut options = HashMap::new();
+        options.insert("types", Value::from(kind.mask()));
+        options.insert("multiple", Value::from(false));
+        options.insert("cursor_mode", Value::from(2u32)); // Embedded in the stream
+        options.insert("persist_mode", Value::from(2u32)); // Until revoked
+        if let Some(token) = restore_token {
+            options.insert("restore_token", Value::from(token.to_string()));
+        }
+        self.request("SelectSources", |options| (session, options), options)?;
+        Ok(())
+    }
+
+    fn start(&self, session: &str) -> Result<Started, String> {
+        let session = OwnedObjectPath::try_from(session).map_err(|e| e.to_string())?;
+        let results = self.request("Start", |options| (session, "", options), HashMap::new())?;
+
+        let streams = results
+            .get("streams")
+            .and_then(|v| <Vec<(u32, HashMap<String, OwnedValue>)>>::try_from(v.clone()).ok())
+            .unwrap_or_default();
+        let node_id = streams
+            .first()
+            .map(|(node, _)| *node)
+            .ok_or("Portal returned no streams")?;
+        let restore_token = results
+            .get("restore_token")
+            .and_then(|v| String::try_from(v.clone()).ok());
+
+        Ok(Started { node_id, restore_token })
+    }
+
+    fn open_pipewire_remote(&self, session: &str) -> Result<OwnedFd, String> {
+        let session = OwnedObjectPath::try_from(session).map_err(|e| e.to_string())?;
+        let options: HashMap<&str, Value<'_>> = HashMap::new();
+        self.screencast()?
+            .call("OpenPipeWireRemote", &(session, options))
+            .map_err(|e| format!("OpenPipeWireRemote failed: {}", e))
+    }
+
+    fn close_session(&self, session: &str) {
+        let proxy = Proxy::new(
+            &self.connection,
+            self.destination.as_str(),
+            session,
+            "org.freedesktop.portal.Session",
+        );
+        if let Ok(proxy) = proxy {
+            let _: Result<(), _> = proxy.call("Close", &());
+        }
+    }
+}
+
+/// A running portal screencast. The portal session and PipeWire remote stay
+/// open until this is dropped, so it must outlive the pipeline using it.
+pub struct ScreenCast {
+    portal: Box<dyn ScreenCastPortal>,
+    session: String,
+    remote: OwnedFd,
+    pub node_id: u32,
+}
+
+impl ScreenCast {
+    /// `source_id` understood by `build_video_source`
+    pub fn source_id(&self) -> String {
+        format!("{}{}:{}", PIPEWIRE_PREFIX, self.remote.as_raw_fd(), self.node_id)
+    }
+}
+
+impl Drop for ScreenCast {
+    fn drop(&mut self) {
+        self.portal.close_session(&self.session);
+        log_to_file(&format!("Portal screencast closed (node {})", self.node_id));
+    }
+}
+
+/// Run the CreateSession / SelectSources / Start / OpenPipeWireRemote
+/// sequence. Returns the screencast and the new restore token.
+pub fn negotiate(
+    portal: Box<dyn ScreenCastPortal>,
+    kind: SourceKind,
+    restore_token: Option<&str>,
+) -> Result<(ScreenCast, Option<String>), String> {
+    let session = portal.create_session()?;
+    let opened = portal
+        .select_sources(&session, kind, restore_token)
+        .and_then(|_| portal.start(&session))
+        .and_then(|started| portal.open_pipewire_remote(&session).map(|remote| (started, remote)));
+
+    match opened {
+        Ok((started, remote)) => {
+            log_to_file(&format!("Portal screencast started (node {})", started.node_id));
+            Ok((
+                ScreenCast {
+                    portal,
+                    session,
+                    remote,
+                    node_id: started.node_id,
+                },
+                started.restore_token,
+            ))
+        }
+        Err(e) => {
+            portal.close_session(&session);
+            Err(e)
+        }
+    }
+}
+
+/// Parse a "pipewire:<fd>:<node>" source id
+pub fn parse_pipewire_source(source_id: &str) -> Option<(i64, u32)> {
+    let (fd, node) = source_id.strip_prefix(PIPEWIRE_PREFIX)?.split_once(':')?;
+    Some((fd.parse().ok()?, node.parse().ok()?))
+}
+
+/// Whether this is a Wayland session (where ximagesrc can't capture)
+pub fn is_wayland() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some()
+        || std::env::var("XDG_SESSION_TYPE").map(|t| t == "wayland").unwrap_or(false)
+}
+
+/// Display server the app runs under, which decides portal use and fallback
+#[derive(Debug, Clone, Copy)]
+struct DisplayServer {
+    wayland: bool,
+    /// X11 (or XWayland) reachable through `DISPLAY`
+    x11: bool,
+}
+
+impl DisplayServer {
+    fn current() -> Self {
+        Self {
+            wayland: is_wayland(),
+            x11: std::env::var_os("DISPLAY").is_some(),
+        }
+    }
+}
+
+/// File holding the restore token for `kind`. Tokens are single-use; every
+/// successful Start returns a fresh one.
+fn token_path(dir: &Path, kind: SourceKind) -> PathBuf {
+    dir.join(format!("screencast_restore_token_{}", kind.as_str()))
+}
+
+fn load_token(dir: &Path, kind: SourceKind) -> Option<String> {
+    let token = std::fs::read_to_string(token_path(dir, kind)).ok()?;
+    Some(token.trim().to_string()).filter(|t| !t.is_empty())
+}
+
+fn save_token(dir: &Path, kind: SourceKind, token: &str) {
+    let _ = std::fs::create_dir_all(dir);
+    if let Err(e) = std::fs::write(token_path(dir, kind), token) {
+        log_to_file(&format!("WARNING: failed to save portal restore token: {}", e));
+    }
+}
+
+/// Turn a requested `source_id` into one `build_video_source` can use.
+/// Portal sources (and any source on Wayland) go through the ScreenCast
+/// portal; if that fails on X11 the stream falls back to ximagesrc.
+/// "pipewire:" ids are only ever produced here, never taken from the caller,
+/// so `pipewiresrc` can't be pointed at an arbitrary fd or node.
+pub fn resolve<R: Runtime>(app: &AppHandle<R>, source_id: &str) -> Result<(String, Option<ScreenCast>), String> {
+    let token_dir = app.path_resolver().app_data_dir();
+    resolve_with(source_id, DisplayServer::current(), token_dir.as_deref(), || {
+        DbusPortal::session().map(|portal| Box::new(portal) as Box<dyn ScreenCastPortal>)
+    })
+}
+
+/// `resolve` with the display server, restore token folder and portal
+/// connection passed in
+fn resolve_with(
+    source_id: &str,
+    display: DisplayServer,
+    token_dir: Option<&Path>,
+    connect: impl FnOnce() -> Result<Box<dyn ScreenCastPortal>, String>,
+) -> Result<(String, Option<ScreenCast>), String> {
+    if testsrc::is_test(source_id) {
+        return Ok((source_id.to_string(), None));
+    }
+    if source_id.starts_with(PIPEWIRE_PREFIX) {
+        return Err(format!(
+            "PipeWire sources come from the portal; request \"{}monitor\" or \"{}window\" instead",
+            PORTAL_PREFIX, PORTAL_PREFIX
+        ));
+    }
+    let kind = match source_id.strip_prefix(PORTAL_PREFIX) {
+        Some("window") => SourceKind::Window,
+        Some(_) => SourceKind::Monitor,
+        None if display.wayland => SourceKind::Monitor,
+        None => return Ok((source_id.to_string(), None)),
+    };
+
+    let restore_token = token_dir.and_then(|dir| load_token(dir, kind));
+    log_to_file(&format!(
+        "Requesting {} screencast from portal (restore token: {})",
+        kind.as_str(),
+        if restore_token.is_some() { "present" } else { "none" }
+    ));
+
+    let result = connect().and_then(|portal| negotiate(portal, kind, restore_token.as_deref()));
+    match result {
+        Ok((screencast, token)) => {
+            if let (Some(dir), Some(token)) = (token_dir, token) {
+                save_token(dir, kind, &token);
+            }
+            Ok((screencast.source_id(), Some(screencast)))
+        }
+        // Never fall back to capturing the whole screen after the user said no
+        Err(e) if e != CANCELLED && !display.wayland && display.x11 => {
+            log_to_file(&format!("Portal screencast failed ({}), falling back to ximagesrc", e));
+            Ok(("monitor:0".to_string(), None))
+        }
+        Err(e) => Err(format!("Screen capture portal failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! The D-Bus tests run a mock ScreenCast portal on a private
+    //! `dbus-daemon`. They are ignored by default; run them with
+    //! `cargo test -- --ignored` where `dbus-daemon` is installed.
+
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::fd::{FromRawFd, IntoRawFd};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::{Arc, Mutex};
+    use zbus::blocking::ConnectionBuilder;
+    use zbus::{dbus_interface, fdo, MessageHeader, SignalContext};
+
+    const SESSION_PATH: &str = "/org/freedesktop/portal/desktop/session/test/1";
+    const NODE_ID: u32 = 42;
+
+    const WAYLAND: DisplayServer = DisplayServer { wayland: true, x11: true };
+    const X11: DisplayServer = DisplayServer { wayland: false, x11: true };
+
+    /// dbus-daemon on its own socket, killed on drop
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon not available");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn connect(&self) -> Connection {
+            ConnectionBuilder::address(self.address.as_str()).unwrap().build().unwrap()
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// What the app sent the mock portal
+    #[derive(Default)]
+    struct Calls {
+        restore_token: Option<String>,
+        closed: bool,
+    }
+
+    struct MockScreenCast {
+        /// Response code for SelectSources: 0 success, 1 cancelled, 2 other,
+        /// `None` to never answer
+        select_response: Option<u32>,
+        /// Token handed out by Start
+        restore_token: &'static str,
+        calls: Arc<Mutex<Calls>>,
+    }
+
+    /// Answer through the Request object the caller derived from its
+    /// unique name and `handle_token`, as xdg-desktop-portal does
+    async fn respond(
+        ctxt: &SignalContext<'_>,
+        header: &MessageHeader<'_>,
+        options: &HashMap<String, OwnedValue>,
+        code: u32,
+        results: HashMap<&str, Value<'_>>,
+    ) -> fdo::Result<OwnedObjectPath> {
+        let sender = header
+            .sender()?
+            .ok_or_else(|| fdo::Error::Failed("no sender".to_string()))?;
+        let token = options
+            .get("handle_token")
+            .and_then(|v| String::try_from(v.clone()).ok())
+            .ok_or_else(|| fdo::Error::InvalidArgs("no handle_token".to_string()))?;
+        let path = format!(
+            "{}/request/{}/{}",
+            PORTAL_PATH,
+            sender.trim_start_matches(':').replace('.', "_"),
+            token
+        );
+        ctxt.connection()
+            .emit_signal(
+                Some(sender.as_str()),
+                path.as_str(),
+                "org.freedesktop.portal.Request",
+                "Response",
+                &(code, results),
+            )
+            .await?;
+        OwnedObjectPath::try_from(path).map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    #[dbus_interface(name = "org.freedesktop.portal.ScreenCast")]
+    impl MockScreenCast {
+        async fn create_session(
+            &self,
+            options: HashMap<String, OwnedValue>,
+            #[zbus(header)] header: MessageHeader<'_>,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> fdo::Result<OwnedObjectPath> {
+            let mut results = HashMap::new();
+            results.insert("session_handle", Value::from(SESSION_PATH));
+            respond(&ctxt, &header, &options, 0, results).await
+        }
+
+        async fn select_sources(
+            &self,
+            _session: OwnedObjectPath,
+            options: HashMap<String, OwnedValue>,
+            #[zbus(header)] header: MessageHeader<'_>,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> fdo::Result<OwnedObjectPath> {
+            self.calls.lock().unwrap().restore_token = options
+                .get("restore_token")
+                .and_then(|v| String::try_from(v.clone()).ok());
+            match self.select_response {
+                Some(code) => respond(&ctxt, &header, &options, code, HashMap::new()).await,
+                None => OwnedObjectPath::try_from("/org/freedesktop/portal/desktop/request/unanswered")
+                    .map_err(|e| fdo::Error::Failed(e.to_string())),
+            }
+        }
+
+        async fn start(
+            &self,
+            _session: OwnedObjectPath,
+            _parent_window: String,
+            options: HashMap<String, OwnedValue>,
+            #[zbus(header)] header: MessageHeader<'_>,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> fdo::Result<OwnedObjectPath> {
+            let streams = vec![(NODE_ID, HashMap::<String, Value<'_>>::new())];
+            let mut results = HashMap::new();
+            results.insert("streams", Value::from(streams));
+            results.insert("restore_token", Value::from(self.restore_token));
+            respond(&ctxt, &header, &options, 0, results).await
+        }
+
+        #[dbus_interface(name = "OpenPipeWireRemote")]
+        fn open_pipewire_remote(
+            &self,
+            _session: OwnedObjectPath,
+            _options: HashMap<String, OwnedValue>,
+        ) -> fdo::Result<OwnedFd> {
+            let file = std::fs::File::open("/dev/null").map_err(|e| fdo::Error::IOError(e.to_string()))?;
+            Ok(unsafe { OwnedFd::from_raw_fd(file.into_raw_fd()) })
+        }
+    }
+
+    struct MockSession {
+        calls: Arc<Mutex<Calls>>,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.portal.Session")]
+    impl MockSession {
+        fn close(&self) {
+            self.calls.lock().unwrap().closed = true;
+        }
+    }
+
+    /// Serve a mock portal on `bus`; keep the returned connection alive
+    fn serve(bus: &PrivateBus, select_response: Option<u32>, calls: &Arc<Mutex<Calls>>) -> Connection {
+        let screencast = MockScreenCast {
+            select_response,
+            restore_token: "new-token",
+            calls: calls.clone(),
+        };
+        ConnectionBuilder::address(bus.address.as_str())
+            .unwrap()
+            .name(PORTAL_DESTINATION)
+            .unwrap()
+            .serve_at(PORTAL_PATH, screencast)
+            .unwrap()
+            .serve_at(SESSION_PATH, MockSession { calls: calls.clone() })
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    fn portal_on(bus: &PrivateBus) -> impl FnOnce() -> Result<Box<dyn ScreenCastPortal>, String> + '_ {
+        move || Ok(Box::new(DbusPortal::with_connection(bus.connect(), PORTAL_DESTINATION)) as Box<dyn ScreenCastPortal>)
+    }
+
+    fn unreachable_portal() -> Result<Box<dyn ScreenCastPortal>, String> {
+        Err("Failed to connect to the session bus".to_string())
+    }
+
+    /// Empty folder for restore tokens
+    fn token_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cinny-portal-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn restore_token_round_trip() {
+        let bus = PrivateBus::start();
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        let _server = serve(&bus, Some(0), &calls);
+        let dir = token_dir("restore");
+        save_token(&dir, SourceKind::Monitor, "old-token");
+
+        let (source_id, screencast) = resolve_with("portal:monitor", WAYLAND, Some(dir.as_path()), portal_on(&bus)).unwrap();
+        assert_eq!(parse_pipewire_source(&source_id).map(|(_, node)| node), Some(NODE_ID));
+        assert_eq!(calls.lock().unwrap().restore_token.as_deref(), Some("old-token"));
+        assert_eq!(load_token(&dir, SourceKind::Monitor).as_deref(), Some("new-token"));
+        assert_eq!(load_token(&dir, SourceKind::Window), None);
+
+        assert!(!calls.lock().unwrap().closed);
+        drop(screencast);
+        assert!(calls.lock().unwrap().closed);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn user_cancel_is_an_error_even_on_x11() {
+        let bus = PrivateBus::start();
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        let _server = serve(&bus, Some(1), &calls);
+        let dir = token_dir("cancel");
+
+        let err = resolve_with("portal:window", X11, Some(dir.as_path()), portal_on(&bus)).err().unwrap();
+        assert!(err.ends_with(CANCELLED), "{}", err);
+        assert!(calls.lock().unwrap().closed);
+        assert_eq!(load_token(&dir, SourceKind::Window), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn rejected_portal_falls_back_on_x11_only() {
+        let bus = PrivateBus::start();
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        let _server = serve(&bus, Some(2), &calls);
+
+        let (source_id, screencast) = resolve_with("portal:monitor", X11, None, portal_on(&bus)).unwrap();
+        assert_eq!(source_id, "monitor:0");
+        assert!(screencast.is_none());
+        assert!(resolve_with("portal:monitor", WAYLAND, None, portal_on(&bus)).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn unanswered_request_times_out() {
+        let bus = PrivateBus::start();
+        let calls = Arc::new(Mutex::new(Calls::default()));
+        let _server = serve(&bus, None, &calls);
+        let connect = || {
+            let mut portal = DbusPortal::with_connection(bus.connect(), PORTAL_DESTINATION);
+            portal.response_timeout = Duration::from_millis(200);
+            Ok(Box::new(portal) as Box<dyn ScreenCastPortal>)
+        };
+
+        let err = resolve_with("portal:monitor", WAYLAND, None, connect).err().unwrap();
+        assert!(err.contains("no answer from the portal"), "{}", err);
+        assert!(calls.lock().unwrap().closed);
+    }
+
+    #[test]
+    fn unreachable_portal_falls_back_on_x11_only() {
+        let (source_id, screencast) = resolve_with("portal:monitor", X11, None, unreachable_portal).unwrap();
+        assert_eq!(source_id, "monitor:0");
+        assert!(screencast.is_none());
+
+        let headless = DisplayServer { wayland: false, x11: false };
+        assert!(resolve_with("portal:monitor", headless, None, unreachable_portal).is_err());
+        assert!(resolve_with("monitor:1", WAYLAND, None, unreachable_portal).is_err());
+    }
+
+    #[test]
+    fn plain_sources_skip_the_portal() {
+        let never = || -> Result<Box<dyn ScreenCastPortal>, String> { panic!("portal contacted") };
+        assert_eq!(resolve_with("xid:123", X11, None, never).unwrap().0, "xid:123");
+        assert_eq!(resolve_with(testsrc::SOURCE_ID, WAYLAND, None, never).unwrap().0, testsrc::SOURCE_ID);
+    }
+
+    #[test]
+    fn raw_pipewire_ids_are_rejected() {
+        let never = || -> Result<Box<dyn ScreenCastPortal>, String> { panic!("portal contacted") };
+        assert!(resolve_with("pipewire:5:42", WAYLAND, None, never).is_err());
+        assert!(resolve_with("pipewire:5:42", X11, None, never).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Automatic WHIP reconnection
+//!
+//! When whipclientsink fails, only the sink is torn down and rebuilt. Capture
+//! branches stay PLAYING; their buffers are dropped at the branch tail while
+//! the sink is detached so sources never see a not-linked flow error.
+
+use gstreamer as gst;
+use gst::prelude::{Cast, ElementExt, ElementExtManual, GstBinExt, GstObjectExt, PadExt, PadExtManual};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tauri::{AppHandle, Runtime};
+
+use super::lifecycle::{self, ErrorKind, StreamState};
+use super::session::{self, StreamSession};
+use super::{build_whip_sink, log_scope, log_to_file, setup_whip, SharedState, StreamConfig};
+
+/// Opt-in reconnect policy for the WHIP sink
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconnectPolicy {
+    /// Maximum number of consecutive reconnect attempts
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the first attempt (doubles each attempt)
+    #[serde(default = "default_initial_delay_ms")]
+    pub initial_delay_ms: u64,
+    /// Upper bound for the delay between attempts
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_initial_delay_ms() -> u64 {
+    1000
+}
+
+fn default_max_delay_ms() -> u64 {
+    30_000
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_delay_ms: default_initial_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Backoff before attempt number `attempt` (1-based)
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(16);
+        Duration::from_millis(self.initial_delay_ms.saturating_mul(factor).min(self.max_delay_ms))
+    }
+}
+
+/// Capture branch tails cut off from the WHIP sink, dropping buffers
+pub struct DetachedSink {
+    tails: Vec<DetachedTail>,
+}
+
+struct DetachedTail {
+    src_pad: gst::Pad,
+    template: String,
+    drop_probe: Option<gst::PadProbeId>,
+}
+
+/// Whether a bus message source is (inside) the current WHIP sink
+pub fn is_from_whip(pipeline: &gst::Pipeline, src: &gst::Object) -> bool {
+    match pipeline.by_name("whip") {
+        Some(whip) => src == whip.upcast_ref::<gst::Object>() || src.has_as_ancestor(&whip),
+        None => false,
+    }
+}
+
+/// Unlink and remove the WHIP sink, leaving capture branches running
+pub fn detach_whip(pipeline: &gst::Pipeline) -> Result<DetachedSink, String> {
+    let whip = pipeline
+        .by_name("whip")
+        .ok_or("No whip element in pipeline")?;
+
+    let mut tails = Vec::new();
+    for sink_pad in whip.sink_pads() {
+        let template = sink_pad
+            .pad_template()
+            .map(|t| t.name_template().to_string())
+            .unwrap_or_else(|| "video_%u".to_string());
+
+        if let Some(src_pad) = sink_pad.peer() {
+            let drop_probe = src_pad.add_probe(
+                gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST,
+                |_, _| gst::PadProbeReturn::Drop,
+            );
+            let _ = src_pad.unlink(&sink_pad);
+            tails.push(DetachedTail {
+                src_pad,
+                template,
+                drop_probe,
+            });
+        }
+        whip.release_request_pad(&sink_pad);
+    }
+
+    let _ = whip.set_state(gst::State::Null);
+    pipeline
+        .remove(&whip)
+        .map_err(|e| format!("Failed to remove whip: {}", e))?;
+
+    log_to_file(&format!("WHIP sink detached ({} branches kept alive)", tails.len()));
+    Ok(DetachedSink { tails })
+}
+
+/// Build a fresh WHIP sink and relink the detached branches into it.
+/// `setup` connects the sink's signal handlers (see `setup_whip`) before it
+/// starts, so the first encoders it creates are configured too.
+/// On failure the new sink is removed and branches keep dropping buffers.
+pub fn attach_whip(
+    pipeline: &gst::Pipeline,
+    config: &StreamConfig,
+    detached: &mut DetachedSink,
+    setup: impl FnOnce(&gst::Element),
+) -> Result<gst::Element, String> {
+    let whip = build_whip_sink(config).make()?;
+    pipeline
+        .add(&whip)
+        .map_err(|e| format!("Failed to add whip: {}", e))?;
+
+    let mut linked = Vec::new();
+    for tail in &detached.tails {
+        let result = whip
+            .request_pad_simple(&tail.template)
+            .ok_or_else(|| format!("whip has no request pad '{}'", tail.template))
+            .and_then(|sink_pad| {
+                tail.src_pad
+                    .link(&sink_pad)
+                    .map(|_| sink_pad)
+                    .map_err(|e| format!("Failed to relink {}: {:?}", tail.src_pad.name(), e))
+            });
+        match result {
+            Ok(sink_pad) => linked.push(sink_pad),
+            Err(e) => {
+                discard_whip(pipeline, &whip, linked);
+                return Err(e);
+            }
+        }
+    }
+
+    setup(&whip);
+    if let Err(e) = whip.sync_state_with_parent() {
+        discard_whip(pipeline, &whip, linked);
+        return Err(format!("Failed to start new whip sink: {}", e));
+    }
+
+    // Let buffers flow again
+    for tail in &mut detached.tails {
+        if let Some(probe) = tail.drop_probe.take() {
+            tail.src_pad.remove_probe(probe);
+        }
+    }
+
+    let _ = pipeline.recalculate_latency();
+    log_to_file("WHIP sink rebuilt and relinked");
+    Ok(whip)
+}
+
+/// Unlink and release the request pads of a WHIP sink that failed to come
+/// up, then remove it. The branch tails keep their drop probes.
+fn discard_whip(pipeline: &gst::Pipeline, whip: &gst::Element, linked: Vec<gst::Pad>) {
+    for sink_pad in linked {
+        if let Some(peer) = sink_pad.peer() {
+            let _ = peer.unlink(&sink_pad);
+        }
+        whip.release_request_pad(&sink_pad);
+    }
+    let _ = whip.set_state(gst::State::Null);
+    let _ = pipeline.remove(whip);
+}
+
+/// A sink that stayed up this long resets the attempt counter
+pub const STABLE_AFTER: Duration = Duration::from_secs(30);
+
+/// Sleep for `delay`, returning false early if the stream is stopped
+fn sleep_while_running(shared: &SharedState, delay: Duration) -> bool {
+    let step = Duration::from_millis(100);
+    let mut waited = Duration::ZERO;
+    while waited < delay {
+        std::thread::sleep(step);
+        waited += step;
+        if !shared.is_running.lock().map(|r| *r).unwrap_or(false) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Detach the failed sink and retry with backoff on a separate thread, so
+/// the bus keeps being drained while waiting. Returns false without touching
+/// the pipeline if attempts are exhausted or the sink can't be removed. When
+/// the retries run out, the session is failed from the retry thread.
+pub fn start_reconnect<R: Runtime>(app: &AppHandle<R>, session: &Arc<StreamSession>, policy: &ReconnectPolicy, attempts: &Arc<AtomicU32>) -> bool {
+    if attempts.load(Ordering::SeqCst) >= policy.max_attempts {
+        log_to_file(&format!("Reconnect attempts exhausted ({})", policy.max_attempts));
+        return false;
+    }
+
+    let mut detached = match detach_whip(&session.pipeline) {
+        Ok(d) => d,
+        Err(e) => {
+            log_to_file(&format!("Failed to detach whip sink: {}", e));
+            return false;
+        }
+    };
+    lifecycle::transition(app, &session.shared, StreamState::Reconnecting, Some("whip"), None, None);
+
+    let app = app.clone();
+    let weak_session = Arc::downgrade(session);
+    let shared = session.shared.clone();
+    let policy = policy.clone();
+    let attempts = attempts.clone();
+    std::thread::spawn(move || {
+        let _log = log_scope(&shared.session_id);
+        let mut last_error = String::new();
+        while attempts.load(Ordering::SeqCst) < policy.max_attempts {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            let delay = policy.delay_for(attempt);
+            log_to_file(&format!("Reconnect attempt {}/{} in {:?}", attempt, policy.max_attempts, delay));
+            lifecycle::report_reconnect(&app, &shared, attempt, policy.max_attempts, delay.as_millis() as u64);
+
+            if !sleep_while_running(&shared, delay) {
+                log_to_file("Stop requested during reconnect");
+                return;
+            }
+            let session = match weak_session.upgrade() {
+                Some(s) => s,
+                None => return,
+            };
+
+            // Config updates swap the sink too; don't interleave with one
+            let _switching = session.switching.lock().unwrap();
+            let config = session.config.lock().unwrap().clone();
+            let result = attach_whip(&session.pipeline, &config, &mut detached, |whip| {
+                setup_whip(whip, &config, session.encoder_factory, &shared)
+            });
+            match result {
+                Ok(_) => {
+                    lifecycle::transition(&app, &shared, StreamState::Connecting, Some("whip"), None, None);
+                    return;
+                }
+                Err(e) => {
+                    log_to_file(&format!("Reconnect attempt {} failed: {}", attempt, e));
+                    lifecycle::report_error(&app, &shared, "whip", ErrorKind::Unknown, e.clone());
+                    last_error = e;
+                }
+            }
+        }
+
+        if let Some(session) = weak_session.upgrade() {
+            let msg = format!("Reconnect failed after {} attempts: {}", policy.max_attempts, last_error);
+            log_to_file(&msg);
+            session::fail(&app, &session, "whip", ErrorKind::Negotiation, msg);
+        }
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(initial_delay_ms: u64, max_delay_ms: u64) -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: 5,
+            initial_delay_ms,
+            max_delay_ms,
+        }
+    }
+
+    #[test]
+    fn delay_doubles_per_attempt() {
+        let policy = policy(1000, 30_000);
+        let delays: Vec<u64> = (1..=5).map(|n| policy.delay_for(n).as_millis() as u64).collect();
+        assert_eq!(delays, [1000, 2000, 4000, 8000, 16_000]);
+        // Attempt 0 is treated like the first
+        assert_eq!(policy.delay_for(0), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let policy = policy(1000, 30_000);
+        assert_eq!(policy.delay_for(6), Duration::from_millis(30_000));
+        assert_eq!(policy.delay_for(100), Duration::from_millis(30_000));
+        assert_eq!(policy.delay_for(u32::MAX), Duration::from_millis(30_000));
+    }
+
+    #[test]
+    fn huge_delays_do_not_overflow() {
+        let policy = policy(u64::MAX / 2, u64::MAX);
+        assert_eq!(policy.delay_for(20), Duration::from_millis(u64::MAX));
+    }
+}
@@ -0,0 +1,352 @@
+//! Local recording of the capture pipeline
+//!
+//! A recorder is a bin attached to the capture tees (see `tap`). It encodes
+//! the raw video itself; audio is already Opus and is muxed as-is. MP4 output
+//! is fragmented and Matroska is written progressively, so a crash loses at
+//! most the last fragment instead of the whole file.
+
+use gstreamer as gst;
+use gst::prelude::{ElementExt, ElementExtManual, GstBinExt, GstBinExtManual, PadExt, PadExtManual};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tauri::{AppHandle, Runtime};
+
+use super::pipeline::{link_to_request_pad, ElementSpec};
+use super::tap::{self, TeeLink};
+use super::{log_to_file, StreamConfig};
+
+/// Name of the recorder bin inside the pipeline
+const RECORDER_NAME: &str = "recorder";
+
+/// MP4 fragment length
+const FRAGMENT_MS: i64 = 1000;
+
+/// How long `stop` waits for the muxer to finalize the file
+const FINALIZE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Container for local recordings
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    Mp4, // Fragmented MP4
+    Mkv, // Matroska
+}
+
+impl Default for RecordingFormat {
+    fn default() -> Self {
+        RecordingFormat::Mp4
+    }
+}
+
+impl RecordingFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Mp4 => "mp4",
+            RecordingFormat::Mkv => "mkv",
+        }
+    }
+
+    fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            "mp4" => Some(RecordingFormat::Mp4),
+            "mkv" => Some(RecordingFormat::Mkv),
+            _ => None,
+        }
+    }
+
+    fn muxer(self) -> ElementSpec {
+        match self {
+            RecordingFormat::Mp4 => ElementSpec::new("mp4mux").int("fragment-duration", FRAGMENT_MS),
+            RecordingFormat::Mkv => ElementSpec::new("matroskamux"),
+        }
+    }
+}
+
+/// Where and how to record
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordingTarget {
+    #[serde(default)]
+    pub format: RecordingFormat,
+    #[serde(default)]
+    pub directory: Option<String>, // Defaults to <Videos>/Cinny
+}
+
+/// A recording file on disk
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingInfo {
+    pub path: String,
+    pub format: RecordingFormat,
+    pub size_bytes: u64,
+    pub modified: u64, // Unix seconds
+}
+
+impl RecordingInfo {
+    fn from_path(path: &Path) -> Option<Self> {
+        let format = RecordingFormat::from_extension(path.extension()?.to_str()?)?;
+        let metadata = std::fs::metadata(path).ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Some(Self {
+            path: path.to_string_lossy().to_string(),
+            format,
+            size_bytes: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// Resolve (and create) the directory recordings are written to
+pub fn recordings_dir<R: Runtime>(app: &AppHandle<R>, directory: Option<&str>) -> Result<PathBuf, String> {
+    let dir = match directory {
+        Some(d) if !d.trim().is_empty() => PathBuf::from(d),
+        _ => tauri::api::path::video_dir()
+            .map(|d| d.join("Cinny"))
+            .or_else(|| app.path_resolver().app_data_dir().map(|d| d.join("recordings")))
+            .ok_or("Could not determine a recordings directory")?,
+    };
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create recordings directory {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+/// Recordings in `dir`, newest first
+pub fn list(dir: &Path) -> Result<Vec<RecordingInfo>, String> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut recordings: Vec<RecordingInfo> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| RecordingInfo::from_path(&entry.path()))
+        .collect();
+    recordings.sort_by(|a, b| b.modified.cmp(&a.modified));
+    Ok(recordings)
+}
+
+/// Whether a bus message source is (inside) the recorder bin
+pub fn is_from_recorder(pipeline: &gst::Pipeline, src: &gst::Object) -> bool {
+    tap::is_from(pipeline, RECORDER_NAME, src)
+}
+
+/// An active recording attached to the pipeline's tees
+pub struct Recorder {
+    bin: gst::Bin,
+    links: Vec<TeeLink>,
+    stopping: Arc<AtomicBool>,
+    finished: mpsc::Receiver<()>,
+    pub path: PathBuf,
+    started: Instant,
+}
+
+/// Build the recorder bin: one ghost sink pad per branch, all muxed into `path`
+fn build_bin(
+    config: &StreamConfig,
+    encoder_factory: &'static str,
+    format: RecordingFormat,
+    path: &Path,
+    with_audio: bool,
+) -> Result<gst::Bin, String> {
+    let bin = gst::Bin::builder().name(RECORDER_NAME).build();
+
+    let mux = format.muxer().make()?;
+    // Not async: the bin joins a pipeline that is already PLAYING
+    let filesink = ElementSpec::new("filesink")
+        .named("recorder_filesink")
+        .str("location", &path.to_string_lossy())
+        .bool("async", false)
+        .make()?;
+    bin.add_many([&mux, &filesink])
+        .map_err(|e| format!("Failed to add muxer to recorder: {}", e))?;
+    mux.link(&filesink)
+        .map_err(|e| format!("Failed to link muxer to filesink: {}", e))?;
+
+    let video = tap::add_branch(&bin, "video", &tap::video_encode_chain(config, encoder_factory, config.fps * 2))?;
+    link_to_request_pad(&video, &mux, "video_%u")?;
+    if with_audio {
+        // Audio is already Opus
+        let audio = tap::add_branch(&bin, "audio", &[tap::consumer_queue()])?;
+        link_to_request_pad(&audio, &mux, "audio_%u")?;
+    }
+
+    Ok(bin)
+}
+
+/// Start recording the running pipeline to a new file
+pub fn start<R: Runtime>(
+    app: &AppHandle<R>,
+    session_id: &str,
+    pipeline: &gst::Pipeline,
+    config: &StreamConfig,
+    encoder_factory: &'static str,
+    target: &RecordingTarget,
+) -> Result<Recorder, String> {
+    if pipeline.by_name(RECORDER_NAME).is_some() {
+        return Err("Already recording".to_string());
+    }
+    if pipeline.by_name("video_tee").is_none() {
+        return Err("No video branch to record".to_string());
+    }
+
+    if target.format == RecordingFormat::Mp4 && !config.codec.fits_mp4() {
+        return Err(format!("{} can't be recorded to MP4, use Matroska", config.codec.as_str().to_uppercase()));
+    }
+
+    let dir = recordings_dir(app, target.directory.as_deref())?;
+    let path = unique_path(&dir, "recording", session_id, target.format.extension());
+    let with_audio = pipeline.by_name("audio_tee").is_some();
+    let bin = build_bin(config, encoder_factory, target.format, &path, with_audio)?;
+
+    // Swallow the EOS sent by `stop` so it ends this file without reaching
+    // the pipeline; a natural EOS (capture ended) passes through.
+    let stopping = Arc::new(AtomicBool::new(false));
+    let (finished_tx, finished) = mpsc::sync_channel(1);
+    if let Some(sink_pad) = bin.by_name("recorder_filesink").and_then(|sink| sink.static_pad("sink")) {
+        let stopping = stopping.clone();
+        sink_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                if event.type_() == gst::EventType::Eos {
+                    let _ = finished_tx.try_send(());
+                    if stopping.load(Ordering::SeqCst) {
+                        return gst::PadProbeReturn::Drop;
+                    }
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    // Shift timestamps so the file starts at zero
+    let offset = pipeline
+        .current_running_time()
+        .map(|t| t.nseconds() as i64)
+        .unwrap_or(0);
+    for pad in bin.sink_pads() {
+        pad.set_offset(-offset);
+    }
+
+    let links = tap::attach(pipeline, &bin)?;
+
+    log_to_file(&format!(
+        "Recording started: {} (encoder: {})",
+        path.display(),
+        encoder_factory
+    ));
+    Ok(Recorder {
+        bin,
+        links,
+        stopping,
+        finished,
+        path,
+        started: Instant::now(),
+    })
+}
+
+/// Current time in Unix seconds, used in file names
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// New file in `dir` named after the session and the current time, with a
+/// counter appended when two files land in the same second
+pub fn unique_path(dir: &Path, kind: &str, session_id: &str, extension: &str) -> PathBuf {
+    let stem = format!("cinny-{}-{}-{}", kind, file_name_part(session_id), unix_now());
+    let mut path = dir.join(format!("{}.{}", stem, extension));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", stem, n, extension));
+        n += 1;
+    }
+    path
+}
+
+/// `text` reduced to characters that are safe in a file name on every
+/// platform, so an id can't point outside the recordings folder
+pub fn file_name_part(text: &str) -> String {
+    text.chars()
+        .take(64)
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+impl Recorder {
+    /// Seconds since the recording started
+    pub fn elapsed_seconds(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    /// Finalize the file and detach from the pipeline. The rest of the
+    /// pipeline (and the WHIP stream, if any) keeps running.
+    pub fn stop(self, pipeline: &gst::Pipeline) -> Result<RecordingInfo, String> {
+        self.stopping.store(true, Ordering::SeqCst);
+
+        // Cut each branch off between buffers, then end it
+        for link in &self.links {
+            link.pad().add_probe(gst::PadProbeType::IDLE, |pad, _| {
+                if let Some(peer) = pad.peer() {
+                    let _ = pad.unlink(&peer);
+                    peer.send_event(gst::event::Eos::new());
+                }
+                gst::PadProbeReturn::Remove
+            });
+        }
+
+        if self.finished.recv_timeout(FINALIZE_TIMEOUT).is_err() {
+            log_to_file(&format!(
+                "WARNING: recording {} did not finalize within {:?}, last fragment may be lost",
+                self.path.display(),
+                FINALIZE_TIMEOUT
+            ));
+        }
+
+        tap::detach(pipeline, &self.bin, &self.links);
+        log_to_file(&format!(
+            "Recording stopped after {}s: {}",
+            self.elapsed_seconds(),
+            self.path.display()
+        ));
+
+        RecordingInfo::from_path(&self.path)
+            .ok_or_else(|| format!("Recording {} was not written", self.path.display()))
+    }
+
+    /// Detach without finalizing (used when the recorder itself failed)
+    pub fn abort(self, pipeline: &gst::Pipeline) {
+        self.stopping.store(true, Ordering::SeqCst);
+        tap::detach(pipeline, &self.bin, &self.links);
+        log_to_file(&format!("Recording aborted: {}", self.path.display()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_path_names_the_session_and_skips_taken_names() {
+        let dir = std::env::temp_dir().join(format!("cinny-recording-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let first = unique_path(&dir, "recording", "stream/1", "mp4");
+        let name = first.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("cinny-recording-stream_1-"), "{}", name);
+        assert!(name.ends_with(".mp4"), "{}", name);
+
+        std::fs::write(&first, b"").unwrap();
+        let second = unique_path(&dir, "recording", "stream/1", "mp4");
+        assert_ne!(first, second);
+        assert!(!second.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
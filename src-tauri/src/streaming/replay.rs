@@ -0,0 +1,417 @@
+//! Instant replay buffer
+//!
+//! Keeps the last N seconds of encoded video and audio in memory, fed from
+//! the capture tees (see `tap`). Saving writes a snapshot of the buffer to an
+//! MP4 file through a short-lived appsrc -> mp4mux -> filesink pipeline, so
+//! the live pipeline is never touched.
+
+use gstreamer as gst;
+use gst::prelude::{ElementExt, GstBinExt, GstBinExtManual, ObjectExt, PadExtManual};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::pipeline::{link_to_request_pad, ElementSpec};
+use super::tap::{self, TeeLink};
+use super::{log_to_file, StreamConfig};
+
+/// Name of the replay bin inside the pipeline
+const REPLAY_NAME: &str = "replay";
+
+/// Upper bound for the buffer length
+const MAX_SECONDS: u32 = 300;
+
+/// How long writing a clip may take
+const SAVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Replay buffer settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    /// Seconds of history to keep (capped at 300)
+    #[serde(default = "default_seconds")]
+    pub seconds: u32,
+}
+
+fn default_seconds() -> u32 {
+    30
+}
+
+/// A saved replay clip
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayClip {
+    pub path: String,
+    pub duration_seconds: f64,
+    pub size_bytes: u64,
+}
+
+/// Encoded buffers of one branch, oldest first
+#[derive(Clone, Default)]
+struct Track {
+    caps: Option<gst::Caps>,
+    buffers: VecDeque<gst::Buffer>,
+}
+
+impl Track {
+    fn set_caps(&mut self, caps: gst::Caps) {
+        // Buffers encoded with other caps can't go in the same file
+        if self.caps.as_ref() != Some(&caps) {
+            self.buffers.clear();
+            self.caps = Some(caps);
+        }
+    }
+}
+
+fn is_keyframe(buffer: &gst::Buffer) -> bool {
+    !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT)
+}
+
+/// Rolling window of encoded video and audio
+#[derive(Clone)]
+pub struct Snapshot {
+    window: gst::ClockTime,
+    video: Track,
+    audio: Track,
+}
+
+impl Snapshot {
+    fn push_video(&mut self, buffer: gst::Buffer) {
+        // A clip must start on a keyframe to decode from its first frame
+        if self.video.buffers.is_empty() && !is_keyframe(&buffer) {
+            return;
+        }
+        self.video.buffers.push_back(buffer);
+        self.trim();
+    }
+
+    fn push_audio(&mut self, buffer: gst::Buffer) {
+        self.audio.buffers.push_back(buffer);
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let newest = match self.video.buffers.back().and_then(|b| b.pts()) {
+            Some(pts) => pts,
+            None => {
+                // No video yet: bound audio on its own
+                if let Some(newest) = self.audio.buffers.back().and_then(|b| b.pts()) {
+                    let start = newest.saturating_sub(self.window);
+                    while self.audio.buffers.front().and_then(|b| b.pts()).map(|p| p < start).unwrap_or(false) {
+                        self.audio.buffers.pop_front();
+                    }
+                }
+                return;
+            }
+        };
+
+        // Drop whole GOPs while the next keyframe still leaves a full window
+        loop {
+            let next_key = self.video.buffers.iter().skip(1).position(is_keyframe).map(|i| i + 1);
+            match next_key {
+                Some(k) if self.video.buffers[k].pts().map(|p| newest.saturating_sub(p) >= self.window).unwrap_or(false) => {
+                    self.video.buffers.drain(..k);
+                }
+                _ => break,
+            }
+        }
+
+        // Keep audio from the first video frame on
+        if let Some(start) = self.video.buffers.front().and_then(|b| b.pts()) {
+            while self.audio.buffers.front().and_then(|b| b.pts()).map(|p| p < start).unwrap_or(false) {
+                self.audio.buffers.pop_front();
+            }
+        }
+    }
+
+    /// Earliest timestamp in the snapshot; clips are rebased to start here
+    fn base(&self) -> Option<gst::ClockTime> {
+        self.video
+            .buffers
+            .iter()
+            .chain(self.audio.buffers.iter())
+            .filter_map(|b| b.dts_or_pts())
+            .min()
+    }
+
+    fn duration(&self) -> gst::ClockTime {
+        let first = self.video.buffers.front().and_then(|b| b.pts());
+        let last = self.video.buffers.back().and_then(|b| b.pts());
+        match (first, last) {
+            (Some(first), Some(last)) => last.saturating_sub(first),
+            _ => gst::ClockTime::ZERO,
+        }
+    }
+}
+
+/// Whether a bus message source is (inside) the replay bin
+pub fn is_from_replay(pipeline: &gst::Pipeline, src: &gst::Object) -> bool {
+    tap::is_from(pipeline, REPLAY_NAME, src)
+}
+
+/// The replay buffer attached to the pipeline's tees
+pub struct ReplayBuffer {
+    bin: gst::Bin,
+    links: Vec<TeeLink>,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+/// End a replay branch in a fakesink whose probe feeds the snapshot
+fn collect(bin: &gst::Bin, last: &gst::Element, snapshot: Arc<Mutex<Snapshot>>, video: bool) -> Result<(), String> {
+    let sink = ElementSpec::new("fakesink")
+        .bool("sync", false)
+        .bool("async", false)
+        .make()?;
+    bin.add(&sink)
+        .map_err(|e| format!("Failed to add replay sink: {}", e))?;
+    last.link(&sink)
+        .map_err(|e| format!("Failed to link replay sink: {}", e))?;
+
+    let pad = sink.static_pad("sink").ok_or("Replay sink has no sink pad")?;
+    pad.add_probe(gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        if let Ok(mut snapshot) = snapshot.lock() {
+            match info.data {
+                Some(gst::PadProbeData::Buffer(ref buffer)) => {
+                    if video {
+                        snapshot.push_video(buffer.clone());
+                    } else {
+                        snapshot.push_audio(buffer.clone());
+                    }
+                }
+                Some(gst::PadProbeData::Event(ref event)) => {
+                    if let gst::EventView::Caps(caps) = event.view() {
+                        let track = if video { &mut snapshot.video } else { &mut snapshot.audio };
+                        track.set_caps(caps.caps_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+
+    Ok(())
+}
+
+/// Start buffering the running pipeline
+pub fn start(
+    pipeline: &gst::Pipeline,
+    config: &StreamConfig,
+    encoder_factory: &'static str,
+    replay: &ReplayConfig,
+) -> Result<ReplayBuffer, String> {
+    if pipeline.by_name(REPLAY_NAME).is_some() {
+        return Err("Replay buffer already running".to_string());
+    }
+    if pipeline.by_name("video_tee").is_none() {
+        return Err("No video branch to buffer".to_string());
+    }
+    // Clips are saved as MP4
+    if !config.codec.fits_mp4() {
+        return Err(format!("The replay buffer does not support {}", config.codec.as_str().to_uppercase()));
+    }
+
+    let seconds = replay.seconds.clamp(1, MAX_SECONDS);
+    let snapshot = Arc::new(Mutex::new(Snapshot {
+        window: gst::ClockTime::from_seconds(seconds as u64),
+        video: Track::default(),
+        audio: Track::default(),
+    }));
+
+    let bin = gst::Bin::builder().name(REPLAY_NAME).build();
+    // A keyframe every second keeps clips close to the requested length
+    let video = tap::add_branch(&bin, "video", &tap::video_encode_chain(config, encoder_factory, config.fps))?;
+    collect(&bin, &video, snapshot.clone(), true)?;
+    if pipeline.by_name("audio_tee").is_some() {
+        let audio = tap::add_branch(&bin, "audio", &[tap::consumer_queue()])?;
+        collect(&bin, &audio, snapshot.clone(), false)?;
+    }
+
+    let links = tap::attach(pipeline, &bin)?;
+    log_to_file(&format!("Replay buffer started ({}s, encoder: {})", seconds, encoder_factory));
+
+    Ok(ReplayBuffer { bin, links, snapshot })
+}
+
+impl ReplayBuffer {
+    /// Copy of the buffered window (buffers are shared, not copied)
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.lock().unwrap().clone()
+    }
+
+    /// Detach from the pipeline and drop the buffered data
+    pub fn stop(self, pipeline: &gst::Pipeline) {
+        tap::detach(pipeline, &self.bin, &self.links);
+        log_to_file("Replay buffer stopped");
+    }
+}
+
+/// Feed one track into an appsrc, rebased to `base`, then end it
+fn push_track(src: &gst::Element, track: &Track, base: gst::ClockTime) -> Result<(), String> {
+    for buffer in &track.buffers {
+        let mut buffer = buffer.copy();
+        if let Some(b) = buffer.get_mut() {
+            b.set_pts(buffer_time(b.pts(), base));
+            b.set_dts(buffer_time(b.dts(), base));
+        }
+        let ret = src.emit_by_name::<gst::FlowReturn>("push-buffer", &[&buffer]);
+        if ret != gst::FlowReturn::Ok {
+            return Err(format!("Failed to write replay buffer: {:?}", ret));
+        }
+    }
+    let _ = src.emit_by_name::<gst::FlowReturn>("end-of-stream", &[]);
+    Ok(())
+}
+
+fn buffer_time(time: Option<gst::ClockTime>, base: gst::ClockTime) -> Option<gst::ClockTime> {
+    time.map(|t| t.saturating_sub(base))
+}
+
+/// Write a snapshot to `path` as MP4
+pub fn save(snapshot: &Snapshot, path: &Path) -> Result<ReplayClip, String> {
+    let video_caps = match snapshot.video.caps {
+        Some(ref caps) if !snapshot.video.buffers.is_empty() => caps.clone(),
+        _ => return Err("Replay buffer is empty".to_string()),
+    };
+    let base = snapshot.base().unwrap_or(gst::ClockTime::ZERO);
+
+    let pipeline = gst::Pipeline::new();
+    let mux = ElementSpec::new("mp4mux").make()?;
+    let filesink = ElementSpec::new("filesink")
+        .str("location", &path.to_string_lossy())
+        .make()?;
+    pipeline
+        .add_many([&mux, &filesink])
+        .map_err(|e| format!("Failed to build replay writer: {}", e))?;
+    mux.link(&filesink)
+        .map_err(|e| format!("Failed to link replay writer: {}", e))?;
+
+    let mut tracks = vec![(&snapshot.video, video_caps, "video_%u")];
+    if let Some(ref caps) = snapshot.audio.caps {
+        if !snapshot.audio.buffers.is_empty() {
+            tracks.push((&snapshot.audio, caps.clone(), "audio_%u"));
+        }
+    }
+
+    let mut sources = Vec::new();
+    for (track, caps, mux_pad) in tracks {
+        let src = ElementSpec::new("appsrc")
+            .enum_nick("format", "time")
+            .int("max-bytes", 0) // The whole clip is queued up front
+            .make()?;
+        src.set_property("caps", &caps);
+        pipeline
+            .add(&src)
+            .map_err(|e| format!("Failed to add replay source: {}", e))?;
+        link_to_request_pad(&src, &mux, mux_pad)?;
+        sources.push((src, track));
+    }
+
+    let result = (|| {
+        pipeline
+            .set_state(gst::State::Playing)
+            .map_err(|e| format!("Failed to start replay writer: {:?}", e))?;
+        for (src, track) in &sources {
+            push_track(src, track, base)?;
+        }
+
+        let bus = pipeline.bus().ok_or("Replay writer has no bus")?;
+        let msg = bus.timed_pop_filtered(
+            gst::ClockTime::from_seconds(SAVE_TIMEOUT.as_secs()),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        );
+        match msg.as_ref().map(|m| m.view()) {
+            Some(gst::MessageView::Eos(..)) => Ok(()),
+            Some(gst::MessageView::Error(err)) => Err(format!("Failed to write replay: {}", err.error())),
+            _ => Err(format!("Timed out writing replay after {:?}", SAVE_TIMEOUT)),
+        }
+    })();
+    let _ = pipeline.set_state(gst::State::Null);
+    result?;
+
+    let size_bytes = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let duration_seconds = snapshot.duration().nseconds() as f64 / 1e9;
+    log_to_file(&format!("Replay saved: {} ({:.1}s, {} bytes)", path.display(), duration_seconds, size_bytes));
+
+    Ok(ReplayClip {
+        path: path.to_string_lossy().to_string(),
+        duration_seconds,
+        size_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOP_MS: u64 = 1000;
+    const FRAME_MS: u64 = 100;
+
+    fn buffer(ms: u64, keyframe: bool) -> gst::Buffer {
+        let mut buffer = gst::Buffer::new();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_mseconds(ms));
+            if !keyframe {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        buffer
+    }
+
+    fn snapshot(seconds: u64) -> Snapshot {
+        gst::init().unwrap();
+        Snapshot {
+            window: gst::ClockTime::from_seconds(seconds),
+            video: Track::default(),
+            audio: Track::default(),
+        }
+    }
+
+    fn first_pts(track: &Track) -> gst::ClockTime {
+        track.buffers.front().and_then(|b| b.pts()).unwrap()
+    }
+
+    #[test]
+    fn clip_starts_on_a_keyframe() {
+        let mut snapshot = snapshot(3);
+        // Joined mid-GOP: deltas before the first keyframe are useless
+        snapshot.push_video(buffer(0, false));
+        snapshot.push_video(buffer(FRAME_MS, false));
+        assert!(snapshot.video.buffers.is_empty());
+
+        for ms in (GOP_MS..10_000).step_by(FRAME_MS as usize) {
+            snapshot.push_video(buffer(ms, ms % GOP_MS == 0));
+            assert!(is_keyframe(snapshot.video.buffers.front().unwrap()), "at {}ms", ms);
+        }
+    }
+
+    #[test]
+    fn trim_keeps_the_configured_window() {
+        let window = gst::ClockTime::from_seconds(3);
+        let mut snapshot = snapshot(3);
+        for ms in (0..10_000).step_by(FRAME_MS as usize) {
+            snapshot.push_video(buffer(ms, ms % GOP_MS == 0));
+            snapshot.push_audio(buffer(ms, true));
+
+            let newest = gst::ClockTime::from_mseconds(ms);
+            let kept = newest - first_pts(&snapshot.video);
+            if newest >= window {
+                // At least the window, at most one GOP more
+                assert!(kept >= window, "{} kept at {}ms", kept, ms);
+                assert!(kept < window + gst::ClockTime::from_mseconds(GOP_MS), "{} kept at {}ms", kept, ms);
+            }
+            assert!(first_pts(&snapshot.audio) >= first_pts(&snapshot.video));
+        }
+        assert_eq!(snapshot.duration(), gst::ClockTime::from_mseconds(9_900 - 6_000));
+    }
+
+    #[test]
+    fn audio_alone_is_bounded_by_the_window() {
+        let mut snapshot = snapshot(2);
+        for ms in (0..5_000).step_by(FRAME_MS as usize) {
+            snapshot.push_audio(buffer(ms, true));
+        }
+        assert_eq!(first_pts(&snapshot.audio), gst::ClockTime::from_mseconds(4_900 - 2_000));
+    }
+}
@@ -0,0 +1,378 @@
+//! Native stream sessions
+//!
+//! Every `start_stream` call creates a session with its own pipeline, config,
+//! lifecycle state and bus watcher. `StreamingState` keys them by session id
+//! so several streams (e.g. a game window to one room, a second monitor to
+//! another) can run at once.
+
+use gstreamer as gst;
+use gst::prelude::{Cast, ElementExt, GstObjectExt};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tauri::{AppHandle, Manager, Runtime};
+
+#[cfg(target_os = "linux")]
+use super::portal::ScreenCast;
+use super::encoder::VideoCodec;
+use super::lifecycle::{self, ErrorKind, StreamState};
+use super::outputs::Output;
+use super::recording::{self, Recorder};
+use super::replay::{self, ReplayBuffer};
+use super::{log_debug, log_scope, log_to_file, reconnect, SharedState, StreamConfig, StreamStatus, StreamingState};
+
+/// One running native stream
+pub struct StreamSession {
+    pub id: String,
+    pub pipeline: gst::Pipeline,
+    pub config: Mutex<StreamConfig>,
+    /// H.264 encoder used by this session (whipclientsink and local encoders)
+    pub encoder_factory: &'static str,
+    pub recorder: Mutex<Option<Recorder>>,
+    pub replay: Mutex<Option<ReplayBuffer>>,
+    /// RTMP / SRT destinations
+    pub outputs: Mutex<Vec<Output>>,
+    /// Held while the capture source is being switched or the slate toggled
+    pub switching: Mutex<()>,
+    /// Slate shown and audio muted (`pause_stream`)
+    pub paused: Mutex<bool>,
+    /// Portal screencast feeding pipewiresrc; must outlive the pipeline
+    #[cfg(target_os = "linux")]
+    pub screencast: Mutex<Option<ScreenCast>>,
+    pub shared: SharedState,
+    start_time: Instant,
+}
+
+impl StreamSession {
+    pub fn new(pipeline: gst::Pipeline, config: StreamConfig, encoder_factory: &'static str, shared: SharedState) -> Self {
+        Self {
+            id: shared.session_id.clone(),
+            pipeline,
+            config: Mutex::new(config),
+            encoder_factory,
+            recorder: Mutex::new(None),
+            replay: Mutex::new(None),
+            outputs: Mutex::new(Vec::new()),
+            switching: Mutex::new(()),
+            paused: Mutex::new(false),
+            #[cfg(target_os = "linux")]
+            screencast: Mutex::new(None),
+            shared,
+            start_time: Instant::now(),
+        }
+    }
+
+    /// Whether this session publishes over WHIP
+    pub fn has_whip(&self) -> bool {
+        self.config.lock().unwrap().has_whip()
+    }
+
+    pub fn codec(&self) -> VideoCodec {
+        self.config.lock().unwrap().codec
+    }
+
+    /// Stopped by a fatal error, kept only to report it
+    pub fn is_failed(&self) -> bool {
+        *self.shared.state.lock().unwrap() == StreamState::Failed
+    }
+
+    pub fn status(&self) -> StreamStatus {
+        // Take one lock at a time; the bus thread locks some of these in another order
+        let (source_id, whip_url) = {
+            let config = self.config.lock().unwrap();
+            (config.source_id.clone(), Some(config.whip_url.clone()).filter(|_| config.has_whip()))
+        };
+        let error = self.shared.last_error.lock().unwrap().clone();
+        let state = *self.shared.state.lock().unwrap();
+        let paused = *self.paused.lock().unwrap();
+        let recording = self.recorder.lock().unwrap()
+            .as_ref()
+            .map(|r| r.path.to_string_lossy().to_string());
+        let outputs = self.outputs.lock().unwrap().iter().map(|o| o.status()).collect();
+        StreamStatus {
+            session_id: Some(self.id.clone()),
+            active: true,
+            source_id: Some(source_id),
+            whip_url,
+            duration_seconds: self.start_time.elapsed().as_secs(),
+            error,
+            state,
+            paused,
+            recording,
+            outputs,
+        }
+    }
+
+    /// Finalize local outputs, then stop the pipeline
+    pub fn shutdown(&self) {
+        log_to_file("Stopping GStreamer pipeline");
+
+        // Finalize the recording before the pipeline goes away
+        let recorder = self.recorder.lock().unwrap().take();
+        if let Some(recorder) = recorder {
+            if let Err(e) = recorder.stop(&self.pipeline) {
+                log_to_file(&format!("WARNING: {}", e));
+            }
+        }
+        let replay_buffer = self.replay.lock().unwrap().take();
+        if let Some(buffer) = replay_buffer {
+            buffer.stop(&self.pipeline);
+        }
+
+        // Send EOS to gracefully stop
+        let eos_event: gst::Event = gst::event::Eos::new();
+        let _: bool = self.pipeline.send_event(eos_event);
+
+        // Wait briefly for EOS to propagate, then force stop
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let _result = self.pipeline.set_state(gst::State::Null);
+
+        // Close the portal session only after pipewiresrc has stopped
+        #[cfg(target_os = "linux")]
+        self.screencast.lock().unwrap().take();
+
+        if let Ok(mut running) = self.shared.is_running.lock() {
+            *running = false;
+        }
+        log_to_file("GStreamer pipeline stopped");
+    }
+}
+
+/// Give up on a session after a fatal error: report it, stop the pipeline
+/// and release its encoder pin. The session stays listed with its error
+/// until `stop_stream` removes it.
+pub fn fail<R: Runtime>(app: &AppHandle<R>, session: &StreamSession, source: &str, kind: ErrorKind, message: String) {
+    lifecycle::transition(app, &session.shared, StreamState::Failed, Some(source), Some(kind), Some(message.clone()));
+    if let Ok(mut error) = session.shared.last_error.lock() {
+        *error = Some(message);
+    }
+    session.shutdown();
+    app.state::<StreamingState>().release_encoder_pin();
+}
+
+/// Tear down a session whose capture ended: stop the pipeline, remove it
+/// from `StreamingState` and release its encoder pin, as `stop_stream` does
+pub fn finish<R: Runtime>(app: &AppHandle<R>, session: &Arc<StreamSession>, source: &str) {
+    lifecycle::transition(app, &session.shared, StreamState::Stopping, Some(source), None, None);
+    session.shutdown();
+    let state = app.state::<StreamingState>();
+    state.remove_session(session);
+    state.release_encoder_pin();
+    lifecycle::transition(app, &session.shared, StreamState::Idle, Some(source), None, None);
+}
+
+/// Spawn the bus message handler for a session.
+/// The thread exits when the session is dropped or stopped.
+pub fn watch_bus<R: Runtime>(app_handle: AppHandle<R>, session: &Arc<StreamSession>) -> Result<(), String> {
+    let bus = session.pipeline.bus().ok_or("Failed to get pipeline bus")?;
+    let weak_session = Arc::downgrade(session);
+    let shared = session.shared.clone();
+
+    std::thread::spawn(move || {
+        let _log = log_scope(&shared.session_id);
+        log_to_file("Message handler thread started");
+        let mut message_count = 0u32;
+        // Shared with the reconnect thread, which counts the attempts
+        let reconnect_attempts = Arc::new(AtomicU32::new(0));
+        let mut live_since: Option<Instant> = None;
+
+        loop {
+            let msg = match bus.timed_pop(gst::ClockTime::from_seconds(1)) {
+                Some(m) => m,
+                None => {
+                    // Log every 10 seconds to show we're still alive
+                    if let Ok(running) = shared.is_running.lock() {
+                        if *running {
+                            message_count += 1;
+                            if message_count % 10 == 0 {
+                                log_debug(&format!("Pipeline still running ({}s)", message_count));
+                            }
+                        }
+                    }
+                    continue;
+                },
+            };
+
+            use gst::MessageView;
+            let src_name = msg.src().map(|s| s.name().to_string()).unwrap_or_else(|| "unknown".to_string());
+
+            match msg.view() {
+                MessageView::Eos(..) => {
+                    log_to_file(&format!("EOS from {}", src_name));
+                    if let Some(session) = weak_session.upgrade() {
+                        finish(&app_handle, &session, &src_name);
+                    }
+                    break;
+                }
+                MessageView::Error(err) => {
+                    let session = match weak_session.upgrade() {
+                        Some(s) => s,
+                        None => break,
+                    };
+                    let p = &session.pipeline;
+                    let config = session.config.lock().unwrap().clone();
+
+                    // Errors from a WHIP sink, output or recording that was already removed are stale
+                    if let Some(src) = msg.src() {
+                        if src != p.upcast_ref::<gst::Object>() && !src.has_as_ancestor(p) {
+                            log_to_file(&format!("Ignoring stale error from removed element {}", src_name));
+                            continue;
+                        }
+                    }
+
+                    let error_msg = format!(
+                        "ERROR from {}: {} (debug: {:?})",
+                        src_name,
+                        err.error(),
+                        err.debug()
+                    );
+                    log_to_file(&error_msg);
+                    let factory = msg.src()
+                        .and_then(|s| s.downcast_ref::<gst::Element>())
+                        .and_then(|e| e.factory())
+                        .map(|f| f.name().to_string());
+                    let kind = lifecycle::classify_error(factory.as_deref(), &err.error());
+
+                    // A failed recording or replay buffer is dropped; the session
+                    // keeps capturing and a new one can be started on it
+                    if let Some(src) = msg.src() {
+                        let from_recorder = recording::is_from_recorder(p, src);
+                        let from_replay = replay::is_from_replay(p, src);
+                        if from_recorder || from_replay {
+                            lifecycle::report_error(&app_handle, &shared, &src_name, ErrorKind::Recording, error_msg.clone());
+                            if from_recorder {
+                                if let Some(recorder) = session.recorder.lock().unwrap().take() {
+                                    recorder.abort(p);
+                                }
+                            } else if let Some(buffer) = session.replay.lock().unwrap().take() {
+                                buffer.stop(p);
+                            }
+                            continue;
+                        }
+
+                        // A dead RTMP / SRT destination is dropped on its own.
+                        // Recorder and replay are read before `outputs` is locked.
+                        let local_running = session.recorder.lock().unwrap().is_some()
+                            || session.replay.lock().unwrap().is_some();
+                        let mut outputs = session.outputs.lock().unwrap();
+                        if let Some(output) = outputs.iter_mut().find(|o| o.is_from(p, src)) {
+                            lifecycle::report_error(&app_handle, &shared, &src_name, kind, error_msg.clone());
+                            output.fail(p, error_msg.clone());
+                            let others_running = local_running || outputs.iter().any(|o| o.is_running());
+                            if config.has_whip() || others_running {
+                                continue;
+                            }
+                        }
+                    }
+
+                    lifecycle::report_error(&app_handle, &shared, &src_name, kind, error_msg.clone());
+
+                    // Rebuild only the WHIP sink if the error came from it and reconnect is enabled
+                    if let (Some(policy), Some(src)) = (&config.reconnect, msg.src()) {
+                        if reconnect::is_from_whip(p, src) {
+                            if live_since.map(|t| t.elapsed() >= reconnect::STABLE_AFTER).unwrap_or(false) {
+                                reconnect_attempts.store(0, Ordering::SeqCst);
+                            }
+                            live_since = None;
+                            if reconnect::start_reconnect(&app_handle, &session, policy, &reconnect_attempts) {
+                                continue;
+                            }
+                        }
+                    }
+
+                    fail(&app_handle, &session, &src_name, kind, error_msg);
+                    break;
+                }
+                MessageView::Warning(warning) => {
+                    let warning_msg = format!(
+                        "WARNING from {}: {} (debug: {:?})",
+                        src_name,
+                        warning.error(),
+                        warning.debug()
+                    );
+                    log_to_file(&warning_msg);
+                    lifecycle::report_warning(&app_handle, &shared, &src_name, warning_msg);
+                }
+                MessageView::StateChanged(state_changed) => {
+                    // Only log state changes for the pipeline itself
+                    if msg.src().map(|s| s.type_().name() == "GstPipeline").unwrap_or(false) {
+                        log_to_file(&format!(
+                            "STATE CHANGED: {:?} -> {:?} (pending: {:?})",
+                            state_changed.old(),
+                            state_changed.current(),
+                            state_changed.pending()
+                        ));
+                        // With WHIP, Live waits for the peer connection instead
+                        let has_whip = weak_session.upgrade().map(|s| s.has_whip()).unwrap_or(false);
+                        if state_changed.current() == gst::State::Playing && !has_whip {
+                            lifecycle::transition(&app_handle, &shared, StreamState::Live, Some(&src_name), None, None);
+                            live_since = Some(Instant::now());
+                        }
+                    }
+                }
+                MessageView::Application(application) => {
+                    // Posted by `lifecycle::watch_peer_connection`, also for a rebuilt WHIP sink
+                    let connected = application.structure().map(|s| s.name() == lifecycle::CONNECTED_MESSAGE).unwrap_or(false);
+                    // Ignore a late message from a WHIP sink already torn down
+                    let current = match (weak_session.upgrade(), msg.src()) {
+                        (Some(session), Some(src)) => src.has_as_ancestor(&session.pipeline),
+                        _ => false,
+                    };
+                    if connected && current {
+                        lifecycle::transition(&app_handle, &shared, StreamState::Live, Some(&src_name), None, None);
+                        live_since = Some(Instant::now());
+                    }
+                }
+                MessageView::StreamStatus(status) => {
+                    log_debug(&format!(
+                        "STREAM STATUS from {}: {:?}",
+                        src_name,
+                        status.type_()
+                    ));
+                }
+                MessageView::Element(element) => {
+                    // Log element-specific messages (like WHIP connection status)
+                    if let Some(structure) = element.structure() {
+                        log_debug(&format!(
+                            "ELEMENT MSG from {}: {}",
+                            src_name,
+                            structure.name()
+                        ));
+                    }
+                }
+                MessageView::Latency(..) => {
+                    log_debug(&format!("LATENCY update from {}", src_name));
+                }
+                MessageView::AsyncDone(..) => {
+                    log_to_file(&format!("ASYNC DONE from {} - pipeline is fully playing", src_name));
+                }
+                MessageView::NewClock(clock) => {
+                    if let Some(c) = clock.clock() {
+                        log_to_file(&format!("NEW CLOCK: {}", c.name()));
+                    }
+                }
+                MessageView::Qos(..) => {
+                    // QoS messages can be spammy, only log occasionally
+                }
+                _ => {}
+            }
+
+            // Check if we should stop
+            if let Ok(running) = shared.is_running.lock() {
+                if !*running {
+                    log_to_file("Stop requested, exiting message loop");
+                    break;
+                }
+            }
+        }
+
+        // Mark as not running when message loop exits
+        if let Ok(mut running) = shared.is_running.lock() {
+            *running = false;
+        }
+        log_to_file("=== MESSAGE HANDLER THREAD EXITED ===");
+    });
+
+    Ok(())
+}
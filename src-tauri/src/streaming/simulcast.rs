@@ -0,0 +1,266 @@
+//! Simulcast layers for WHIP
+//!
+//! This is not RID simulcast: whipclientsink can't send one track with
+//! several encodings, so each lower layer is published as a separate video
+//! track. `video_tee` is scaled down and fed into another `video_%u` pad, and
+//! the encoder whipclientsink creates for that pad is held under the layer's
+//! bitrate cap. The full layer is the normal video branch and keeps the
+//! usual congestion-control range. The SFU sees unrelated tracks and won't
+//! switch a viewer between them; receivers have to pick a track themselves.
+
+use gstreamer as gst;
+use gst::prelude::{GstBinExt, ObjectExt, ToValue};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use super::pipeline::{update_element, ElementSpec};
+use super::{encoder, log_to_file, quality_bitrates, QualityMode, StreamConfig};
+
+/// Opt-in simulcast mode
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulcastConfig {
+    /// Number of spatial layers including the full one (2 or 3)
+    #[serde(default = "default_layers")]
+    pub layers: u32,
+}
+
+fn default_layers() -> u32 {
+    3
+}
+
+impl Default for SimulcastConfig {
+    fn default() -> Self {
+        Self { layers: default_layers() }
+    }
+}
+
+/// Smallest layer worth publishing
+const MIN_WIDTH: u32 = 160;
+const MIN_HEIGHT: u32 = 90;
+
+/// Floor for a lower layer's bitrate cap
+const MIN_LAYER_BITRATE: u32 = 150_000;
+
+/// One published resolution
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SimulcastLayer {
+    pub rid: &'static str, // "f" / "h" / "q"
+    pub width: u32,
+    pub height: u32,
+    pub max_bitrate: u32, // bps
+}
+
+/// Share of the full layer's maximum bitrate given to the half and quarter
+/// layers, in per mille. Quality modes spend more on the small layers.
+fn layer_shares(mode: &QualityMode) -> [u32; 2] {
+    match mode {
+        QualityMode::Performance => [250, 80],
+        QualityMode::Balanced => [300, 100],
+        QualityMode::Quality => [350, 120],
+        QualityMode::Lossless => [400, 150],
+    }
+}
+
+/// Layers for `config`, full resolution first. Empty when simulcast is off
+/// or the session has no WHIP output.
+pub fn layers(config: &StreamConfig) -> Vec<SimulcastLayer> {
+    let count = match config.simulcast {
+        Some(ref simulcast) if config.has_whip() => simulcast.layers.clamp(2, 3),
+        _ => return Vec::new(),
+    };
+    let (_, _, max_bitrate) = quality_bitrates(config);
+    let shares = layer_shares(&config.quality_mode);
+
+    let mut layers = vec![SimulcastLayer {
+        rid: "f",
+        width: config.width,
+        height: config.height,
+        max_bitrate,
+    }];
+    for (i, rid) in ["h", "q"].iter().enumerate().take(count as usize - 1) {
+        let divisor = 2u32 << i;
+        // Encoders want even dimensions
+        let width = config.width / divisor & !1;
+        let height = config.height / divisor & !1;
+        if width < MIN_WIDTH || height < MIN_HEIGHT {
+            break;
+        }
+        let max_bitrate = (max_bitrate as u64 * shares[i] as u64 / 1000) as u32;
+        layers.push(SimulcastLayer {
+            rid,
+            width,
+            height,
+            max_bitrate: max_bitrate.max(MIN_LAYER_BITRATE),
+        });
+    }
+    layers
+}
+
+/// Chain scaling `video_tee` output down to `layer`
+pub fn layer_chain(layer: &SimulcastLayer) -> Vec<ElementSpec> {
+    vec![
+        ElementSpec::new("videoscale").named(&format!("simulcast_scale_{}", layer.rid)),
+        layer_caps(layer),
+    ]
+}
+
+fn layer_caps(layer: &SimulcastLayer) -> ElementSpec {
+    ElementSpec::caps(format!(
+        "video/x-raw,width={},height={},pixel-aspect-ratio=1/1",
+        layer.width, layer.height
+    ))
+    .named(&format!("simulcast_caps_{}", layer.rid))
+}
+
+/// Apply new layer sizes to a running pipeline
+pub fn resize(pipeline: &gst::Pipeline, layers: &[SimulcastLayer]) -> Result<(), String> {
+    for layer in layers.iter().skip(1) {
+        if let Some(caps) = pipeline.by_name(&format!("simulcast_caps_{}", layer.rid)) {
+            update_element(&caps, &layer_caps(layer))?;
+        }
+    }
+    Ok(())
+}
+
+/// Bitrate caps of a session's layers, shared with the encoders' cap
+/// handlers so a bitrate change reaches encoders that are already running
+#[derive(Clone, Default)]
+pub struct LayerCaps(Arc<Mutex<Vec<u32>>>);
+
+impl LayerCaps {
+    pub fn set(&self, layers: &[SimulcastLayer]) {
+        *self.0.lock().unwrap() = layers.iter().map(|l| l.max_bitrate).collect();
+    }
+
+    fn get(&self, index: usize) -> Option<u32> {
+        self.0.lock().unwrap().get(index).copied()
+    }
+}
+
+/// Layer carried by whipclientsink pad `pad_name` if it is a lower layer
+/// ("video_1" and up; "video_0" is the full layer)
+fn lower_layer_index(pad_name: &str) -> Option<usize> {
+    pad_name
+        .strip_prefix("video_")
+        .and_then(|n| n.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+}
+
+/// Hold each lower layer's encoder under its cap in `caps`, which is set to
+/// `layers` first. Layers are linked in order, so whipclientsink pad
+/// `video_N` carries layer N.
+pub fn connect_layer_caps(whip: &gst::Element, factory: &'static str, layers: &[SimulcastLayer], caps: &LayerCaps) {
+    caps.set(layers);
+    if layers.len() < 2 {
+        return;
+    }
+    let caps = caps.clone();
+    whip.connect("encoder-setup", true, move |args| {
+        let pad_name = args.get(2).and_then(|v| v.get::<String>().ok()).unwrap_or_default();
+        let encoder = args.get(3).and_then(|v| v.get::<gst::Element>().ok());
+        let index = lower_layer_index(&pad_name).filter(|&n| caps.get(n).is_some());
+        if let (Some(encoder), Some(index)) = (encoder, index) {
+            let layer_caps = caps.clone();
+            if encoder::cap_bitrate(&encoder, factory, move || layer_caps.get(index)) {
+                log_to_file(&format!(
+                    "Simulcast layer {} capped at {} kbps",
+                    pad_name,
+                    caps.get(index).unwrap_or(0) / 1000
+                ));
+            }
+        }
+        Some(false.to_value())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::pipeline::tests::config;
+    use serde_json::json;
+
+    fn simulcast(overrides: serde_json::Value) -> StreamConfig {
+        let mut base = json!({ "simulcast": { "layers": 3 } });
+        for (key, value) in overrides.as_object().unwrap() {
+            base[key] = value.clone();
+        }
+        config(base)
+    }
+
+    fn sizes(layers: &[SimulcastLayer]) -> Vec<(&'static str, u32, u32)> {
+        layers.iter().map(|l| (l.rid, l.width, l.height)).collect()
+    }
+
+    #[test]
+    fn layers_halve_and_quarter_the_full_size() {
+        let layers = layers(&simulcast(json!({})));
+        assert_eq!(sizes(&layers), [("f", 1920, 1080), ("h", 960, 540), ("q", 480, 270)]);
+    }
+
+    #[test]
+    fn layer_dimensions_are_rounded_down_to_even() {
+        let layers = layers(&simulcast(json!({ "width": 1366, "height": 766 })));
+        assert_eq!(sizes(&layers), [("f", 1366, 766), ("h", 682, 382), ("q", 340, 190)]);
+    }
+
+    #[test]
+    fn layers_below_the_minimum_size_are_dropped() {
+        // Quarter would be 120x66
+        let small = layers(&simulcast(json!({ "width": 480, "height": 270 })));
+        assert_eq!(sizes(&small), [("f", 480, 270), ("h", 240, 134)]);
+        // Even the half layer is too small: nothing but the full layer
+        let tiny = layers(&simulcast(json!({ "width": 300, "height": 170 })));
+        assert_eq!(sizes(&tiny), [("f", 300, 170)]);
+    }
+
+    #[test]
+    fn layer_count_is_clamped_to_two_or_three() {
+        assert_eq!(layers(&simulcast(json!({ "simulcast": { "layers": 1 } }))).len(), 2);
+        assert_eq!(layers(&simulcast(json!({ "simulcast": { "layers": 2 } }))).len(), 2);
+        assert_eq!(layers(&simulcast(json!({ "simulcast": { "layers": 7 } }))).len(), 3);
+    }
+
+    #[test]
+    fn no_layers_without_simulcast_or_whip() {
+        assert!(layers(&config(json!({}))).is_empty());
+        assert!(layers(&simulcast(json!({ "whip_url": "" }))).is_empty());
+    }
+
+    #[test]
+    fn lower_layers_get_the_quality_mode_share() {
+        for mode in [QualityMode::Performance, QualityMode::Balanced, QualityMode::Quality, QualityMode::Lossless] {
+            let mut config = simulcast(json!({}));
+            config.quality_mode = mode.clone();
+            let (_, _, max_bitrate) = quality_bitrates(&config);
+            let layers = layers(&config);
+            let shares = layer_shares(&mode);
+
+            assert_eq!(layers[0].max_bitrate, max_bitrate, "{:?}", mode);
+            for (layer, share) in layers[1..].iter().zip(shares.iter()) {
+                let expected = (max_bitrate as u64 * *share as u64 / 1000) as u32;
+                assert_eq!(layer.max_bitrate, expected.max(MIN_LAYER_BITRATE), "{:?} {}", mode, layer.rid);
+            }
+        }
+    }
+
+    #[test]
+    fn lower_layer_bitrate_has_a_floor() {
+        let layers = layers(&simulcast(json!({ "bitrate": 300 })));
+        assert_eq!(layers[2].max_bitrate, MIN_LAYER_BITRATE);
+        assert!(layers[0].max_bitrate > MIN_LAYER_BITRATE);
+    }
+
+    #[test]
+    fn video_pads_map_to_lower_layers() {
+        assert_eq!(lower_layer_index("video_0"), None);
+        assert_eq!(lower_layer_index("video_1"), Some(1));
+        assert_eq!(lower_layer_index("video_2"), Some(2));
+        assert_eq!(lower_layer_index("audio_1"), None);
+        assert_eq!(lower_layer_index("video_x"), None);
+
+        let caps = LayerCaps::default();
+        caps.set(&layers(&simulcast(json!({}))));
+        assert!(caps.get(2).is_some());
+        assert_eq!(caps.get(3), None);
+    }
+}
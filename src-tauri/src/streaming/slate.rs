@@ -0,0 +1,111 @@
+//! "Be right back" slate for paused streams
+//!
+//! While paused the video selector shows the slate instead of the capture
+//! and every audio input is muted, so the WHIP session, recordings and the
+//! stream clock keep running without showing or playing anything private.
+
+use serde::{Deserialize, Serialize};
+
+use super::pipeline::ElementSpec;
+use super::StreamConfig;
+
+/// What viewers see while the stream is paused
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlateConfig {
+    #[serde(default)]
+    pub image: Option<String>, // PNG or JPEG, letterboxed; replaces the colour
+    #[serde(default = "default_color")]
+    pub color: u32, // 0xAARRGGBB
+    #[serde(default = "default_text")]
+    pub text: Option<String>, // Drawn centred, None = no text
+}
+
+fn default_color() -> u32 {
+    0xFF10_1014
+}
+
+fn default_text() -> Option<String> {
+    Some("Be right back".to_string())
+}
+
+impl Default for SlateConfig {
+    fn default() -> Self {
+        Self {
+            image: None,
+            color: default_color(),
+            text: default_text(),
+        }
+    }
+}
+
+/// Decoder for a still image, by extension
+fn image_decoder(path: &str) -> Result<&'static str, String> {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => Ok("pngdec"),
+        "jpg" | "jpeg" => Ok("jpegdec"),
+        _ => Err(format!("Unsupported slate image (PNG or JPEG only): {}", path)),
+    }
+}
+
+/// Selector input showing `slate`, with the same caps as the capture source
+pub fn source(slate: &SlateConfig, config: &StreamConfig) -> Result<Vec<ElementSpec>, String> {
+    let mut video = Vec::new();
+
+    match slate.image {
+        Some(ref path) => {
+            if !std::path::Path::new(path).is_file() {
+                return Err(format!("Slate image not found: {}", path));
+            }
+            video.push(ElementSpec::new("filesrc").str("location", path));
+            video.push(ElementSpec::new(image_decoder(path)?));
+            video.push(ElementSpec::new("videoconvert"));
+            video.push(ElementSpec::new("videoscale"));
+            video.push(ElementSpec::caps(format!(
+                "video/x-raw,width={},height={},pixel-aspect-ratio=1/1",
+                config.width, config.height
+            )));
+            video.push(ElementSpec::new("imagefreeze").bool("is-live", true));
+        }
+        None => {
+            video.push(
+                ElementSpec::new("videotestsrc")
+                    .bool("is-live", true)
+                    .enum_nick("pattern", "solid-color")
+                    .int("foreground-color", slate.color as i64),
+            );
+        }
+    }
+
+    if let Some(ref text) = slate.text {
+        video.push(
+            ElementSpec::new("textoverlay")
+                .str("text", text)
+                .str("font-desc", "Sans Bold 36")
+                .enum_nick("halignment", "center")
+                .enum_nick("valignment", "center")
+                .bool("shaded-background", false),
+        );
+    }
+
+    video.push(ElementSpec::new("videoconvert"));
+    video.push(ElementSpec::caps(format!(
+        "video/x-raw,format=NV12,width={},height={},framerate={}/1,pixel-aspect-ratio=1/1",
+        config.width, config.height, config.fps
+    )));
+
+    // The capture side of the selector carries GPU memory on Windows
+    #[cfg(target_os = "windows")]
+    {
+        video.push(ElementSpec::new("d3d11upload"));
+        video.push(ElementSpec::caps(format!(
+            "video/x-raw(memory:D3D11Memory),format=NV12,width={},height={},pixel-aspect-ratio=1/1",
+            config.width, config.height
+        )));
+    }
+
+    Ok(video)
+}
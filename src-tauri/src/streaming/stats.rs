@@ -0,0 +1,478 @@
+//! Live WebRTC statistics for native streams
+//!
+//! Periodically reads the `stats` structure of the `whip` element (which
+//! embeds the webrtcbin stats of each session), adds encoder/capture counters
+//! gathered from pad probes, and emits the result as a `stream-stats` event.
+//! WHEP playback gets the receiving-side equivalent as `playback-stats`.
+
+use gstreamer as gst;
+use gst::prelude::{Cast, ElementExt, GstBinExt, ObjectExt, PadExtManual, ToValue};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, Runtime};
+
+use super::{log_scope, log_to_file, SharedState};
+
+/// How often stats are collected and emitted
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Event name for stats updates
+pub const STATS_EVENT: &str = "stream-stats";
+
+/// Event name for playback stats updates
+pub const PLAYBACK_STATS_EVENT: &str = "playback-stats";
+
+/// Stats event payload
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StreamStats {
+    pub session_id: String,
+    pub bitrate_kbps: f64,                // Measured outgoing RTP bitrate
+    pub target_bitrate_kbps: Option<f64>, // Congestion-control estimate
+    pub rtt_ms: Option<f64>,
+    pub packets_sent: u64,
+    pub packets_lost: i64,
+    pub fraction_lost: Option<f64>, // 0.0 - 1.0, as reported by the receiver
+    pub nack_count: u64,
+    pub pli_count: u64,
+    pub encoder_fps: f64,
+    pub dropped_frames: u64, // Capture frames the leaky video queue threw away
+}
+
+/// Playback stats event payload
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlaybackStats {
+    pub session_id: String,
+    pub bitrate_kbps: f64, // Measured incoming RTP bitrate
+    pub packets_received: u64,
+    pub packets_lost: i64,
+    pub jitter_ms: Option<f64>,
+    pub decoded_fps: f64,
+    pub frames_decoded: u64,
+}
+
+/// Counters fed by probes and signals on the running pipeline. Kept in
+/// `SharedState` so every rebuilt WHIP sink feeds the same totals.
+#[derive(Clone, Default)]
+pub struct Counters {
+    encoded_frames: Arc<AtomicU64>,
+    capture_queue: QueueDrops,
+}
+
+/// Buffers into and out of a leaky queue. Whatever went in, hasn't come out
+/// and isn't queued any more was dropped.
+#[derive(Clone, Default)]
+pub struct QueueDrops {
+    received: Arc<AtomicU64>,
+    forwarded: Arc<AtomicU64>,
+}
+
+impl QueueDrops {
+    /// Count the buffers passing `queue`'s pads
+    pub fn watch(&self, queue: &gst::Element) {
+        for (pad, counter) in [("sink", &self.received), ("src", &self.forwarded)] {
+            if let Some(pad) = queue.static_pad(pad) {
+                let counter = counter.clone();
+                pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    gst::PadProbeReturn::Ok
+                });
+            }
+        }
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Buffers `queue` dropped so far
+    pub fn dropped(&self, queue: &gst::Element) -> u64 {
+        let queued: u32 = queue.property("current-level-buffers");
+        dropped_buffers(self.received(), self.forwarded.load(Ordering::Relaxed), u64::from(queued))
+    }
+}
+
+fn dropped_buffers(received: u64, forwarded: u64, queued: u64) -> u64 {
+    received.saturating_sub(forwarded).saturating_sub(queued)
+}
+
+/// Totals accumulated from one stats structure
+#[derive(Default)]
+struct RtpTotals {
+    bytes_sent: u64,
+    packets_sent: u64,
+    packets_lost: i64,
+    nack_count: u64,
+    pli_count: u64,
+    rtt_seconds: Vec<f64>,
+    fraction_lost: Vec<f64>,
+    estimated_bitrate: Option<u64>,
+}
+
+/// Totals of the inbound streams in one webrtcbin stats structure
+#[derive(Default)]
+struct InboundTotals {
+    bytes_received: u64,
+    packets_received: u64,
+    packets_lost: i64,
+    jitter_seconds: Vec<f64>,
+}
+
+/// Read an unsigned counter regardless of the integer type webrtcbin used
+fn get_u64(s: &gst::StructureRef, field: &str) -> Option<u64> {
+    s.get::<u64>(field)
+        .ok()
+        .or_else(|| s.get::<u32>(field).ok().map(u64::from))
+        .or_else(|| s.get::<i64>(field).ok().map(|v| v.max(0) as u64))
+        .or_else(|| s.get::<i32>(field).ok().map(|v| v.max(0) as u64))
+}
+
+fn get_i64(s: &gst::StructureRef, field: &str) -> Option<i64> {
+    s.get::<i64>(field)
+        .ok()
+        .or_else(|| s.get::<i32>(field).ok().map(i64::from))
+        .or_else(|| s.get::<u64>(field).ok().map(|v| v as i64))
+        .or_else(|| s.get::<u32>(field).ok().map(i64::from))
+}
+
+/// Walk a (possibly nested) stats structure and accumulate RTP totals
+fn accumulate(s: &gst::StructureRef, totals: &mut RtpTotals) {
+    match s.name().as_str() {
+        "rtp-outbound-stream-stats" => {
+            totals.bytes_sent += get_u64(s, "bytes-sent").unwrap_or(0);
+            totals.packets_sent += get_u64(s, "packets-sent").unwrap_or(0);
+            totals.nack_count += get_u64(s, "nack-count").unwrap_or(0);
+            totals.pli_count += get_u64(s, "pli-count").unwrap_or(0);
+        }
+        "rtp-remote-inbound-stream-stats" => {
+            totals.packets_lost += get_i64(s, "packets-lost").unwrap_or(0);
+            if let Ok(rtt) = s.get::<f64>("round-trip-time") {
+                totals.rtt_seconds.push(rtt);
+            }
+            if let Ok(fraction) = s.get::<f64>("fraction-lost") {
+                totals.fraction_lost.push(fraction);
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(bitrate) = get_u64(s, "estimated-bitrate") {
+        totals.estimated_bitrate = Some(totals.estimated_bitrate.unwrap_or(0) + bitrate);
+    }
+
+    for (_, value) in s.iter() {
+        if let Ok(nested) = value.get::<gst::Structure>() {
+            accumulate(&nested, totals);
+        }
+    }
+}
+
+fn accumulate_inbound(s: &gst::StructureRef, totals: &mut InboundTotals) {
+    if s.name().as_str() == "rtp-inbound-stream-stats" {
+        totals.bytes_received += get_u64(s, "bytes-received").unwrap_or(0);
+        totals.packets_received += get_u64(s, "packets-received").unwrap_or(0);
+        totals.packets_lost += get_i64(s, "packets-lost").unwrap_or(0);
+        if let Ok(jitter) = s.get::<f64>("jitter") {
+            totals.jitter_seconds.push(jitter);
+        }
+    }
+
+    for (_, value) in s.iter() {
+        if let Ok(nested) = value.get::<gst::Structure>() {
+            accumulate_inbound(&nested, totals);
+        }
+    }
+}
+
+/// Ask the webrtcbin inside `whepclientsrc` for its stats
+fn webrtcbin_stats(pipeline: &gst::Pipeline) -> Option<gst::Structure> {
+    let webrtcbin = pipeline
+        .iterate_all_by_element_factory_name("webrtcbin")
+        .into_iter()
+        .flatten()
+        .next()?;
+    let promise = gst::Promise::new();
+    webrtcbin.emit_by_name::<()>("get-stats", &[&None::<gst::Pad>, &promise]);
+    match promise.wait() {
+        gst::PromiseResult::Replied => promise.get_reply().map(|s| s.to_owned()),
+        _ => None,
+    }
+}
+
+/// Find the congestion-control estimate on any rtpgccbwe inside whipclientsink
+fn gcc_estimate(whip: &gst::Element) -> Option<u64> {
+    let bin = whip.downcast_ref::<gst::Bin>()?;
+    let mut total = None;
+    for element in bin.iterate_all_by_element_factory_name("rtpgccbwe") {
+        if let Ok(elem) = element {
+            if elem.find_property("estimated-bitrate").is_some() {
+                let value: u32 = elem.property("estimated-bitrate");
+                total = Some(total.unwrap_or(0) + u64::from(value));
+            }
+        }
+    }
+    total
+}
+
+fn average(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+/// Install probes/signals used for encoder fps and dropped frames
+/// Count encoded frames on every encoder `whip` creates. Needed on each
+/// WHIP sink, including ones rebuilt by a reconnect or config update.
+pub fn count_encoded_frames(whip: &gst::Element, counters: &Counters) {
+    let encoded = counters.encoded_frames.clone();
+    whip.connect("encoder-setup", true, move |args| {
+        if let Some(encoder) = args.get(3).and_then(|v| v.get::<gst::Element>().ok()) {
+            if let Some(pad) = encoder.static_pad("src") {
+                let encoded = encoded.clone();
+                pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                    encoded.fetch_add(1, Ordering::Relaxed);
+                    gst::PadProbeReturn::Ok
+                });
+            }
+        }
+        Some(false.to_value())
+    });
+}
+
+/// Build a `stream-stats` payload from one tick's totals and counters
+fn stream_stats(
+    session_id: &str,
+    totals: &RtpTotals,
+    byte_delta: u64,
+    frame_delta: u64,
+    elapsed: f64,
+    target: Option<u64>,
+    dropped_frames: u64,
+) -> StreamStats {
+    StreamStats {
+        session_id: session_id.to_string(),
+        bitrate_kbps: (byte_delta as f64 * 8.0) / elapsed / 1000.0,
+        target_bitrate_kbps: target.map(|b| b as f64 / 1000.0),
+        rtt_ms: average(&totals.rtt_seconds).map(|s| s * 1000.0),
+        packets_sent: totals.packets_sent,
+        packets_lost: totals.packets_lost,
+        fraction_lost: average(&totals.fraction_lost),
+        nack_count: totals.nack_count,
+        pli_count: totals.pli_count,
+        encoder_fps: frame_delta as f64 / elapsed,
+        dropped_frames,
+    }
+}
+
+/// Start the stats collector thread for a running pipeline.
+/// The thread exits when the pipeline is dropped or `shared.is_running` goes false.
+pub fn spawn_collector<R: Runtime>(app: AppHandle<R>, pipeline: &gst::Pipeline, shared: SharedState) {
+    if pipeline.by_name("whip").is_none() {
+        log_to_file("Stats: no whip element, collector not started");
+        return;
+    }
+    let counters = shared.counters.clone();
+    if let Some(queue) = pipeline.by_name("video_queue") {
+        counters.capture_queue.watch(&queue);
+    }
+    let weak_pipeline = pipeline.downgrade();
+
+    std::thread::spawn(move || {
+        let _log = log_scope(&shared.session_id);
+        log_to_file("Stats collector started");
+        let mut last_bytes = 0u64;
+        let mut last_frames = 0u64;
+        let mut last_tick = Instant::now();
+
+        loop {
+            std::thread::sleep(STATS_INTERVAL);
+
+            let running = shared.is_running.lock().map(|r| *r).unwrap_or(false);
+            let pipeline = match weak_pipeline.upgrade() {
+                Some(p) if running => p,
+                _ => break,
+            };
+            // No sink while a reconnect or config update rebuilds it
+            let whip = match pipeline.by_name("whip") {
+                Some(w) => w,
+                None => continue,
+            };
+
+            let mut totals = RtpTotals::default();
+            if whip.find_property("stats").is_some() {
+                let stats: gst::Structure = whip.property("stats");
+                accumulate(&stats, &mut totals);
+            }
+
+            let elapsed = last_tick.elapsed().as_secs_f64().max(0.001);
+            last_tick = Instant::now();
+
+            // A rebuilt sink starts counting from zero
+            if totals.bytes_sent < last_bytes {
+                last_bytes = 0;
+            }
+            let byte_delta = totals.bytes_sent.saturating_sub(last_bytes);
+            last_bytes = totals.bytes_sent;
+            let frames = counters.encoded_frames.load(Ordering::Relaxed);
+            let frame_delta = frames.saturating_sub(last_frames);
+            last_frames = frames;
+
+            let target = gcc_estimate(&whip).or(totals.estimated_bitrate);
+            let dropped = pipeline
+                .by_name("video_queue")
+                .map(|queue| counters.capture_queue.dropped(&queue))
+                .unwrap_or(0);
+
+            let payload = stream_stats(&shared.session_id, &totals, byte_delta, frame_delta, elapsed, target, dropped);
+
+            let _ = app.emit_all(STATS_EVENT, payload);
+        }
+
+        log_to_file("Stats collector exited");
+    });
+}
+
+/// Start the stats collector thread for a WHEP playback pipeline.
+/// `frames_decoded` counts video frames reaching the output.
+pub fn spawn_playback_collector<R: Runtime>(app: AppHandle<R>, pipeline: &gst::Pipeline, shared: SharedState, frames_decoded: Arc<AtomicU64>) {
+    let weak_pipeline = pipeline.downgrade();
+
+    std::thread::spawn(move || {
+        let _log = log_scope(&shared.session_id);
+        log_to_file("Playback stats collector started");
+        let mut last_bytes = 0u64;
+        let mut last_frames = 0u64;
+        let mut last_tick = Instant::now();
+
+        loop {
+            std::thread::sleep(STATS_INTERVAL);
+
+            let running = shared.is_running.lock().map(|r| *r).unwrap_or(false);
+            let pipeline = match weak_pipeline.upgrade() {
+                Some(p) if running => p,
+                _ => break,
+            };
+
+            let mut totals = InboundTotals::default();
+            if let Some(stats) = webrtcbin_stats(&pipeline) {
+                accumulate_inbound(&stats, &mut totals);
+            }
+            drop(pipeline);
+
+            let elapsed = last_tick.elapsed().as_secs_f64().max(0.001);
+            last_tick = Instant::now();
+
+            let byte_delta = totals.bytes_received.saturating_sub(last_bytes);
+            last_bytes = totals.bytes_received;
+            let frames = frames_decoded.load(Ordering::Relaxed);
+            let frame_delta = frames.saturating_sub(last_frames);
+            last_frames = frames;
+
+            let payload = PlaybackStats {
+                session_id: shared.session_id.clone(),
+                bitrate_kbps: (byte_delta as f64 * 8.0) / elapsed / 1000.0,
+                packets_received: totals.packets_received,
+                packets_lost: totals.packets_lost,
+                jitter_ms: average(&totals.jitter_seconds).map(|s| s * 1000.0),
+                decoded_fps: frame_delta as f64 / elapsed,
+                frames_decoded: frames,
+            };
+
+            let _ = app.emit_all(PLAYBACK_STATS_EVENT, payload);
+        }
+
+        log_to_file("Playback stats collector exited");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// whipclientsink `stats`: one consumer whose webrtcbin stats hold an
+    /// outbound video stream, the receiver's report on it and the
+    /// congestion-control estimate
+    fn whip_stats() -> gst::Structure {
+        gst::init().unwrap();
+        let outbound = gst::Structure::builder("rtp-outbound-stream-stats")
+            .field("bytes-sent", 250_000u64)
+            .field("packets-sent", 200u64)
+            .field("nack-count", 3u32)
+            .field("pli-count", 1u32)
+            .build();
+        let remote = gst::Structure::builder("rtp-remote-inbound-stream-stats")
+            .field("packets-lost", 4i32)
+            .field("round-trip-time", 0.040f64)
+            .field("fraction-lost", 0.02f64)
+            .build();
+        let audio_remote = gst::Structure::builder("rtp-remote-inbound-stream-stats")
+            .field("packets-lost", 1i64)
+            .field("round-trip-time", 0.060f64)
+            .field("fraction-lost", 0.0f64)
+            .build();
+        let webrtcbin = gst::Structure::builder("application/x-webrtc-stats")
+            .field("rtp-outbound-stream-stats_1", outbound)
+            .field("rtp-remote-inbound-stream-stats_1", remote)
+            .field("rtp-remote-inbound-stream-stats_2", audio_remote)
+            .build();
+        let consumer = gst::Structure::builder("application/x-webrtcsink-consumer-stats")
+            .field("estimated-bitrate", 3_500_000u32)
+            .field("webrtcbin", webrtcbin)
+            .build();
+        gst::Structure::builder("application/x-webrtcsink-stats")
+            .field("consumer-1", consumer)
+            .build()
+    }
+
+    #[test]
+    fn nested_stats_are_totalled() {
+        let mut totals = RtpTotals::default();
+        accumulate(&whip_stats(), &mut totals);
+        assert_eq!(totals.bytes_sent, 250_000);
+        assert_eq!(totals.packets_sent, 200);
+        assert_eq!(totals.packets_lost, 5);
+        assert_eq!(totals.nack_count, 3);
+        assert_eq!(totals.pli_count, 1);
+        assert_eq!(totals.rtt_seconds, [0.040, 0.060]);
+        assert_eq!(totals.estimated_bitrate, Some(3_500_000));
+    }
+
+    #[test]
+    fn payload_reports_rates_rtt_and_loss() {
+        let mut totals = RtpTotals::default();
+        accumulate(&whip_stats(), &mut totals);
+        // 250 kB over 2 s, 120 frames
+        let stats = stream_stats("stream-1", &totals, 250_000, 120, 2.0, totals.estimated_bitrate, 7);
+        assert_eq!(stats.session_id, "stream-1");
+        assert!((stats.bitrate_kbps - 1000.0).abs() < 1e-9, "{}", stats.bitrate_kbps);
+        assert_eq!(stats.target_bitrate_kbps, Some(3500.0));
+        assert!((stats.rtt_ms.unwrap() - 50.0).abs() < 1e-9);
+        assert!((stats.fraction_lost.unwrap() - 0.01).abs() < 1e-9);
+        assert_eq!(stats.packets_lost, 5);
+        assert!((stats.encoder_fps - 60.0).abs() < 1e-9);
+        assert_eq!(stats.dropped_frames, 7);
+    }
+
+    #[test]
+    fn missing_reports_stay_empty() {
+        gst::init().unwrap();
+        let mut totals = RtpTotals::default();
+        accumulate(&gst::Structure::new_empty("application/x-webrtcsink-stats"), &mut totals);
+        let stats = stream_stats("stream-1", &totals, 0, 0, 1.0, None, 0);
+        assert_eq!(stats.rtt_ms, None);
+        assert_eq!(stats.fraction_lost, None);
+        assert_eq!(stats.target_bitrate_kbps, None);
+    }
+
+    #[test]
+    fn dropped_buffers_exclude_queued_ones() {
+        // 100 in, 90 out, 3 still queued: 7 were thrown away
+        assert_eq!(dropped_buffers(100, 90, 3), 7);
+        assert_eq!(dropped_buffers(100, 97, 3), 0);
+        // Counters read mid-push can briefly disagree
+        assert_eq!(dropped_buffers(100, 99, 3), 0);
+    }
+}
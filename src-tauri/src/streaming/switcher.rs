@@ -0,0 +1,172 @@
+//! Hot-switching the capture source
+//!
+//! The video branch runs through an `input-selector`. Switching builds the
+//! new source in its own bin on a fresh selector pad, flips `active-pad` once
+//! it produces a frame, then removes the old source. Everything downstream
+//! (tees, WHIP sink, recordings) keeps running, so WebRTC is not renegotiated.
+//! Both sources are scaled to the configured size, so output caps don't change.
+//! The pause slate goes on a selector pad the same way, but the capture
+//! stays linked so resuming is just flipping `active-pad` back.
+
+use gstreamer as gst;
+use gst::prelude::{ElementExt, ElementExtManual, GstBinExt, GstObjectExt, ObjectExt, PadExt, PadExtManual};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use super::pipeline::{source_bin, ElementSpec};
+use super::log_to_file;
+
+/// Name of the selector in the video branch
+pub const SELECTOR_NAME: &str = "video_selector";
+
+/// Name of the slate bin while a stream is paused
+pub const SLATE_NAME: &str = "video_slate";
+
+/// How long the new source may take to deliver its first frame
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Unique suffix for source bins
+static NEXT_SOURCE: AtomicU32 = AtomicU32::new(1);
+
+/// Elements feeding selector pad `pad`, nearest first. The initial source
+/// is a plain chain in the pipeline; later ones are a single bin.
+fn upstream_chain(pad: &gst::Pad) -> Vec<gst::Element> {
+    let mut chain = Vec::new();
+    let mut next = pad.peer().and_then(|p| p.parent_element());
+    while let Some(element) = next {
+        let sink_pads = element.sink_pads();
+        next = match sink_pads.as_slice() {
+            [sink] => sink.peer().and_then(|p| p.parent_element()),
+            _ => None,
+        };
+        chain.push(element);
+    }
+    chain
+}
+
+/// Remove a source that is no longer selected
+fn remove_source(pipeline: &gst::Pipeline, selector: &gst::Element, pad: &gst::Pad) {
+    let chain = upstream_chain(pad);
+    if let Some(peer) = pad.peer() {
+        let _ = peer.unlink(pad);
+    }
+    selector.release_request_pad(pad);
+
+    // Stop the source first so nothing pushes into elements being removed
+    for element in chain.iter().rev() {
+        let _ = element.set_state(gst::State::Null);
+        let _ = pipeline.remove(element);
+    }
+    log_to_file(&format!(
+        "Removed old video source ({})",
+        chain.iter().map(|e| e.name().to_string()).collect::<Vec<_>>().join(" <- ")
+    ));
+}
+
+/// Add `bin` on a new selector pad and wait until it produces a frame.
+/// On failure the bin is removed again and the selection is unchanged.
+fn link_source(pipeline: &gst::Pipeline, selector: &gst::Element, bin: &gst::Bin) -> Result<gst::Pad, String> {
+    pipeline
+        .add(bin)
+        .map_err(|e| format!("Failed to add {} to pipeline: {}", bin.name(), e))?;
+
+    let new_pad = match selector.request_pad_simple("sink_%u") {
+        Some(pad) => pad,
+        None => {
+            let _ = pipeline.remove(bin);
+            return Err("Video selector has no free sink pad".to_string());
+        }
+    };
+    let abandon = |e: String| {
+        if let Some(src) = bin.static_pad("src") {
+            let _ = src.unlink(&new_pad);
+        }
+        selector.release_request_pad(&new_pad);
+        let _ = bin.set_state(gst::State::Null);
+        let _ = pipeline.remove(bin);
+        log_to_file(&format!("Source switch failed: {}", e));
+        e
+    };
+
+    let src = bin
+        .static_pad("src")
+        .ok_or_else(|| abandon("New source has no src pad".to_string()))?;
+    src.link(&new_pad)
+        .map_err(|e| abandon(format!("Failed to link new source: {:?}", e)))?;
+
+    // Switch only once the new source is actually producing
+    let (first_frame_tx, first_frame) = mpsc::sync_channel(1);
+    new_pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+        let _ = first_frame_tx.try_send(());
+        gst::PadProbeReturn::Remove
+    });
+
+    bin.sync_state_with_parent()
+        .map_err(|e| abandon(format!("Failed to start new source: {}", e)))?;
+
+    if first_frame.recv_timeout(FIRST_FRAME_TIMEOUT).is_err() {
+        return Err(abandon(format!("New source produced no frames within {:?}", FIRST_FRAME_TIMEOUT)));
+    }
+    Ok(new_pad)
+}
+
+fn selector(pipeline: &gst::Pipeline) -> Result<gst::Element, String> {
+    pipeline
+        .by_name(SELECTOR_NAME)
+        .ok_or_else(|| "Pipeline has no video source selector".to_string())
+}
+
+/// Replace the running capture source with one built from `specs`.
+/// On failure the old source stays selected and nothing is changed.
+pub fn switch_source(pipeline: &gst::Pipeline, specs: &[ElementSpec]) -> Result<(), String> {
+    let selector = selector(pipeline)?;
+    let old_pad = selector.property::<Option<gst::Pad>>("active-pad");
+
+    let name = format!("video_source_{}", NEXT_SOURCE.fetch_add(1, Ordering::Relaxed));
+    let bin = source_bin(&name, specs)?;
+    let new_pad = link_source(pipeline, &selector, &bin)?;
+
+    selector.set_property("active-pad", &new_pad);
+    log_to_file(&format!("Video source switched to {}", bin.name()));
+
+    if let Some(old_pad) = old_pad.filter(|p| *p != new_pad) {
+        remove_source(pipeline, &selector, &old_pad);
+    }
+
+    Ok(())
+}
+
+/// Select a slate built from `specs` in front of the capture. The capture
+/// keeps running on its own pad, its frames are just not forwarded.
+pub fn show_slate(pipeline: &gst::Pipeline, specs: &[ElementSpec]) -> Result<(), String> {
+    if pipeline.by_name(SLATE_NAME).is_some() {
+        return Err("Slate is already shown".to_string());
+    }
+    let selector = selector(pipeline)?;
+    let bin = source_bin(SLATE_NAME, specs)?;
+    let pad = link_source(pipeline, &selector, &bin)?;
+    selector.set_property("active-pad", &pad);
+    log_to_file("Slate shown");
+    Ok(())
+}
+
+/// Go back to the capture and remove the slate
+pub fn hide_slate(pipeline: &gst::Pipeline) -> Result<(), String> {
+    let selector = selector(pipeline)?;
+    let slate_pad = pipeline
+        .by_name(SLATE_NAME)
+        .and_then(|slate| slate.static_pad("src"))
+        .and_then(|src| src.peer())
+        .ok_or("No slate is shown")?;
+    let capture_pad = selector
+        .sink_pads()
+        .into_iter()
+        .find(|p| *p != slate_pad)
+        .ok_or("Video selector has no capture pad")?;
+
+    selector.set_property("active-pad", &capture_pad);
+    remove_source(pipeline, &selector, &slate_pad);
+    log_to_file("Slate hidden, capture resumed");
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! Runtime consumers of the capture tees
+//!
+//! Recordings and the replay buffer are bins with a "video" (and optionally
+//! "audio") ghost sink pad, hung off `video_tee` / `audio_tee` while the
+//! pipeline is PLAYING and removed again without disturbing other consumers.
+
+use gstreamer as gst;
+use gst::prelude::{Cast, ElementExt, ElementExtManual, GstBinExt, GstBinExtManual, GstObjectExt, PadExt};
+
+use super::pipeline::ElementSpec;
+use super::{encoder, quality_bitrates, StreamConfig};
+
+/// Leaky queue heading each branch: a slow consumer drops its own frames
+/// instead of stalling the stream
+pub fn consumer_queue() -> ElementSpec {
+    ElementSpec::new("queue")
+        .int("max-size-buffers", 0)
+        .int("max-size-bytes", 0)
+        .int("max-size-time", 1_000_000_000)
+        .enum_nick("leaky", "downstream")
+}
+
+/// Raw video from `video_tee` to the stream's codec, with our own encoder
+/// (whipclientsink's encoder is not reachable from outside the sink). The
+/// size and framerate are pinned to `config`, so a file keeps its caps when
+/// `update_stream_config` changes the capture.
+pub fn video_encode_chain(config: &StreamConfig, encoder_factory: &'static str, keyframe_interval: u32) -> Vec<ElementSpec> {
+    let (start_bitrate, _, _) = quality_bitrates(config);
+    let mut chain = vec![
+        consumer_queue(),
+        ElementSpec::new("videoscale"),
+        ElementSpec::new("videorate"),
+        ElementSpec::caps(format!(
+            "video/x-raw,width={},height={},framerate={}/1,pixel-aspect-ratio=1/1",
+            config.width,
+            config.height,
+            config.fps.max(1)
+        )),
+        ElementSpec::new("videoconvert"),
+        encoder::standalone_encoder(
+            encoder_factory,
+            start_bitrate / 1000,
+            keyframe_interval,
+            config.preset.as_deref(),
+            &config.quality_mode,
+        ),
+    ];
+    if let Some(parser) = config.codec.parser() {
+        chain.push(ElementSpec::new(parser));
+    }
+    chain
+}
+
+/// Create `specs` inside `bin`, link them in order and expose the first
+/// element's sink pad as ghost pad `name`. Returns the last element.
+pub fn add_branch(bin: &gst::Bin, name: &str, specs: &[ElementSpec]) -> Result<gst::Element, String> {
+    if specs.is_empty() {
+        return Err(format!("Empty {} branch in {}", name, bin.name()));
+    }
+    let elements = specs
+        .iter()
+        .map(|spec| spec.make())
+        .collect::<Result<Vec<_>, _>>()?;
+    bin.add_many(&elements)
+        .map_err(|e| format!("Failed to add {} elements to {}: {}", name, bin.name(), e))?;
+    gst::Element::link_many(&elements)
+        .map_err(|e| format!("Failed to link {} {} branch: {}", bin.name(), name, e))?;
+
+    let target = elements[0]
+        .static_pad("sink")
+        .ok_or_else(|| format!("{} {} branch has no sink pad", bin.name(), name))?;
+    let ghost = gst::GhostPad::builder_with_target(&target)
+        .map_err(|e| format!("Failed to create {} {} pad: {}", bin.name(), name, e))?
+        .name(name)
+        .build();
+    bin.add_pad(&ghost)
+        .map_err(|e| format!("Failed to add {} {} pad: {}", bin.name(), name, e))?;
+
+    Ok(elements[elements.len() - 1].clone())
+}
+
+/// Add `specs` straight to the pipeline (not in a bin, so later consumer
+/// bins can hang off a tee at their end) and feed them from `tee_name`.
+/// They stay for the lifetime of the pipeline.
+pub fn add_shared_chain(pipeline: &gst::Pipeline, tee_name: &str, specs: &[ElementSpec]) -> Result<(), String> {
+    let tee = pipeline
+        .by_name(tee_name)
+        .ok_or_else(|| format!("No {} in pipeline", tee_name))?;
+    let elements = specs
+        .iter()
+        .map(|spec| spec.make())
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = elements.first().ok_or("Empty shared chain")?;
+    pipeline
+        .add_many(&elements)
+        .map_err(|e| format!("Failed to add shared chain to pipeline: {}", e))?;
+    gst::Element::link_many(&elements)
+        .map_err(|e| format!("Failed to link shared chain: {}", e))?;
+    for element in elements.iter().rev() {
+        element
+            .sync_state_with_parent()
+            .map_err(|e| format!("Failed to start {}: {}", element.name(), e))?;
+    }
+
+    let pad = tee
+        .request_pad_simple("src_%u")
+        .ok_or_else(|| format!("{} has no free src pad", tee_name))?;
+    let sink = first.static_pad("sink").ok_or("Shared chain has no sink pad")?;
+    pad.link(&sink)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to link {} to shared chain: {:?}", tee_name, e))
+}
+
+/// A tee src pad feeding a consumer bin
+pub struct TeeLink {
+    tee: gst::Element,
+    pad: gst::Pad,
+}
+
+impl TeeLink {
+    pub fn pad(&self) -> &gst::Pad {
+        &self.pad
+    }
+}
+
+/// Add `bin` to the running pipeline and link each of its ghost sink pads
+/// to a new src pad on the matching tee ("video" -> `video_tee`, ...).
+/// On failure the bin is removed again.
+pub fn attach(pipeline: &gst::Pipeline, bin: &gst::Bin) -> Result<Vec<TeeLink>, String> {
+    pipeline
+        .add(bin)
+        .map_err(|e| format!("Failed to add {} to pipeline: {}", bin.name(), e))?;
+
+    if let Err(e) = bin.sync_state_with_parent() {
+        detach(pipeline, bin, &[]);
+        return Err(format!("Failed to start {}: {}", bin.name(), e));
+    }
+
+    let mut links = Vec::new();
+    for ghost in bin.sink_pads() {
+        let tee_name = format!("{}_tee", ghost.name());
+        let result = pipeline
+            .by_name(&tee_name)
+            .ok_or_else(|| format!("No {} in pipeline", tee_name))
+            .and_then(|tee| {
+                let pad = tee
+                    .request_pad_simple("src_%u")
+                    .ok_or_else(|| format!("{} has no free src pad", tee_name))?;
+                links.push(TeeLink { tee, pad: pad.clone() });
+                pad.link(&ghost)
+                    .map(|_| ())
+                    .map_err(|e| format!("Failed to link {} to {}: {:?}", tee_name, bin.name(), e))
+            });
+        if let Err(e) = result {
+            detach(pipeline, bin, &links);
+            return Err(e);
+        }
+    }
+
+    Ok(links)
+}
+
+/// Unlink `bin` from its tees, stop it and remove it from the pipeline
+pub fn detach(pipeline: &gst::Pipeline, bin: &gst::Bin, links: &[TeeLink]) {
+    for link in links {
+        if let Some(peer) = link.pad.peer() {
+            let _ = link.pad.unlink(&peer);
+        }
+        link.tee.release_request_pad(&link.pad);
+    }
+    let _ = bin.set_state(gst::State::Null);
+    let _ = pipeline.remove(bin);
+}
+
+/// Whether a bus message source is (inside) the bin called `name`
+pub fn is_from(pipeline: &gst::Pipeline, name: &str, src: &gst::Object) -> bool {
+    match pipeline.by_name(name) {
+        Some(bin) => src == bin.upcast_ref::<gst::Object>() || src.has_as_ancestor(&bin),
+        None => false,
+    }
+}
@@ -0,0 +1,48 @@
+//! Deterministic capture for headless runs
+//!
+//! `source_id: "test"` replaces screen capture with `videotestsrc` and desktop
+//! audio with `audiotestsrc`, so the full pipeline (encoders, tees, WHIP) can
+//! run without a display, audio server or capture permissions.
+
+use super::pipeline::ElementSpec;
+use super::StreamConfig;
+
+/// `source_id` selecting the test source
+pub const SOURCE_ID: &str = "test";
+
+pub fn is_test(source_id: &str) -> bool {
+    source_id == SOURCE_ID
+}
+
+/// SMPTE bars with the same caps as a real capture source
+pub fn video(config: &StreamConfig) -> Vec<ElementSpec> {
+    let mut video = vec![
+        ElementSpec::new("videotestsrc")
+            .bool("is-live", true)
+            .enum_nick("pattern", "smpte"),
+        ElementSpec::caps(format!(
+            "video/x-raw,format=NV12,width={},height={},framerate={}/1,pixel-aspect-ratio=1/1",
+            config.width, config.height, config.fps
+        )),
+    ];
+
+    #[cfg(target_os = "windows")]
+    {
+        video.push(ElementSpec::new("d3d11upload"));
+        video.push(ElementSpec::caps(format!(
+            "video/x-raw(memory:D3D11Memory),format=NV12,width={},height={},pixel-aspect-ratio=1/1",
+            config.width, config.height
+        )));
+    }
+
+    video
+}
+
+/// 440 Hz tone standing in for desktop audio
+pub fn audio() -> ElementSpec {
+    ElementSpec::new("audiotestsrc")
+        .bool("is-live", true)
+        .enum_nick("wave", "sine")
+        .float("freq", 440.0)
+        .float("volume", 0.2)
+}
@@ -0,0 +1,179 @@
+//! In-process WHIP endpoint for headless tests
+//!
+//! A `whipserversrc` pipeline listening on localhost: it answers the SDP
+//! offer from `whipclientsink` like a LiveKit ingress would and counts the
+//! decoded video / audio buffers it receives. Together with the `test`
+//! source this lets `start_stream` / `get_stream_status` / `stop_stream` run
+//! end to end without a desktop or network. Built for tests and with the
+//! `whip-test-endpoint` feature.
+
+use gstreamer as gst;
+use gst::prelude::{ElementExt, GstBinExt, GstObjectExt, ObjectExt, PadExt, PadExtManual};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::pipeline::ElementSpec;
+use super::log_to_file;
+
+#[derive(Default)]
+struct Received {
+    video: AtomicU64,
+    audio: AtomicU64,
+}
+
+/// A running local WHIP endpoint; stopped on drop
+pub struct WhipEndpoint {
+    pipeline: gst::Pipeline,
+    url: String,
+    received: Arc<Received>,
+}
+
+/// Ask the OS for a port nobody is listening on
+fn free_port() -> Result<u16, String> {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .map_err(|e| format!("No free port for WHIP endpoint: {}", e))
+}
+
+/// Terminate a decoded stream in a fakesink, counting its buffers
+fn consume(pipeline: &gst::Pipeline, pad: &gst::Pad, received: &Arc<Received>) -> Result<(), String> {
+    let sink = ElementSpec::new("fakesink")
+        .bool("sync", false)
+        .bool("async", false)
+        .make()?;
+    pipeline
+        .add(&sink)
+        .map_err(|e| format!("Failed to add fakesink: {}", e))?;
+    sink.sync_state_with_parent()
+        .map_err(|e| format!("Failed to start fakesink: {}", e))?;
+    let sink_pad = sink.static_pad("sink").ok_or("fakesink has no sink pad")?;
+    pad.link(&sink_pad)
+        .map_err(|e| format!("Failed to link {}: {:?}", pad.name(), e))?;
+
+    let video = pad.name().starts_with("video");
+    let received = received.clone();
+    pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+        let counter = if video { &received.video } else { &received.audio };
+        counter.fetch_add(1, Ordering::Relaxed);
+        gst::PadProbeReturn::Ok
+    });
+    Ok(())
+}
+
+impl WhipEndpoint {
+    /// Start listening on a free localhost port
+    pub fn start() -> Result<Self, String> {
+        let port = free_port()?;
+        let src = ElementSpec::new("whipserversrc")
+            .named("whip_endpoint")
+            .str("signaller::host-addr", &format!("http://127.0.0.1:{}", port))
+            .make()?;
+        // Host candidates are enough on localhost; don't wait on a STUN server
+        src.set_property("stun-server", None::<String>);
+
+        let pipeline = gst::Pipeline::new();
+        pipeline
+            .add(&src)
+            .map_err(|e| format!("Failed to add whipserversrc: {}", e))?;
+
+        let received = Arc::new(Received::default());
+        let weak_pipeline = pipeline.downgrade();
+        let pad_received = received.clone();
+        src.connect_pad_added(move |_, pad| {
+            if let Some(pipeline) = weak_pipeline.upgrade() {
+                if let Err(e) = consume(&pipeline, pad, &pad_received) {
+                    log_to_file(&format!("WHIP endpoint: {}", e));
+                }
+            }
+        });
+
+        pipeline
+            .set_state(gst::State::Playing)
+            .map_err(|e| format!("Failed to start WHIP endpoint: {:?}", e))?;
+
+        let url = format!("http://127.0.0.1:{}/whip/endpoint", port);
+        log_to_file(&format!("Local WHIP endpoint listening on {}", url));
+        Ok(Self { pipeline, url, received })
+    }
+
+    /// URL to use as `StreamConfig.whip_url`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn video_buffers(&self) -> u64 {
+        self.received.video.load(Ordering::Relaxed)
+    }
+
+    pub fn audio_buffers(&self) -> u64 {
+        self.received.audio.load(Ordering::Relaxed)
+    }
+
+    /// Wait until video (and audio, if `audio`) has arrived
+    pub fn wait_for_media(&self, audio: bool, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if self.video_buffers() > 0 && (!audio || self.audio_buffers() > 0) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    }
+}
+
+impl Drop for WhipEndpoint {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::lifecycle::StreamState;
+    use crate::streaming::pipeline::tests::config;
+    use crate::streaming::{get_stream_status, start_stream, stop_stream, StreamingState};
+    use serde_json::json;
+    use tauri::Manager;
+
+    #[test]
+    #[ignore = "needs GStreamer with the webrtc (rs) plugins"]
+    fn test_source_goes_live_over_local_whip() {
+        gst::init().unwrap();
+        let endpoint = WhipEndpoint::start().unwrap();
+        let app = tauri::test::mock_app();
+        app.manage(StreamingState::default());
+        let app = app.handle();
+
+        let config = config(json!({
+            "source_id": "test",
+            "whip_url": endpoint.url(),
+            "width": 640,
+            "height": 360,
+            "fps": 30,
+            "bitrate": 1000,
+        }));
+        tauri::async_runtime::block_on(async {
+            let id = start_stream(app.clone(), config, Some("e2e".to_string())).await.unwrap();
+            assert_eq!(id, "e2e");
+
+            let deadline = Instant::now() + Duration::from_secs(20);
+            let mut status = get_stream_status(app.clone(), Some(id.clone())).await.unwrap();
+            while status.state != StreamState::Live && Instant::now() < deadline {
+                assert_ne!(status.state, StreamState::Failed, "{:?}", status.error);
+                std::thread::sleep(Duration::from_millis(100));
+                status = get_stream_status(app.clone(), Some(id.clone())).await.unwrap();
+            }
+            assert_eq!(status.state, StreamState::Live);
+            assert!(endpoint.wait_for_media(true, Duration::from_secs(10)));
+
+            stop_stream(app.clone(), Some(id.clone())).await.unwrap();
+            let status = get_stream_status(app.clone(), None).await.unwrap();
+            assert_eq!(status.state, StreamState::Idle);
+        });
+    }
+}
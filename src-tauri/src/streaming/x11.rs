@@ -0,0 +1,175 @@
+//! Capture source enumeration on X11
+//!
+//! Monitors come from XRandR, top-level windows from the window manager's
+//! `_NET_CLIENT_LIST`. Thumbnails are grabbed with GetImage and encoded like
+//! the Windows ones.
+
+use x11rb::connection::Connection;
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt as _, ImageFormat, MapState, Window};
+use x11rb::rust_connection::RustConnection;
+
+use super::{log_to_file, CaptureSource, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+
+/// `source_id` prefix for window capture ("xid:<window id>")
+pub const XID_PREFIX: &str = "xid:";
+
+/// A monitor rectangle in root window coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct MonitorRect {
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+}
+
+type X11Result<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn intern(conn: &RustConnection, name: &str) -> X11Result<Atom> {
+    Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+}
+
+/// XRandR monitors, primary first
+fn monitors(conn: &RustConnection, root: Window) -> X11Result<Vec<(String, MonitorRect)>> {
+    let mut monitors = conn.randr_get_monitors(root, true)?.reply()?.monitors;
+    monitors.sort_by_key(|m| !m.primary);
+    monitors
+        .into_iter()
+        .map(|m| {
+            let name = conn.get_atom_name(m.name)?.reply()?.name;
+            let rect = MonitorRect {
+                x: m.x,
+                y: m.y,
+                width: m.width,
+                height: m.height,
+            };
+            Ok((String::from_utf8_lossy(&name).to_string(), rect))
+        })
+        .collect()
+}
+
+/// Geometry of monitor `index` as listed by `list_sources`
+pub fn monitor_rect(index: usize) -> Option<MonitorRect> {
+    let (conn, screen_num) = x11rb::connect(None).ok()?;
+    let root = conn.setup().roots[screen_num].root;
+    monitors(&conn, root).ok()?.into_iter().nth(index).map(|(_, rect)| rect)
+}
+
+/// Window title from `_NET_WM_NAME`, falling back to `WM_NAME`
+fn window_title(conn: &RustConnection, window: Window, net_wm_name: Atom, utf8: Atom) -> Option<String> {
+    let title = conn
+        .get_property(false, window, net_wm_name, utf8, 0, 1024)
+        .ok()?
+        .reply()
+        .ok()
+        .filter(|r| !r.value.is_empty())
+        .or_else(|| {
+            conn.get_property(false, window, AtomEnum::WM_NAME, AtomEnum::STRING, 0, 1024)
+                .ok()?
+                .reply()
+                .ok()
+        })?;
+    Some(String::from_utf8_lossy(&title.value).trim().to_string()).filter(|t| !t.is_empty())
+}
+
+/// Grab `width` x `height` pixels of `drawable` as a JPEG data URL
+fn thumbnail(conn: &RustConnection, drawable: Window, x: i16, y: i16, width: u16, height: u16) -> Option<String> {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{imageops::FilterType, ImageBuffer, ImageEncoder, Rgb};
+    use std::io::Cursor;
+
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let reply = conn
+        .get_image(ImageFormat::Z_PIXMAP, drawable, x, y, width, height, !0)
+        .ok()?
+        .reply()
+        .ok()?;
+    // 24/32-bit visuals are stored as BGRX
+    if reply.depth < 24 || reply.data.len() < width as usize * height as usize * 4 {
+        return None;
+    }
+
+    let img: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(width as u32, height as u32, |px, py| {
+        let i = (py as usize * width as usize + px as usize) * 4;
+        Rgb([reply.data[i + 2], reply.data[i + 1], reply.data[i]])
+    });
+    let thumb = image::imageops::resize(&img, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, FilterType::Triangle);
+
+    let mut jpeg_data = Cursor::new(Vec::new());
+    let encoder = JpegEncoder::new_with_quality(&mut jpeg_data, 70);
+    encoder
+        .write_image(thumb.as_raw(), THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, image::ExtendedColorType::Rgb8)
+        .ok()?;
+
+    use base64::Engine;
+    let b64 = base64::engine::general_purpose::STANDARD.encode(jpeg_data.into_inner());
+    Some(format!("data:image/jpeg;base64,{}", b64))
+}
+
+/// Monitors and visible top-level windows on the X server
+pub fn list_sources() -> X11Result<Vec<CaptureSource>> {
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen_num].root;
+    let mut sources = Vec::new();
+
+    for (index, (name, rect)) in monitors(&conn, root)?.into_iter().enumerate() {
+        sources.push(CaptureSource {
+            id: format!("monitor:{}", index),
+            name: if name.is_empty() { format!("Display {}", index + 1) } else { name },
+            source_type: "screen".to_string(),
+            width: Some(rect.width as u32),
+            height: Some(rect.height as u32),
+            hwnd: None,
+            hmonitor: None,
+            xid: None,
+            thumbnail: thumbnail(&conn, root, rect.x, rect.y, rect.width, rect.height),
+        });
+    }
+
+    let client_list = intern(&conn, "_NET_CLIENT_LIST")?;
+    let net_wm_name = intern(&conn, "_NET_WM_NAME")?;
+    let utf8 = intern(&conn, "UTF8_STRING")?;
+    let windows: Vec<Window> = conn
+        .get_property(false, root, client_list, AtomEnum::WINDOW, 0, u32::MAX)?
+        .reply()?
+        .value32()
+        .map(|ids| ids.collect())
+        .unwrap_or_default();
+
+    for window in windows {
+        // Skip minimized / withdrawn windows
+        let viewable = conn
+            .get_window_attributes(window)?
+            .reply()
+            .map(|a| a.map_state == MapState::VIEWABLE)
+            .unwrap_or(false);
+        if !viewable {
+            continue;
+        }
+        let title = match window_title(&conn, window, net_wm_name, utf8) {
+            Some(t) => t,
+            None => continue,
+        };
+        let geometry = match conn.get_geometry(window)?.reply() {
+            Ok(g) if g.width >= 50 && g.height >= 50 => g,
+            _ => continue,
+        };
+
+        sources.push(CaptureSource {
+            id: format!("{}{}", XID_PREFIX, window),
+            name: title,
+            source_type: "window".to_string(),
+            width: Some(geometry.width as u32),
+            height: Some(geometry.height as u32),
+            hwnd: None,
+            hmonitor: None,
+            xid: Some(window as u64),
+            thumbnail: thumbnail(&conn, window, 0, 0, geometry.width, geometry.height),
+        });
+    }
+
+    log_to_file(&format!("X11: found {} capture sources", sources.len()));
+    Ok(sources)
+}